@@ -0,0 +1,270 @@
+use crate::msg::InstantiateMsg;
+use crate::state::{Config, CONFIG};
+use cosmwasm_std::{DepsMut, MessageInfo, Response, StdResult};
+
+pub const INSTANTIATE_REPLY_ID: u64 = 1;
+
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+
+pub fn instantiate_contract(
+    deps: DepsMut,
+    info: MessageInfo,
+    msg: InstantiateMsg,
+) -> StdResult<Response> {
+    let config = Config {
+        owner: info.sender,
+        vault_code_id: msg.vault_code_id,
+    };
+    CONFIG.save(deps.storage, &config)?;
+    Ok(Response::new().add_attribute("action", "instantiate"))
+}
+
+fn page_limit(limit: Option<u32>) -> usize {
+    limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize
+}
+
+pub mod execute {
+    use super::*;
+    use crate::error::ContractError;
+    use crate::msg::{VaultInstantiateMsg, VaultMigrateMsg};
+    use crate::state::{vaults, VaultRecord, PENDING_VAULT};
+    use cosmwasm_std::{to_json_binary, Env, Order, SubMsg, WasmMsg};
+    use cw_storage_plus::Bound;
+
+    fn check_owner(deps: &DepsMut, info: &MessageInfo) -> Result<Config, ContractError> {
+        let config = CONFIG.load(deps.storage)?;
+        if info.sender != config.owner {
+            return Err(ContractError::NotOwner {
+                owner: config.owner.to_string(),
+            });
+        }
+        Ok(config)
+    }
+
+    pub fn create_vault(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        label: String,
+        name: String,
+        expected_denom: String,
+    ) -> Result<Response, ContractError> {
+        let taken = vaults()
+            .idx
+            .label
+            .item(deps.storage, label.clone())?
+            .is_some();
+        if taken {
+            return Err(ContractError::LabelTaken { label });
+        }
+        let config = CONFIG.load(deps.storage)?;
+        PENDING_VAULT.save(
+            deps.storage,
+            &VaultRecord {
+                owner: info.sender.clone(),
+                label: label.clone(),
+                code_id: config.vault_code_id,
+            },
+        )?;
+
+        // The factory stays admin so it can migrate the vault later on.
+        let instantiate = WasmMsg::Instantiate {
+            admin: Some(env.contract.address.to_string()),
+            code_id: config.vault_code_id,
+            msg: to_json_binary(&VaultInstantiateMsg {
+                name,
+                expected_denom,
+            })?,
+            funds: vec![],
+            label: label.clone(),
+        };
+        Ok(Response::new()
+            .add_submessage(SubMsg::reply_on_success(instantiate, INSTANTIATE_REPLY_ID))
+            .add_attribute("action", "create_vault")
+            .add_attribute("owner", info.sender)
+            .add_attribute("label", label))
+    }
+
+    pub fn update_code_id(
+        deps: DepsMut,
+        info: MessageInfo,
+        code_id: u64,
+    ) -> Result<Response, ContractError> {
+        let mut config = check_owner(&deps, &info)?;
+        config.vault_code_id = code_id;
+        CONFIG.save(deps.storage, &config)?;
+        Ok(Response::new()
+            .add_attribute("action", "update_code_id")
+            .add_attribute("code_id", code_id.to_string()))
+    }
+
+    /// Migrates one page of vaults; call again with the last address to continue.
+    pub fn migrate_vaults(
+        deps: DepsMut,
+        info: MessageInfo,
+        code_id: u64,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    ) -> Result<Response, ContractError> {
+        check_owner(&deps, &info)?;
+        let start = start_after
+            .map(|address| deps.api.addr_validate(&address))
+            .transpose()?;
+        let page = vaults()
+            .range(
+                deps.storage,
+                start.map(Bound::exclusive),
+                None,
+                Order::Ascending,
+            )
+            .take(page_limit(limit))
+            .collect::<StdResult<Vec<_>>>()?;
+
+        let mut messages = vec![];
+        for (address, mut record) in page.clone() {
+            if record.code_id == code_id {
+                continue;
+            }
+            messages.push(WasmMsg::Migrate {
+                contract_addr: address.to_string(),
+                new_code_id: code_id,
+                msg: to_json_binary(&VaultMigrateMsg {})?,
+            });
+            record.code_id = code_id;
+            vaults().save(deps.storage, address, &record)?;
+        }
+
+        let last = page
+            .last()
+            .map(|(address, _)| address.to_string())
+            .unwrap_or_default();
+        Ok(Response::new()
+            .add_messages(messages.clone())
+            .add_attribute("action", "migrate_vaults")
+            .add_attribute("code_id", code_id.to_string())
+            .add_attribute("migrated", messages.len().to_string())
+            .add_attribute("last", last))
+    }
+}
+
+pub mod query {
+    use super::*;
+    use crate::msg::{ConfigResponse, VaultResponse, VaultsResponse};
+    use crate::state::{vaults, VaultRecord};
+    use cosmwasm_std::{to_json_binary, Addr, Binary, Deps, Order, StdError};
+    use cw_storage_plus::Bound;
+
+    fn vault_response(address: Addr, record: VaultRecord) -> VaultResponse {
+        VaultResponse {
+            address: address.to_string(),
+            owner: record.owner.to_string(),
+            label: record.label,
+            code_id: record.code_id,
+        }
+    }
+
+    pub fn get_config(deps: Deps) -> StdResult<Binary> {
+        let config = CONFIG.load(deps.storage)?;
+        to_json_binary(&ConfigResponse {
+            owner: config.owner.to_string(),
+            vault_code_id: config.vault_code_id,
+        })
+    }
+
+    pub fn get_vault(deps: Deps, address: String) -> StdResult<Binary> {
+        let address = deps.api.addr_validate(&address)?;
+        let record = vaults().load(deps.storage, address.clone())?;
+        to_json_binary(&vault_response(address, record))
+    }
+
+    pub fn get_vault_by_label(deps: Deps, label: String) -> StdResult<Binary> {
+        let (key, record) = vaults()
+            .idx
+            .label
+            .item(deps.storage, label.clone())?
+            .ok_or_else(|| StdError::not_found(format!("vault {label}")))?;
+        let address = Addr::unchecked(String::from_utf8(key).map_err(StdError::from)?);
+        to_json_binary(&vault_response(address, record))
+    }
+
+    pub fn get_vaults(
+        deps: Deps,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    ) -> StdResult<Binary> {
+        let start = start_after
+            .map(|address| deps.api.addr_validate(&address))
+            .transpose()?;
+        let vaults = vaults()
+            .range(
+                deps.storage,
+                start.map(Bound::exclusive),
+                None,
+                Order::Ascending,
+            )
+            .take(page_limit(limit))
+            .map(|item| item.map(|(address, record)| vault_response(address, record)))
+            .collect::<StdResult<Vec<_>>>()?;
+        to_json_binary(&VaultsResponse { vaults })
+    }
+
+    pub fn get_vaults_by_owner(
+        deps: Deps,
+        owner: String,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    ) -> StdResult<Binary> {
+        let owner = deps.api.addr_validate(&owner)?;
+        let start = start_after
+            .map(|address| deps.api.addr_validate(&address))
+            .transpose()?;
+        let vaults = vaults()
+            .idx
+            .owner
+            .prefix(owner)
+            .range(
+                deps.storage,
+                start.map(Bound::exclusive),
+                None,
+                Order::Ascending,
+            )
+            .take(page_limit(limit))
+            .map(|item| item.map(|(address, record)| vault_response(address, record)))
+            .collect::<StdResult<Vec<_>>>()?;
+        to_json_binary(&VaultsResponse { vaults })
+    }
+}
+
+pub mod reply {
+    use super::*;
+    use crate::error::ContractError;
+    use crate::msg::VaultExecuteMsg;
+    use crate::state::{vaults, PENDING_VAULT};
+    use cosmwasm_std::{to_json_binary, Reply, WasmMsg};
+    use cw_utils::parse_reply_instantiate_data;
+
+    /// Records the new vault and hands ownership from the factory to the requester.
+    pub fn instantiate_reply(deps: DepsMut, msg: Reply) -> Result<Response, ContractError> {
+        let data = parse_reply_instantiate_data(msg)?;
+        let address = deps.api.addr_validate(&data.contract_address)?;
+        let record = PENDING_VAULT.load(deps.storage)?;
+        PENDING_VAULT.remove(deps.storage);
+        vaults().save(deps.storage, address.clone(), &record)?;
+
+        let update_owner = WasmMsg::Execute {
+            contract_addr: address.to_string(),
+            msg: to_json_binary(&VaultExecuteMsg::UpdateOwner {
+                owner: record.owner.to_string(),
+                vault_id: None,
+            })?,
+            funds: vec![],
+        };
+        Ok(Response::new()
+            .add_message(update_owner)
+            .add_attribute("action", "vault_created")
+            .add_attribute("vault", address)
+            .add_attribute("owner", record.owner)
+            .add_attribute("label", record.label))
+    }
+}
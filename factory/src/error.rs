@@ -0,0 +1,21 @@
+use cosmwasm_std::StdError;
+use cw_utils::ParseReplyError;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ContractError {
+    #[error("{0}")]
+    Std(#[from] StdError),
+
+    #[error("{0}")]
+    ParseReply(#[from] ParseReplyError),
+
+    #[error("Unauthorized - only {owner} can call this function")]
+    NotOwner { owner: String },
+
+    #[error("A vault with label {label} already exists")]
+    LabelTaken { label: String },
+
+    #[error("Unknown reply id {id}")]
+    UnknownReplyId { id: u64 },
+}
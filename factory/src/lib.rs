@@ -0,0 +1,71 @@
+use cosmwasm_std::{
+    entry_point, Binary, Deps, DepsMut, Env, MessageInfo, Reply, Response, StdResult,
+};
+use error::ContractError;
+use msg::{ExecuteMsg, InstantiateMsg, QueryMsg};
+
+mod contract;
+mod error;
+pub mod msg;
+mod state;
+#[cfg(test)]
+mod test;
+
+#[entry_point]
+pub fn instantiate(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    msg: InstantiateMsg,
+) -> StdResult<Response> {
+    contract::instantiate_contract(deps, info, msg)
+}
+
+#[entry_point]
+pub fn execute(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: ExecuteMsg,
+) -> Result<Response, ContractError> {
+    match msg {
+        ExecuteMsg::CreateVault {
+            label,
+            name,
+            expected_denom,
+        } => contract::execute::create_vault(deps, env, info, label, name, expected_denom),
+        ExecuteMsg::UpdateCodeId { code_id } => {
+            contract::execute::update_code_id(deps, info, code_id)
+        }
+        ExecuteMsg::MigrateVaults {
+            code_id,
+            start_after,
+            limit,
+        } => contract::execute::migrate_vaults(deps, info, code_id, start_after, limit),
+    }
+}
+
+#[entry_point]
+pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::GetConfig {} => contract::query::get_config(deps),
+        QueryMsg::GetVault { address } => contract::query::get_vault(deps, address),
+        QueryMsg::GetVaultByLabel { label } => contract::query::get_vault_by_label(deps, label),
+        QueryMsg::GetVaults { start_after, limit } => {
+            contract::query::get_vaults(deps, start_after, limit)
+        }
+        QueryMsg::GetVaultsByOwner {
+            owner,
+            start_after,
+            limit,
+        } => contract::query::get_vaults_by_owner(deps, owner, start_after, limit),
+    }
+}
+
+#[entry_point]
+pub fn reply(deps: DepsMut, _env: Env, msg: Reply) -> Result<Response, ContractError> {
+    match msg.id {
+        contract::INSTANTIATE_REPLY_ID => contract::reply::instantiate_reply(deps, msg),
+        id => Err(ContractError::UnknownReplyId { id }),
+    }
+}
@@ -0,0 +1,84 @@
+use cosmwasm_schema::{cw_serde, QueryResponses};
+
+#[cw_serde]
+pub struct InstantiateMsg {
+    pub vault_code_id: u64,
+}
+
+#[cw_serde]
+pub enum ExecuteMsg {
+    CreateVault {
+        label: String,
+        name: String,
+        expected_denom: String,
+    },
+    UpdateCodeId {
+        code_id: u64,
+    },
+    MigrateVaults {
+        code_id: u64,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+}
+
+#[cw_serde]
+#[derive(QueryResponses)]
+pub enum QueryMsg {
+    #[returns(ConfigResponse)]
+    GetConfig {},
+    #[returns(VaultResponse)]
+    GetVault { address: String },
+    #[returns(VaultResponse)]
+    GetVaultByLabel { label: String },
+    #[returns(VaultsResponse)]
+    GetVaults {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    #[returns(VaultsResponse)]
+    GetVaultsByOwner {
+        owner: String,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+}
+
+#[cw_serde]
+pub struct ConfigResponse {
+    pub owner: String,
+    pub vault_code_id: u64,
+}
+
+#[cw_serde]
+pub struct VaultResponse {
+    pub address: String,
+    pub owner: String,
+    pub label: String,
+    pub code_id: u64,
+}
+
+#[cw_serde]
+pub struct VaultsResponse {
+    pub vaults: Vec<VaultResponse>,
+}
+
+// The vault messages the factory sends. They mirror `the_vault::msg` so the
+// factory does not build the vault and its chain features into its own wasm.
+
+#[cw_serde]
+pub struct VaultInstantiateMsg {
+    pub name: String,
+    pub expected_denom: String,
+}
+
+#[cw_serde]
+pub struct VaultMigrateMsg {}
+
+#[cw_serde]
+pub enum VaultExecuteMsg {
+    UpdateOwner {
+        owner: String,
+        vault_id: Option<u64>,
+    },
+}
@@ -0,0 +1,42 @@
+use cosmwasm_std::Addr;
+use cw_storage_plus::{Index, IndexList, IndexedMap, Item, MultiIndex, UniqueIndex};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Config {
+    pub owner: Addr,
+    pub vault_code_id: u64,
+}
+
+pub const CONFIG: Item<Config> = Item::new("config");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct VaultRecord {
+    pub owner: Addr,
+    pub label: String,
+    pub code_id: u64,
+}
+
+/// Vault requested by `CreateVault`, waiting for its address in the reply.
+pub const PENDING_VAULT: Item<VaultRecord> = Item::new("pending_vault");
+
+pub struct VaultIndexes<'a> {
+    pub owner: MultiIndex<'a, Addr, VaultRecord, Addr>,
+    pub label: UniqueIndex<'a, String, VaultRecord, Addr>,
+}
+
+impl<'a> IndexList<VaultRecord> for VaultIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<VaultRecord>> + '_> {
+        let v: Vec<&dyn Index<VaultRecord>> = vec![&self.owner, &self.label];
+        Box::new(v.into_iter())
+    }
+}
+
+/// Vaults created by the factory, keyed by contract address.
+pub fn vaults<'a>() -> IndexedMap<'a, Addr, VaultRecord, VaultIndexes<'a>> {
+    let indexes = VaultIndexes {
+        owner: MultiIndex::new(|_pk, v| v.owner.clone(), "vaults", "vaults__owner"),
+        label: UniqueIndex::new(|v| v.label.clone(), "vaults__label"),
+    };
+    IndexedMap::new("vaults", indexes)
+}
@@ -0,0 +1,226 @@
+use crate::error::ContractError;
+use crate::msg::{
+    ConfigResponse, ExecuteMsg, InstantiateMsg, QueryMsg, VaultResponse, VaultsResponse,
+};
+use crate::{execute, instantiate, query, reply};
+use cosmwasm_std::{Addr, Empty};
+use cw_multi_test::{App, Contract, ContractWrapper, Executor};
+use the_vault::msg::{QueryMsg as VaultQueryMsg, StateResponse};
+
+fn factory_contract() -> Box<dyn Contract<Empty>> {
+    let contract = ContractWrapper::new(execute, instantiate, query).with_reply(reply);
+    Box::new(contract)
+}
+
+fn vault_contract() -> Box<dyn Contract<Empty>> {
+    let contract =
+        ContractWrapper::new(the_vault::execute, the_vault::instantiate, the_vault::query)
+            .with_reply(the_vault::reply)
+            .with_sudo(the_vault::sudo)
+            .with_migrate(the_vault::migrate);
+    Box::new(contract)
+}
+
+fn create_vault(app: &mut App, factory: &Addr, owner: &Addr, label: &str) -> Addr {
+    app.execute_contract(
+        owner.clone(),
+        factory.clone(),
+        &ExecuteMsg::CreateVault {
+            label: label.to_string(),
+            name: format!("Vault {label}"),
+            expected_denom: "atom".to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+    let vault: VaultResponse = app
+        .wrap()
+        .query_wasm_smart(
+            factory,
+            &QueryMsg::GetVaultByLabel {
+                label: label.to_string(),
+            },
+        )
+        .unwrap();
+    Addr::unchecked(vault.address)
+}
+
+#[test]
+fn multitest_factory() {
+    let admin = Addr::unchecked("admin");
+    let alice = Addr::unchecked("alice");
+    let bob = Addr::unchecked("bob");
+
+    let mut app = App::default();
+    let vault_id = app.store_code(vault_contract());
+    let factory_id = app.store_code(factory_contract());
+    let factory = app
+        .instantiate_contract(
+            factory_id,
+            admin.clone(),
+            &InstantiateMsg {
+                vault_code_id: vault_id,
+            },
+            &[],
+            "Vault factory",
+            None,
+        )
+        .unwrap();
+
+    let alice_one = create_vault(&mut app, &factory, &alice, "alice-one");
+    let alice_two = create_vault(&mut app, &factory, &alice, "alice-two");
+    let bob_one = create_vault(&mut app, &factory, &bob, "bob-one");
+
+    // The requester owns the new vault and the factory is its admin
+    let state: StateResponse = app
+        .wrap()
+        .query_wasm_smart(&alice_one, &VaultQueryMsg::GetState { vault_id: None })
+        .unwrap();
+    assert_eq!(state.owner, alice.to_string());
+    assert_eq!(state.name, "Vault alice-one");
+    let info = app.wrap().query_wasm_contract_info(&bob_one).unwrap();
+    assert_eq!(info.admin, Some(factory.to_string()));
+    assert_eq!(info.code_id, vault_id);
+
+    // Labels are unique across the factory
+    let err = app
+        .execute_contract(
+            bob.clone(),
+            factory.clone(),
+            &ExecuteMsg::CreateVault {
+                label: "alice-one".to_string(),
+                name: "Copy".to_string(),
+                expected_denom: "atom".to_string(),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::LabelTaken {
+            label: "alice-one".to_string()
+        }
+    );
+
+    let res: VaultsResponse = app
+        .wrap()
+        .query_wasm_smart(
+            &factory,
+            &QueryMsg::GetVaultsByOwner {
+                owner: alice.to_string(),
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    let mut owned: Vec<String> = res.vaults.iter().map(|v| v.address.clone()).collect();
+    owned.sort();
+    let mut expected = vec![alice_one.to_string(), alice_two.to_string()];
+    expected.sort();
+    assert_eq!(owned, expected);
+
+    let first: VaultsResponse = app
+        .wrap()
+        .query_wasm_smart(
+            &factory,
+            &QueryMsg::GetVaults {
+                start_after: None,
+                limit: Some(2),
+            },
+        )
+        .unwrap();
+    assert_eq!(first.vaults.len(), 2);
+    let rest: VaultsResponse = app
+        .wrap()
+        .query_wasm_smart(
+            &factory,
+            &QueryMsg::GetVaults {
+                start_after: Some(first.vaults[1].address.clone()),
+                limit: Some(2),
+            },
+        )
+        .unwrap();
+    assert_eq!(rest.vaults.len(), 1);
+
+    // Only the factory owner manages code upgrades
+    let new_vault_id = app.store_code(vault_contract());
+    let err = app
+        .execute_contract(
+            alice.clone(),
+            factory.clone(),
+            &ExecuteMsg::UpdateCodeId {
+                code_id: new_vault_id,
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::NotOwner {
+            owner: admin.to_string()
+        }
+    );
+    app.execute_contract(
+        admin.clone(),
+        factory.clone(),
+        &ExecuteMsg::UpdateCodeId {
+            code_id: new_vault_id,
+        },
+        &[],
+    )
+    .unwrap();
+    let config: ConfigResponse = app
+        .wrap()
+        .query_wasm_smart(&factory, &QueryMsg::GetConfig {})
+        .unwrap();
+    assert_eq!(config.vault_code_id, new_vault_id);
+
+    // Children are migrated in batches
+    app.execute_contract(
+        admin.clone(),
+        factory.clone(),
+        &ExecuteMsg::MigrateVaults {
+            code_id: new_vault_id,
+            start_after: None,
+            limit: Some(2),
+        },
+        &[],
+    )
+    .unwrap();
+    let migrated = [&alice_one, &alice_two, &bob_one]
+        .iter()
+        .filter(|addr| app.wrap().query_wasm_contract_info(**addr).unwrap().code_id == new_vault_id)
+        .count();
+    assert_eq!(migrated, 2);
+
+    app.execute_contract(
+        admin.clone(),
+        factory.clone(),
+        &ExecuteMsg::MigrateVaults {
+            code_id: new_vault_id,
+            start_after: Some(first.vaults[1].address.clone()),
+            limit: Some(2),
+        },
+        &[],
+    )
+    .unwrap();
+    for addr in [&alice_one, &alice_two, &bob_one] {
+        let info = app.wrap().query_wasm_contract_info(addr).unwrap();
+        assert_eq!(info.code_id, new_vault_id);
+        let vault: VaultResponse = app
+            .wrap()
+            .query_wasm_smart(
+                &factory,
+                &QueryMsg::GetVault {
+                    address: addr.to_string(),
+                },
+            )
+            .unwrap();
+        assert_eq!(vault.code_id, new_vault_id);
+    }
+
+    // New vaults use the updated code
+    let bob_two = create_vault(&mut app, &factory, &bob, "bob-two");
+    let info = app.wrap().query_wasm_contract_info(&bob_two).unwrap();
+    assert_eq!(info.code_id, new_vault_id);
+}
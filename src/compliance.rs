@@ -0,0 +1,14 @@
+//! Messages of the compliance registry used to screen deposits and payouts.
+
+use cosmwasm_schema::cw_serde;
+
+#[cw_serde]
+pub enum ComplianceQueryMsg {
+    /// Whether `address` must not send to or receive from the vault.
+    IsFlagged { address: String },
+}
+
+#[cw_serde]
+pub struct FlaggedResponse {
+    pub flagged: bool,
+}
@@ -1,6 +1,35 @@
-use crate::msg::InstantiateMsg;
-use crate::state::{State, DEPOSIT_ADDRESSES, STATE};
-use cosmwasm_std::{DepsMut, MessageInfo, Response, StdResult, Uint128};
+use crate::compliance::{ComplianceQueryMsg, FlaggedResponse};
+use crate::error::ContractError;
+use crate::msg::{FeeReceipt, HookExecuteMsg, InstantiateMsg, VaultHookMsg};
+use crate::state::{
+    Budget, Campaign, Closure, FeeConfig, RateLimits, State, WithholdingRule, ACCRUED_FEES,
+    ALLOWANCES, ALLOWANCE_BUDGETS, BUDGETS, COMPLIANCE_REGISTRY, DEAD_MAN_SWITCH, DELEGATIONS,
+    DENY_LIST, DEPOSITED, DEPOSIT_ADDRESSES, ESCROW, FEE_CONFIG, GLOBAL_WITHHOLDING,
+    HELD_WITHHOLDING, HOOKS, LAST_PRICE, ORACLE_CONFIG, PEGGED_ALLOWANCES, PEGGED_RESERVES,
+    PLEDGED_TOTAL, PLEDGE_EXPIRIES, POSITIONS, RECOVERY_VOTES, RESERVED_TOTAL, SHARE_CONFIG,
+    SPENDER_OUTFLOW, STATE, UNBONDINGS, VAULT_OUTFLOW, VOUCHER_PUBKEY, WITHHOLDING_RULES,
+    WITHHOLDING_TOTALS,
+};
+use cosmwasm_std::{
+    coins, to_json_binary, Addr, BankMsg, Deps, DepsMut, MessageInfo, Response, StdResult, Storage,
+    SubMsg, Timestamp, Uint128, WasmMsg,
+};
+use cw_storage_plus::{Bound, PrefixBound};
+
+pub const STAKING_REPLY_ID: u64 = 1;
+pub const IBC_TRANSFER_REPLY_ID: u64 = 2;
+pub const SWAP_REPLY_ID: u64 = 3;
+pub const HOOK_REPLY_ID: u64 = 4;
+
+const DAY_SECONDS: u64 = 86_400;
+/// How long contributors of a failed campaign can reclaim before it ends.
+pub const CAMPAIGN_RECLAIM_SECONDS: u64 = 30 * DAY_SECONDS;
+/// Running pledges every deposit is matched against.
+pub const MAX_ACTIVE_PLEDGES: usize = 20;
+/// How long the price of the last pegged claim bounds the next oracle price.
+pub const PRICE_REFERENCE_SECONDS: u64 = DAY_SECONDS;
+/// Latest clawbacks listed with an allowance.
+pub const CLAWBACKS_SHOWN: usize = 30;
 
 pub fn instantiate_contract(
     deps: DepsMut,
@@ -18,58 +47,928 @@ pub fn instantiate_contract(
     Ok(Response::new().add_attribute("action", "instantiate"))
 }
 
+/// Tokens held back for a pegged allowance: the quote amount at the last paid
+/// price, or the per-claim cap until a price is known.
+pub fn pegged_reserve(storage: &dyn Storage, quote_amount: Uint128) -> StdResult<Uint128> {
+    let config = match ORACLE_CONFIG.may_load(storage)? {
+        Some(value) => value,
+        None => return Ok(Uint128::zero()),
+    };
+    let amount = match LAST_PRICE.may_load(storage)? {
+        Some(reference) => quote_amount
+            .checked_div_floor(reference.price)
+            .unwrap_or(config.max_tokens_per_claim),
+        None => config.max_tokens_per_claim,
+    };
+    Ok(amount.min(config.max_tokens_per_claim))
+}
+
+/// Tokens promised to a spender, in tokens or pegged to the quote currency.
+pub fn allowance_reserve(storage: &dyn Storage, spender: &Addr) -> StdResult<Uint128> {
+    let amount = ALLOWANCES
+        .may_load(storage, spender.clone())?
+        .unwrap_or_default();
+    let pegged = PEGGED_RESERVES
+        .may_load(storage, spender.clone())?
+        .unwrap_or_default();
+    Ok(amount + pegged)
+}
+
+/// Part of `total_amount` that is not promised to any spender.
+pub fn free_amount(storage: &dyn Storage, state: &State) -> StdResult<Uint128> {
+    let reserved = RESERVED_TOTAL.may_load(storage)?.unwrap_or_default();
+    Ok(state.total_amount.saturating_sub(reserved))
+}
+
+/// Moves `RESERVED_TOTAL` from one reserve of a spender to the next.
+fn adjust_reserved(storage: &mut dyn Storage, previous: Uint128, amount: Uint128) -> StdResult<()> {
+    let reserved = RESERVED_TOTAL.may_load(storage)?.unwrap_or_default();
+    RESERVED_TOTAL.save(storage, &(reserved + amount).saturating_sub(previous))
+}
+
+/// Sets the token allowance of a spender and keeps `RESERVED_TOTAL` in step.
+pub fn save_allowance(storage: &mut dyn Storage, spender: &Addr, amount: Uint128) -> StdResult<()> {
+    let previous = ALLOWANCES
+        .may_load(storage, spender.clone())?
+        .unwrap_or_default();
+    ALLOWANCES.save(storage, spender.clone(), &amount)?;
+    adjust_reserved(storage, previous, amount)
+}
+
+/// Removes the token allowance of a spender and keeps `RESERVED_TOTAL` in step.
+pub fn remove_allowance(storage: &mut dyn Storage, spender: &Addr) -> StdResult<()> {
+    let previous = ALLOWANCES
+        .may_load(storage, spender.clone())?
+        .unwrap_or_default();
+    ALLOWANCES.remove(storage, spender.clone());
+    adjust_reserved(storage, previous, Uint128::zero())
+}
+
+/// Values the pegged allowance of a spender at the current reference price,
+/// when it changes or is claimed.
+pub fn refresh_pegged_reserve(storage: &mut dyn Storage, spender: &Addr) -> StdResult<()> {
+    let previous = PEGGED_RESERVES
+        .may_load(storage, spender.clone())?
+        .unwrap_or_default();
+    let amount = match PEGGED_ALLOWANCES.may_load(storage, spender.clone())? {
+        Some(quote_amount) => {
+            let amount = pegged_reserve(storage, quote_amount)?;
+            PEGGED_RESERVES.save(storage, spender.clone(), &amount)?;
+            amount
+        }
+        None => {
+            PEGGED_RESERVES.remove(storage, spender.clone());
+            Uint128::zero()
+        }
+    };
+    adjust_reserved(storage, previous, amount)
+}
+
+/// Revalues every pegged allowance, after the oracle changed.
+pub fn refresh_pegged_reserves(storage: &mut dyn Storage) -> StdResult<()> {
+    let spenders = PEGGED_RESERVES
+        .keys(storage, None, None, cosmwasm_std::Order::Ascending)
+        .collect::<StdResult<Vec<Addr>>>()?;
+    for spender in spenders {
+        refresh_pegged_reserve(storage, &spender)?;
+    }
+    Ok(())
+}
+
+/// Rebuilds `RESERVED_TOTAL` and the pegged snapshots from the allowances,
+/// for vaults that stored allowances before the running total existed.
+pub fn recount_reserved(storage: &mut dyn Storage) -> StdResult<()> {
+    let reserved = ALLOWANCES
+        .range(storage, None, None, cosmwasm_std::Order::Ascending)
+        .try_fold(Uint128::zero(), |total, item| {
+            let (_, amount) = item?;
+            StdResult::Ok(total + amount)
+        })?;
+    RESERVED_TOTAL.save(storage, &reserved)?;
+    PEGGED_RESERVES.clear(storage);
+    let spenders = PEGGED_ALLOWANCES
+        .keys(storage, None, None, cosmwasm_std::Order::Ascending)
+        .collect::<StdResult<Vec<Addr>>>()?;
+    for spender in spenders {
+        refresh_pegged_reserve(storage, &spender)?;
+    }
+    Ok(())
+}
+
+/// Fills `DEPOSITED` from the deposit addresses of vaults that recorded
+/// deposits before it existed.
+pub fn backfill_deposited(storage: &mut dyn Storage) -> StdResult<()> {
+    if !DEPOSITED.is_empty(storage) {
+        return Ok(());
+    }
+    let deposits = DEPOSIT_ADDRESSES
+        .range(storage, None, None, cosmwasm_std::Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (address, amount) in deposits {
+        DEPOSITED.save(storage, address, &amount)?;
+    }
+    Ok(())
+}
+
+/// Whether share holders own part of the free funds.
+pub fn shares_outstanding(storage: &dyn Storage) -> StdResult<bool> {
+    Ok(SHARE_CONFIG
+        .may_load(storage)?
+        .is_some_and(|config| !config.total_supply.is_zero()))
+}
+
+/// Funds leaving the pool while shares are outstanding would be paid out of
+/// the share holders' funds.
+pub fn check_no_shares(storage: &dyn Storage) -> Result<(), ContractError> {
+    if shares_outstanding(storage)? {
+        return Err(ContractError::SharesOutstanding {});
+    }
+    Ok(())
+}
+
+/// Funds behind the shares: the free funds and whatever the vault has staked
+/// or is unbonding, which come back to the pool.
+pub fn share_assets(storage: &dyn Storage, state: &State) -> StdResult<Uint128> {
+    let staked = DELEGATIONS
+        .range(storage, None, None, cosmwasm_std::Order::Ascending)
+        .try_fold(Uint128::zero(), |total, item| {
+            let (_, amount) = item?;
+            StdResult::Ok(total + amount)
+        })?;
+    Ok(free_amount(storage, state)? + staked + unbonding_amount(storage)?)
+}
+
+/// Raising a promise while shares are outstanding would pay it out of the
+/// share holders' funds.
+pub fn check_shares_undiluted(
+    storage: &dyn Storage,
+    previous: Uint128,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    if amount > previous && shares_outstanding(storage)? {
+        return Err(ContractError::SharesOutstanding {});
+    }
+    Ok(())
+}
+
+/// First bucket that overlaps the window ending at `now`.
+fn first_bucket(now: Timestamp, window_seconds: u64, limits: &RateLimits) -> u64 {
+    now.seconds().saturating_sub(window_seconds) / limits.bucket_seconds
+}
+
+pub fn spender_outflow(
+    storage: &dyn Storage,
+    spender: &Addr,
+    now: Timestamp,
+    limits: &RateLimits,
+) -> StdResult<Uint128> {
+    let start = first_bucket(now, limits.window_seconds, limits);
+    SPENDER_OUTFLOW
+        .prefix(spender.clone())
+        .range(
+            storage,
+            Some(Bound::inclusive(start)),
+            None,
+            cosmwasm_std::Order::Ascending,
+        )
+        .try_fold(Uint128::zero(), |total, item| {
+            let (_, amount) = item?;
+            StdResult::Ok(total + amount)
+        })
+}
+
+pub fn daily_outflow(
+    storage: &dyn Storage,
+    now: Timestamp,
+    limits: &RateLimits,
+) -> StdResult<Uint128> {
+    let start = first_bucket(now, DAY_SECONDS, limits);
+    VAULT_OUTFLOW
+        .range(
+            storage,
+            Some(Bound::inclusive(start)),
+            None,
+            cosmwasm_std::Order::Ascending,
+        )
+        .try_fold(Uint128::zero(), |total, item| {
+            let (_, amount) = item?;
+            StdResult::Ok(total + amount)
+        })
+}
+
+/// What is left of the daily outflow cap, or `None` without a cap.
+pub fn daily_outflow_remaining(
+    storage: &dyn Storage,
+    now: Timestamp,
+) -> StdResult<Option<Uint128>> {
+    let limits = match crate::state::RATE_LIMITS.may_load(storage)? {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    Ok(match limits.max_daily_outflow {
+        Some(max_amount) => Some(max_amount.saturating_sub(daily_outflow(storage, now, &limits)?)),
+        None => None,
+    })
+}
+
+/// Hands the vault to a new owner. The owner's deposit record moves along,
+/// the voucher key of the previous owner stops working and a dead man's
+/// switch starts over from now.
+pub fn change_owner(
+    storage: &mut dyn Storage,
+    state: &mut State,
+    new_owner: Addr,
+    now: Timestamp,
+) -> StdResult<()> {
+    let deposited = DEPOSIT_ADDRESSES
+        .may_load(storage, state.owner.clone())?
+        .unwrap_or_default();
+    DEPOSIT_ADDRESSES.remove(storage, state.owner.clone());
+    let existing = DEPOSIT_ADDRESSES
+        .may_load(storage, new_owner.clone())?
+        .unwrap_or_default();
+    DEPOSIT_ADDRESSES.save(storage, new_owner.clone(), &(existing + deposited))?;
+    let deposited = DEPOSITED
+        .may_load(storage, state.owner.clone())?
+        .unwrap_or_default();
+    DEPOSITED.remove(storage, state.owner.clone());
+    let existing = DEPOSITED
+        .may_load(storage, new_owner.clone())?
+        .unwrap_or_default();
+    DEPOSITED.save(storage, new_owner.clone(), &(existing + deposited))?;
+    VOUCHER_PUBKEY.remove(storage);
+    if let Some(mut switch) = DEAD_MAN_SWITCH.may_load(storage)? {
+        switch.last_heartbeat = now;
+        DEAD_MAN_SWITCH.save(storage, &switch)?;
+        RECOVERY_VOTES.clear(storage);
+    }
+    state.owner = new_owner;
+    STATE.save(storage, state)
+}
+
+/// Checks an outflow against the rate limits and counts it. Claims pass the
+/// spender, withdrawals only count towards the daily limit.
+pub fn record_outflow(
+    storage: &mut dyn Storage,
+    spender: Option<&Addr>,
+    amount: Uint128,
+    now: Timestamp,
+) -> Result<(), ContractError> {
+    let limits = match crate::state::RATE_LIMITS.may_load(storage)? {
+        Some(value) => value,
+        None => return Ok(()),
+    };
+    let bucket = now.seconds() / limits.bucket_seconds;
+
+    if let (Some(spender), Some(max_amount)) = (spender, limits.max_claim_per_window) {
+        let used = spender_outflow(storage, spender, now, &limits)?;
+        if used + amount > max_amount {
+            return Err(ContractError::SpenderLimitExceeded {
+                remaining: max_amount.saturating_sub(used).u128(),
+            });
+        }
+    }
+    if let Some(max_amount) = limits.max_daily_outflow {
+        let used = daily_outflow(storage, now, &limits)?;
+        if used + amount > max_amount {
+            return Err(ContractError::DailyOutflowExceeded {
+                remaining: max_amount.saturating_sub(used).u128(),
+            });
+        }
+    }
+
+    // Buckets that left both windows are dropped.
+    let stale = first_bucket(now, limits.window_seconds.max(DAY_SECONDS), &limits);
+    if let Some(spender) = spender {
+        let old_buckets = SPENDER_OUTFLOW
+            .prefix(spender.clone())
+            .keys(
+                storage,
+                None,
+                Some(Bound::exclusive(stale)),
+                cosmwasm_std::Order::Ascending,
+            )
+            .collect::<StdResult<Vec<u64>>>()?;
+        for old_bucket in old_buckets {
+            SPENDER_OUTFLOW.remove(storage, (spender.clone(), old_bucket));
+        }
+        let key = (spender.clone(), bucket);
+        let value = SPENDER_OUTFLOW
+            .may_load(storage, key.clone())?
+            .unwrap_or_default();
+        SPENDER_OUTFLOW.save(storage, key, &(value + amount))?;
+    }
+    let old_buckets = VAULT_OUTFLOW
+        .keys(
+            storage,
+            None,
+            Some(Bound::exclusive(stale)),
+            cosmwasm_std::Order::Ascending,
+        )
+        .collect::<StdResult<Vec<u64>>>()?;
+    for old_bucket in old_buckets {
+        VAULT_OUTFLOW.remove(storage, old_bucket);
+    }
+    let value = VAULT_OUTFLOW.may_load(storage, bucket)?.unwrap_or_default();
+    VAULT_OUTFLOW.save(storage, bucket, &(value + amount))?;
+    Ok(())
+}
+
+/// Moves a spender's part of its budget's `reserved` to what its allowances
+/// hold back now.
+pub fn sync_budget(
+    storage: &mut dyn Storage,
+    spender: &Addr,
+) -> StdResult<Option<(String, Budget)>> {
+    let mut link = match ALLOWANCE_BUDGETS.may_load(storage, spender.clone())? {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    let reserved = allowance_reserve(storage, spender)?;
+    let mut budget = BUDGETS.load(storage, link.budget.clone())?;
+    budget.reserved = budget.reserved.saturating_sub(link.reserved) + reserved;
+    BUDGETS.save(storage, link.budget.clone(), &budget)?;
+    link.reserved = reserved;
+    ALLOWANCE_BUDGETS.save(storage, spender.clone(), &link)?;
+    Ok(Some((link.budget, budget)))
+}
+
+/// The allowances of a budget must fit in what is left of its allocation.
+pub fn check_budget(storage: &mut dyn Storage, spender: &Addr) -> Result<(), ContractError> {
+    if let Some((name, budget)) = sync_budget(storage, spender)? {
+        let available = budget.allocated.saturating_sub(budget.spent);
+        if budget.reserved > available {
+            return Err(ContractError::BudgetExceeded {
+                name,
+                available: available.u128(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Takes a spender and its reservation out of its budget.
+pub fn unassign_budget(storage: &mut dyn Storage, spender: &Addr) -> StdResult<Option<String>> {
+    let link = match ALLOWANCE_BUDGETS.may_load(storage, spender.clone())? {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    let mut budget = BUDGETS.load(storage, link.budget.clone())?;
+    budget.reserved = budget.reserved.saturating_sub(link.reserved);
+    BUDGETS.save(storage, link.budget.clone(), &budget)?;
+    ALLOWANCE_BUDGETS.remove(storage, spender.clone());
+    Ok(Some(link.budget))
+}
+
+/// A spender left without any allowance leaves its budget.
+pub fn release_budget(storage: &mut dyn Storage, spender: &Addr) -> StdResult<()> {
+    if ALLOWANCES.has(storage, spender.clone()) || PEGGED_ALLOWANCES.has(storage, spender.clone()) {
+        sync_budget(storage, spender)?;
+    } else {
+        unassign_budget(storage, spender)?;
+    }
+    Ok(())
+}
+
+/// Refund of a depositor of a closed vault.
+pub fn refund_amount(closure: &Closure, deposited: Uint128) -> Uint128 {
+    if closure.total_deposits.is_zero() {
+        return Uint128::zero();
+    }
+    closure
+        .refund_pool
+        .multiply_ratio(deposited, closure.total_deposits)
+}
+
+/// Unused budget of all matching pledges.
+pub fn pledged_amount(storage: &dyn Storage) -> StdResult<Uint128> {
+    Ok(PLEDGED_TOTAL.may_load(storage)?.unwrap_or_default())
+}
+
+/// Sponsors of the pledges that have not expired yet.
+pub fn active_pledges(storage: &dyn Storage, now: Timestamp) -> StdResult<Vec<Addr>> {
+    PLEDGE_EXPIRIES
+        .prefix_range(
+            storage,
+            Some(PrefixBound::exclusive(now.nanos())),
+            None,
+            cosmwasm_std::Order::Ascending,
+        )
+        .map(|item| item.map(|((_, sponsor), _)| sponsor))
+        .collect()
+}
+
+/// Tokens of `denom` the contract holds for others than the pool: escrow,
+/// sponsors' pledges, savings positions, uncollected fees, withholding of
+/// IBC payouts and sub-vaults.
+pub fn held_outside_pool(storage: &dyn Storage, state: &State, denom: &str) -> StdResult<Uint128> {
+    let mut held = vaults::locked_amount(storage, denom)?;
+    if denom == state.expected_denom {
+        held += ESCROW.may_load(storage)?.unwrap_or_default()
+            + pledged_amount(storage)?
+            + savings_amount(storage)?
+            + ACCRUED_FEES.may_load(storage)?.unwrap_or_default()
+            + HELD_WITHHOLDING.may_load(storage)?.unwrap_or_default();
+    }
+    Ok(held)
+}
+
+/// Stake that is unbonding and not credited to the pool yet.
+pub fn unbonding_amount(storage: &dyn Storage) -> StdResult<Uint128> {
+    UNBONDINGS
+        .range(storage, None, None, cosmwasm_std::Order::Ascending)
+        .try_fold(Uint128::zero(), |total, item| {
+            let (_, unbonding) = item?;
+            StdResult::Ok(total + unbonding.amount)
+        })
+}
+
+/// Unbonded tokens that are in the bank `balance` but not credited yet: what
+/// it holds beyond the accounted funds, up to the pending unbondings.
+pub fn arrived_unbonded(
+    storage: &dyn Storage,
+    state: &State,
+    balance: Uint128,
+) -> StdResult<Uint128> {
+    let accounted = state.total_amount + held_outside_pool(storage, state, &state.expected_denom)?;
+    Ok(balance
+        .saturating_sub(accounted)
+        .min(unbonding_amount(storage)?))
+}
+
+/// Principal of all savings positions.
+pub fn savings_amount(storage: &dyn Storage) -> StdResult<Uint128> {
+    POSITIONS
+        .range(storage, None, None, cosmwasm_std::Order::Ascending)
+        .try_fold(Uint128::zero(), |total, item| {
+            let (_, position) = item?;
+            StdResult::Ok(total + position.amount)
+        })
+}
+
+/// Takes the protocol fee at `bps` from `gross`. Returns `None` without a fee
+/// config, otherwise the receipt and the payment to the treasury, if any.
+pub fn take_fee(
+    storage: &mut dyn Storage,
+    gross: Uint128,
+    denom: &str,
+    bps: impl Fn(&FeeConfig) -> u16,
+) -> StdResult<Option<(FeeReceipt, Option<BankMsg>)>> {
+    let config = match FEE_CONFIG.may_load(storage)? {
+        Some(config) => config,
+        None => return Ok(None),
+    };
+    let rate = bps(&config);
+    let mut fee = Uint128::zero();
+    if rate > 0 {
+        fee = gross.multiply_ratio(rate, 10_000u128).max(config.min_fee);
+        if let Some(max_fee) = config.max_fee {
+            fee = fee.min(max_fee);
+        }
+        fee = fee.min(gross);
+    }
+    let receipt = FeeReceipt {
+        gross,
+        fee,
+        net: gross - fee,
+    };
+    if fee.is_zero() {
+        return Ok(Some((receipt, None)));
+    }
+    let payment = match config.treasury {
+        Some(treasury) => Some(BankMsg::Send {
+            to_address: treasury.to_string(),
+            amount: coins(fee.u128(), denom),
+        }),
+        None => {
+            let accrued = ACCRUED_FEES.may_load(storage)?.unwrap_or_default();
+            ACCRUED_FEES.save(storage, &(accrued + fee))?;
+            None
+        }
+    };
+    Ok(Some((receipt, payment)))
+}
+
+/// Adds the fee breakdown of a deposit or claim to its response.
+pub fn fee_response(
+    mut response: Response,
+    receipt: &FeeReceipt,
+    payment: Option<BankMsg>,
+) -> StdResult<Response> {
+    if let Some(payment) = payment {
+        response = response.add_message(payment);
+    }
+    Ok(response
+        .add_attribute("gross_amount", receipt.gross)
+        .add_attribute("fee", receipt.fee)
+        .add_attribute("net_amount", receipt.net)
+        .set_data(to_json_binary(receipt)?))
+}
+
+/// Calendar year (UTC) of a block time.
+pub fn calendar_year(time: Timestamp) -> u32 {
+    // Days to civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let days = time.seconds() / DAY_SECONDS + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    // The computed year starts in March.
+    let year = era * 400 + year_of_era + u64::from(month_index >= 10);
+    year as u32
+}
+
+/// Withholding on a claim of `amount` after `claimed` was already claimed this
+/// year. Every part of the claim is taxed at the rate of its bracket.
+pub fn withholding_amount(rule: &WithholdingRule, claimed: Uint128, amount: Uint128) -> Uint128 {
+    let end = claimed + amount;
+    let mut withheld = Uint128::zero();
+    let mut lower = Uint128::zero();
+    let mut rate = rule.rate;
+    for bracket in rule.brackets.iter().map(Some).chain([None]) {
+        let upper = bracket.map_or(Uint128::MAX, |bracket| bracket.threshold);
+        withheld += end.min(upper).saturating_sub(claimed.max(lower)) * rate;
+        if let Some(bracket) = bracket {
+            lower = bracket.threshold;
+            rate = bracket.rate;
+        }
+    }
+    withheld
+}
+
+/// Adds a claim to the spender's totals for the year. Returns the destination
+/// and the withheld amount when a rule applies.
+pub fn withhold(
+    storage: &mut dyn Storage,
+    spender: &Addr,
+    amount: Uint128,
+    now: Timestamp,
+) -> StdResult<Option<(Addr, Uint128)>> {
+    let rule = match WITHHOLDING_RULES.may_load(storage, spender.clone())? {
+        Some(rule) => rule,
+        None => match GLOBAL_WITHHOLDING.may_load(storage)? {
+            Some(rule) => rule,
+            None => return Ok(None),
+        },
+    };
+    let key = (spender.clone(), calendar_year(now));
+    let mut totals = WITHHOLDING_TOTALS
+        .may_load(storage, key.clone())?
+        .unwrap_or_default();
+    let withheld = withholding_amount(&rule, totals.claimed, amount);
+    totals.claimed += amount;
+    totals.withheld += withheld;
+    WITHHOLDING_TOTALS.save(storage, key, &totals)?;
+    Ok(Some((rule.destination, withheld)))
+}
+
+/// Rejects addresses on the deny list or flagged by the compliance registry.
+pub fn screen(deps: Deps, addresses: &[&str]) -> Result<(), ContractError> {
+    let registry = COMPLIANCE_REGISTRY.may_load(deps.storage)?;
+    for address in addresses {
+        let mut flagged = DENY_LIST.has(deps.storage, Addr::unchecked(*address));
+        if let (false, Some(registry)) = (flagged, &registry) {
+            let response: FlaggedResponse = deps.querier.query_wasm_smart(
+                registry,
+                &ComplianceQueryMsg::IsFlagged {
+                    address: address.to_string(),
+                },
+            )?;
+            flagged = response.flagged;
+        }
+        if flagged {
+            return Err(ContractError::AddressFlagged {
+                address: address.to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Whether the campaign ended without reaching its goal.
+pub fn campaign_failed(campaign: &Campaign, now: Timestamp) -> bool {
+    !campaign.unlocked && now > campaign.deadline && campaign.raised < campaign.goal
+}
+
+/// Whether the campaign still holds the funds. A failed campaign lets go once
+/// the reclaim period is over, and whatever was not reclaimed stays in the pool.
+pub fn campaign_locked(campaign: &Campaign, now: Timestamp) -> bool {
+    let ended = campaign_failed(campaign, now)
+        && now > campaign.deadline.plus_seconds(CAMPAIGN_RECLAIM_SECONDS);
+    !(campaign.unlocked || ended)
+}
+
+/// One submessage per registered hook. Failures of non-blocking hooks are
+/// caught in the reply and only logged.
+pub fn hook_messages(storage: &dyn Storage, msg: VaultHookMsg) -> StdResult<Vec<SubMsg>> {
+    let msg = to_json_binary(&HookExecuteMsg::VaultHook(msg))?;
+    HOOKS
+        .range(storage, None, None, cosmwasm_std::Order::Ascending)
+        .map(|item| {
+            let (address, blocking) = item?;
+            let execute = WasmMsg::Execute {
+                contract_addr: address.to_string(),
+                msg: msg.clone(),
+                funds: vec![],
+            };
+            Ok(if blocking {
+                SubMsg::new(execute)
+            } else {
+                SubMsg::reply_on_error(execute, HOOK_REPLY_ID)
+            })
+        })
+        .collect()
+}
+
+// Shares are priced against the free pool. The virtual share and token keep
+// the first deposit from being front-run by a donation to the vault.
+pub fn shares_for_deposit(amount: Uint128, free_amount: Uint128, supply: Uint128) -> Uint128 {
+    amount.multiply_ratio(supply + Uint128::one(), free_amount + Uint128::one())
+}
+
+pub fn amount_for_shares(shares: Uint128, free_amount: Uint128, supply: Uint128) -> Uint128 {
+    shares.multiply_ratio(free_amount + Uint128::one(), supply + Uint128::one())
+}
+
 pub mod execute {
+    use crate::dex::{Asset, AssetInfo, DexExecuteMsg};
     use crate::error::ContractError;
     use crate::error::ContractError::UnauthorizedDepositAddress;
-    use crate::state::{ALLOWANCES, DEPOSIT_ADDRESSES, STATE};
-    use cosmwasm_std::{BankMsg, Coin, DepsMut, Env, MessageInfo, Response, Uint128};
+    use crate::ibc;
+    use crate::merkle::{self, Hash};
+    use crate::msg::{
+        MilestoneMsg, ReceiveMsg, VaultHookMsg, VaultPacket, Voucher, WithholdingBracketMsg,
+    };
+    use crate::oracle::{OracleQueryMsg, PriceResponse};
+    use crate::state::{
+        Budget, BudgetLink, Campaign, Clawback, Closure, DeadManSwitch, Distribution, FeeConfig,
+        Grant, HeldWithholding, Invoice, InvoiceReceipt, Milestone, OracleConfig, PayoutPreference,
+        PendingSwap, PendingTransfer, Pledge, Position, RateLimits, Reallocation, ReferencePrice,
+        ShareConfig, StakingConfig, State, SwapConfig, TrustedVault, Unbonding, Vendor,
+        WithholdingBracket, WithholdingRule, ACCRUED_FEES, ALLOWANCES, ALLOWANCE_BUDGETS, BUDGETS,
+        CAMPAIGN, CAMPAIGN_CONTRIBUTIONS, CLAWBACKS, CLAWBACK_COUNT, CLOSURE, COMPLIANCE_REGISTRY,
+        DEAD_MAN_SWITCH, DELEGATIONS, DENY_LIST, DEPOSITED, DEPOSIT_ADDRESSES, DISTRIBUTIONS,
+        DISTRIBUTION_CLAIMS, DISTRIBUTION_COUNT, EARLY_EXIT_PENALTY, ESCROW, FEE_CONFIG,
+        FROZEN_ALLOWANCES, GLOBAL_WITHHOLDING, GRANTS, GRANT_COUNT, HELD_WITHHOLDING, HOOKS,
+        INVOICES, INVOICE_COUNT, LAST_PRICE, MIN_PLEDGE_BUDGET, ORACLE_CONFIG, OUTGOING_TRANSFER,
+        PAYOUT_PREFERENCES, PEGGED_ALLOWANCES, PENDING_SWAP, PENDING_TRANSFERS, PLEDGED_TOTAL,
+        PLEDGES, PLEDGE_EXPIRIES, PLEDGE_MATCHES, POSITIONS, POSITION_COUNT, RATE_LIMITS,
+        REALLOCATIONS, REALLOCATION_COUNT, RECOVERY_VOTES, REFUND_CLAIMS, SHARE_CONFIG,
+        SPENDER_OUTFLOW, STAKING_BALANCE, STAKING_CONFIG, STATE, SWAP_CONFIG, TRUSTED_VAULTS,
+        UNBONDINGS, UNBONDING_COUNT, UNPAID_INVOICES, USED_NONCES, VAULT_CHANNELS, VAULT_OUTFLOW,
+        VENDORS, VOUCHER_PUBKEY, WITHHOLDING_RULES,
+    };
+    use cosmwasm_std::{
+        coins, from_json, to_json_binary, to_json_vec, Addr, BankMsg, Binary, Coin, CosmosMsg,
+        Decimal, Deps, DepsMut, DistributionMsg, Env, IbcMsg, IbcTimeout, MessageInfo, Response,
+        StakingMsg, StdError, StdResult, Storage, SubMsg, Timestamp, Uint128, WasmMsg,
+    };
+    use cw20::{Cw20ExecuteMsg, Cw20ReceiveMsg};
+    use cw_storage_plus::Bound;
+    use sha2::{Digest, Sha256};
 
-    pub fn deposit_token(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
-        let mut current_state = STATE.load(deps.storage)?;
-        let mut current_coin = Coin {
-            denom: "".to_string(),
-            amount: Uint128::zero(),
-        };
-        if info.funds.iter().any(|coin| {
-            current_coin = coin.clone();
-            coin.denom == current_state.expected_denom
-        }) {
-            let address = info.sender;
-            if current_coin.denom != current_state.expected_denom {
-                return Err(ContractError::InvalidDenom {
-                    denom: current_coin.denom.to_string(),
+    const DEFAULT_INVOICE_LIMIT: u32 = 10;
+    const MAX_INVOICE_LIMIT: u32 = 30;
+
+    pub fn deposit_token(
+        mut deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+    ) -> Result<Response, ContractError> {
+        super::screen(deps.as_ref(), &[info.sender.as_str()])?;
+        let current_state = STATE.load(deps.storage)?;
+        let address = info.sender;
+        if let Some(coin) = info
+            .funds
+            .iter()
+            .find(|coin| coin.denom == current_state.expected_denom)
+        {
+            if !DEPOSIT_ADDRESSES.has(deps.storage, address.clone()) {
+                return Err(UnauthorizedDepositAddress {
+                    address: address.to_string(),
                 });
             }
-            let address_amount = DEPOSIT_ADDRESSES.may_load(deps.storage, address.clone())?;
-            match address_amount {
-                Some(value) => {
-                    DEPOSIT_ADDRESSES.save(
-                        deps.storage,
-                        address.clone(),
-                        &value.checked_add(current_coin.amount).unwrap_or(value),
-                    )?;
-                    current_state.total_amount = current_state
-                        .total_amount
-                        .checked_add(current_coin.amount)
-                        .unwrap_or(current_state.total_amount);
-
-                    STATE.save(deps.storage, &current_state)?;
-                }
-                None => {
-                    return Err(UnauthorizedDepositAddress {
-                        address: address.clone().to_string(),
-                    });
+            let response = credit_deposit(deps.branch(), &env, address.clone(), coin.amount)?;
+            return match_deposit(deps, &env, address, coin.amount, response);
+        }
+
+        Err(ContractError::InvalidDenom {
+            denom: current_state.expected_denom,
+        })
+    }
+
+    /// Adds a deposit in the payout denom to the pool and to the depositor's total.
+    pub fn credit_deposit(
+        deps: DepsMut,
+        env: &Env,
+        address: Addr,
+        amount: Uint128,
+    ) -> Result<Response, ContractError> {
+        let mut current_state = STATE.load(deps.storage)?;
+        let mut response = Response::new();
+        // The fee never reaches the pool.
+        let fee = super::take_fee(
+            deps.storage,
+            amount,
+            &current_state.expected_denom,
+            |config| config.deposit_bps,
+        )?;
+        let amount = match fee {
+            Some((receipt, payment)) => {
+                if receipt.net.is_zero() {
+                    return Err(ContractError::FeeExceedsAmount { fee: receipt.fee });
                 }
+                response = super::fee_response(response, &receipt, payment)?;
+                receipt.net
             }
-            Ok(Response::new()
-                .add_attribute("action", "deposit")
-                .add_attribute("address", address.to_string())
-                .add_attribute("amount", current_coin.amount))
-        } else {
-            Err(ContractError::InvalidDenom {
-                denom: current_state.expected_denom,
-            })
+            None => amount,
+        };
+        response = response
+            .add_attribute("action", "deposit")
+            .add_attribute("address", address.to_string())
+            .add_attribute("amount", amount);
+
+        // Shares are priced before the deposit is added to the pool.
+        if let Some(mut config) = SHARE_CONFIG.may_load(deps.storage)? {
+            let shares = super::shares_for_deposit(
+                amount,
+                super::share_assets(deps.storage, &current_state)?,
+                config.total_supply,
+            );
+            if shares.is_zero() {
+                return Err(ContractError::ZeroShares {});
+            }
+            config.total_supply += shares;
+            SHARE_CONFIG.save(deps.storage, &config)?;
+            response = response
+                .add_message(WasmMsg::Execute {
+                    contract_addr: config.token.to_string(),
+                    msg: to_json_binary(&Cw20ExecuteMsg::Mint {
+                        recipient: address.to_string(),
+                        amount: shares,
+                    })?,
+                    funds: vec![],
+                })
+                .add_attribute("shares", shares);
+        }
+
+        if let Some(mut campaign) = CAMPAIGN.may_load(deps.storage)? {
+            if super::campaign_locked(&campaign, env.block.time) {
+                campaign.raised += amount;
+                CAMPAIGN.save(deps.storage, &campaign)?;
+                let contributed = CAMPAIGN_CONTRIBUTIONS
+                    .may_load(deps.storage, address.clone())?
+                    .unwrap_or_default();
+                CAMPAIGN_CONTRIBUTIONS.save(
+                    deps.storage,
+                    address.clone(),
+                    &(contributed + amount),
+                )?;
+            }
+        }
+
+        let value = DEPOSIT_ADDRESSES
+            .may_load(deps.storage, address.clone())?
+            .unwrap_or_default();
+        DEPOSIT_ADDRESSES.save(
+            deps.storage,
+            address.clone(),
+            &value.checked_add(amount).unwrap_or(value),
+        )?;
+        let deposited = DEPOSITED
+            .may_load(deps.storage, address.clone())?
+            .unwrap_or_default();
+        DEPOSITED.save(
+            deps.storage,
+            address.clone(),
+            &deposited.checked_add(amount).unwrap_or(deposited),
+        )?;
+        current_state.total_amount = current_state
+            .total_amount
+            .checked_add(amount)
+            .unwrap_or(current_state.total_amount);
+        STATE.save(deps.storage, &current_state)?;
+
+        Ok(response.add_submessages(super::hook_messages(
+            deps.storage,
+            VaultHookMsg::Deposit {
+                address: address.to_string(),
+                amount,
+            },
+        )?))
+    }
+
+    /// Moves the sponsors' matches for a deposit from their budgets into the pool.
+    pub fn match_deposit(
+        deps: DepsMut,
+        env: &Env,
+        depositor: Addr,
+        amount: Uint128,
+        mut response: Response,
+    ) -> Result<Response, ContractError> {
+        let mut total_matched = Uint128::zero();
+        for sponsor in super::active_pledges(deps.storage, env.block.time)? {
+            if sponsor == depositor {
+                continue;
+            }
+            let mut pledge = PLEDGES.load(deps.storage, sponsor.clone())?;
+            let key = (sponsor.clone(), depositor.clone());
+            let matched_before = PLEDGE_MATCHES
+                .may_load(deps.storage, key.clone())?
+                .unwrap_or_default();
+            let matched = (amount * pledge.ratio)
+                .min(pledge.budget)
+                .min(pledge.cap_per_depositor.saturating_sub(matched_before));
+            if matched.is_zero() {
+                continue;
+            }
+            pledge.budget -= matched;
+            pledge.matched += matched;
+            PLEDGES.save(deps.storage, sponsor.clone(), &pledge)?;
+            PLEDGE_MATCHES.save(deps.storage, key, &(matched_before + matched))?;
+            total_matched += matched;
+            response = response
+                .add_attribute("sponsor", sponsor.to_string())
+                .add_attribute("matched", matched);
+        }
+        if !total_matched.is_zero() {
+            let mut current_state = STATE.load(deps.storage)?;
+            current_state.total_amount += total_matched;
+            STATE.save(deps.storage, &current_state)?;
+            let pledged = super::pledged_amount(deps.storage)?;
+            PLEDGED_TOTAL.save(deps.storage, &(pledged - total_matched))?;
+        }
+        Ok(response)
+    }
+
+    /// Swaps a deposit in another denom into the payout denom when auto swap is on.
+    /// The depositor names the least they accept, the reply credits what the pair returned.
+    pub fn swap_deposit(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        min_return: Uint128,
+    ) -> Result<Response, ContractError> {
+        super::screen(deps.as_ref(), &[info.sender.as_str()])?;
+        let current_state = STATE.load(deps.storage)?;
+        let address = info.sender;
+        let (config, offer) = match (SWAP_CONFIG.may_load(deps.storage)?, info.funds.as_slice()) {
+            (Some(config), [coin])
+                if config.auto_swap && coin.denom != current_state.expected_denom =>
+            {
+                (config, coin.clone())
+            }
+            _ => {
+                return Err(ContractError::InvalidDenom {
+                    denom: current_state.expected_denom,
+                })
+            }
+        };
+        if !DEPOSIT_ADDRESSES.has(deps.storage, address.clone()) {
+            return Err(UnauthorizedDepositAddress {
+                address: address.to_string(),
+            });
         }
+        if min_return.is_zero() {
+            return Err(ContractError::ZeroMinReturn {});
+        }
+        PENDING_SWAP.save(
+            deps.storage,
+            &PendingSwap {
+                router: config.router.clone(),
+                depositor: address.clone(),
+                offer: offer.clone(),
+                min_return,
+            },
+        )?;
+
+        let swap_msg = WasmMsg::Execute {
+            contract_addr: config.router.to_string(),
+            msg: to_json_binary(&DexExecuteMsg::Swap {
+                offer_asset: Asset {
+                    info: AssetInfo::NativeToken {
+                        denom: offer.denom.clone(),
+                    },
+                    amount: offer.amount,
+                },
+                ask_asset_info: Some(AssetInfo::NativeToken {
+                    denom: current_state.expected_denom,
+                }),
+                belief_price: Some(Decimal::from_ratio(offer.amount, min_return)),
+                max_spread: Some(config.max_slippage),
+                to: Some(env.contract.address.to_string()),
+            })?,
+            funds: vec![offer.clone()],
+        };
+        Ok(Response::new()
+            .add_submessage(SubMsg::reply_on_success(swap_msg, super::SWAP_REPLY_ID))
+            .add_attribute("action", "swap_deposit")
+            .add_attribute("address", address.to_string())
+            .add_attribute("offer_amount", offer.amount)
+            .add_attribute("offer_denom", offer.denom)
+            .add_attribute("min_return", min_return))
     }
 
     pub fn add_deposit_address(
@@ -91,7 +990,10 @@ pub mod execute {
                 })
             }
         };
-        DEPOSIT_ADDRESSES.save(deps.storage, address, &Uint128::zero())?;
+        // Adding an address again keeps what it deposited so far.
+        if !DEPOSIT_ADDRESSES.has(deps.storage, address.clone()) {
+            DEPOSIT_ADDRESSES.save(deps.storage, address, &Uint128::zero())?;
+        }
         Ok(Response::new()
             .add_attribute("action", "add_deposit_address")
             .add_attribute("address", deposit_address))
@@ -130,19 +1032,60 @@ pub mod execute {
                 owner: current_state.owner.to_string(),
             });
         }
+        super::screen(deps.as_ref(), &[info.sender.as_str()])?;
+        // The pool belongs to the share holders, who leave by redeeming.
+        super::check_no_shares(deps.storage)?;
 
-        let balance = deps.querier.query_all_balances(&env.contract.address)?;
-        let bank_msg = BankMsg::Send {
-            to_address: info.sender.to_string(),
-            amount: balance,
-        };
+        // Escrow, sub-vaults, pledges and the like stay in the vault, and so
+        // do unbonded tokens until `ClaimUnbonded` credits them.
+        let balance = deps
+            .querier
+            .query_all_balances(&env.contract.address)?
+            .into_iter()
+            .map(|mut coin| {
+                let mut held = super::held_outside_pool(deps.storage, &current_state, &coin.denom)?;
+                if coin.denom == current_state.expected_denom {
+                    held += super::arrived_unbonded(deps.storage, &current_state, coin.amount)?;
+                }
+                coin.amount = coin.amount.saturating_sub(held);
+                StdResult::Ok(coin)
+            })
+            .filter(|coin| coin.as_ref().map_or(true, |coin| !coin.amount.is_zero()))
+            .collect::<StdResult<Vec<Coin>>>()?;
 
-        current_state.total_amount = Uint128::zero();
+        // The payout denom is capped to what the daily outflow limit allows,
+        // the rest stays in the pool.
+        let mut balance = balance;
+        let remaining = super::daily_outflow_remaining(deps.storage, env.block.time)?;
+        let mut left = Uint128::zero();
+        for coin in balance.iter_mut() {
+            if coin.denom == current_state.expected_denom {
+                if let Some(remaining) = remaining {
+                    left = coin.amount.saturating_sub(remaining);
+                    coin.amount = coin.amount.min(remaining);
+                }
+                super::record_outflow(deps.storage, None, coin.amount, env.block.time)?;
+            }
+        }
+        balance.retain(|coin| !coin.amount.is_zero());
+
+        current_state.total_amount = current_state.total_amount.min(left);
         STATE.save(deps.storage, &current_state)?;
 
-        Ok(Response::new()
-            .add_message(bank_msg)
-            .add_attribute("action", "withdraw"))
+        let mut response = Response::new().add_attribute("action", "withdraw");
+        if !balance.is_empty() {
+            response = response.add_message(BankMsg::Send {
+                to_address: info.sender.to_string(),
+                amount: balance.clone(),
+            });
+        }
+        Ok(response.add_submessages(super::hook_messages(
+            deps.storage,
+            VaultHookMsg::Withdraw {
+                owner: info.sender.to_string(),
+                amount: balance,
+            },
+        )?))
     }
 
     pub fn add_allowance(
@@ -161,8 +1104,21 @@ pub mod execute {
             Ok(value) => value,
             Err(_) => return Err(ContractError::NotValidAddress { address: spender }),
         };
-        ALLOWANCES.save(deps.storage, address, &amount)?;
+        let previous = ALLOWANCES
+            .may_load(deps.storage, address.clone())?
+            .unwrap_or_default();
+        super::check_shares_undiluted(deps.storage, previous, amount)?;
+        super::save_allowance(deps.storage, &address, amount)?;
+        super::check_budget(deps.storage, &address)?;
+        let hooks = super::hook_messages(
+            deps.storage,
+            VaultHookMsg::AllowanceChanged {
+                spender: spender.clone(),
+                amount: Some(amount),
+            },
+        )?;
         Ok(Response::new()
+            .add_submessages(hooks)
             .add_attribute("action", "add_allowance")
             .add_attribute("spender", spender)
             .add_attribute("amount", amount.to_string()))
@@ -195,11 +1151,24 @@ pub mod execute {
                         })
                     }
                 };
-                ALLOWANCES
-                    .save(deps.storage, address, &amounts[index])
-                    .map_err(ContractError::Std)
+                let previous = ALLOWANCES
+                    .may_load(deps.storage, address.clone())?
+                    .unwrap_or_default();
+                super::check_shares_undiluted(deps.storage, previous, amounts[index])?;
+                super::save_allowance(deps.storage, &address, amounts[index])?;
+                super::check_budget(deps.storage, &address)
             })?;
-        Ok(Response::new().add_attribute("action", "add_allowance_list"))
+        let mut response = Response::new().add_attribute("action", "add_allowance_list");
+        for (spender, amount) in spenders.into_iter().zip(amounts) {
+            response = response.add_submessages(super::hook_messages(
+                deps.storage,
+                VaultHookMsg::AllowanceChanged {
+                    spender,
+                    amount: Some(amount),
+                },
+            )?);
+        }
+        Ok(response)
     }
 
     pub fn remove_allowance(
@@ -217,8 +1186,17 @@ pub mod execute {
             Ok(value) => value,
             Err(_) => return Err(ContractError::NotValidAddress { address: spender }),
         };
-        ALLOWANCES.remove(deps.storage, address);
+        super::remove_allowance(deps.storage, &address)?;
+        super::release_budget(deps.storage, &address)?;
+        let hooks = super::hook_messages(
+            deps.storage,
+            VaultHookMsg::AllowanceChanged {
+                spender: spender.clone(),
+                amount: None,
+            },
+        )?;
         Ok(Response::new()
+            .add_submessages(hooks)
             .add_attribute("action", "remove_allowance")
             .add_attribute("spender", spender))
     }
@@ -239,24 +1217,77 @@ pub mod execute {
             Ok(value) => value,
             Err(_) => return Err(ContractError::NotValidAddress { address: spender }),
         };
-        ALLOWANCES.save(deps.storage, address, &amount)?;
+        let previous = ALLOWANCES
+            .may_load(deps.storage, address.clone())?
+            .unwrap_or_default();
+        super::check_shares_undiluted(deps.storage, previous, amount)?;
+        super::save_allowance(deps.storage, &address, amount)?;
+        super::check_budget(deps.storage, &address)?;
+        let hooks = super::hook_messages(
+            deps.storage,
+            VaultHookMsg::AllowanceChanged {
+                spender: spender.clone(),
+                amount: Some(amount),
+            },
+        )?;
         Ok(Response::new()
+            .add_submessages(hooks)
             .add_attribute("action", "update_allowance")
             .add_attribute("spender", spender)
             .add_attribute("amount", amount.to_string()))
     }
 
-    pub fn retrieve_allowance(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    pub fn retrieve_allowance(
+        mut deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+    ) -> Result<Response, ContractError> {
+        if FROZEN_ALLOWANCES.has(deps.storage, info.sender.clone()) {
+            return Err(ContractError::AllowanceFrozen {
+                spender: info.sender.to_string(),
+            });
+        }
+        super::screen(deps.as_ref(), &[info.sender.as_str()])?;
         let mut current_state = STATE.load(deps.storage)?;
         let allowance = match ALLOWANCES.load(deps.storage, info.clone().sender) {
             Ok(value) => value,
-            Err(_) => {
-                return Err(ContractError::NoAllowance {
-                    address: info.sender.to_string(),
-                })
-            }
+            Err(_) => match PEGGED_ALLOWANCES.may_load(deps.storage, info.sender.clone())? {
+                Some(quote_amount) => {
+                    pegged_amount(deps.branch(), &env, &current_state, quote_amount)?
+                }
+                None => {
+                    return Err(ContractError::NoAllowance {
+                        address: info.sender.to_string(),
+                    })
+                }
+            },
         };
 
+        if allowance.is_zero() {
+            return Err(ContractError::ZeroAllowance {
+                address: info.sender.to_string(),
+            });
+        }
+        if allowance > current_state.total_amount {
+            return Err(ContractError::NotEnoughFunds {
+                total_amount: current_state.total_amount.u128(),
+            });
+        }
+        super::record_outflow(deps.storage, Some(&info.sender), allowance, env.block.time)?;
+        if let Some(link) = ALLOWANCE_BUDGETS.may_load(deps.storage, info.sender.clone())? {
+            let name = link.budget;
+            let mut budget = BUDGETS.load(deps.storage, name.clone())?;
+            let available = budget.allocated.saturating_sub(budget.spent);
+            if allowance > available {
+                return Err(ContractError::BudgetExceeded {
+                    name,
+                    available: available.u128(),
+                });
+            }
+            budget.spent += allowance;
+            BUDGETS.save(deps.storage, name, &budget)?;
+        }
+
         current_state.total_amount = current_state
             .total_amount
             .checked_sub(allowance)
@@ -264,15 +1295,110 @@ pub mod execute {
 
         STATE.save(deps.storage, &current_state)?;
 
-        let bank_msg = BankMsg::Send {
-            to_address: info.clone().sender.to_string(),
-            amount: vec![Coin {
-                denom: current_state.expected_denom,
-                amount: allowance,
-            }],
+        // The whole allowance leaves the pool, the spender gets it minus the
+        // fee and the withholding.
+        let mut payout_response = Response::new();
+        let mut payout = allowance;
+        if let Some((receipt, payment)) = super::take_fee(
+            deps.storage,
+            allowance,
+            &current_state.expected_denom,
+            |config| config.claim_bps,
+        )? {
+            if receipt.net.is_zero() {
+                return Err(ContractError::FeeExceedsAmount { fee: receipt.fee });
+            }
+            payout_response = super::fee_response(payout_response, &receipt, payment)?;
+            payout = receipt.net;
+        }
+        let preference = PAYOUT_PREFERENCES.may_load(deps.storage, info.sender.clone())?;
+        let mut withholding = None;
+        if let Some((destination, withheld)) =
+            super::withhold(deps.storage, &info.sender, payout, env.block.time)?
+        {
+            payout -= withheld;
+            payout_response = payout_response
+                .add_attribute("withheld", withheld)
+                .add_attribute("withholding_destination", destination.to_string());
+            if preference.is_some() {
+                // An IBC payout can still fail, so the withholding waits for the ack.
+                let held = HELD_WITHHOLDING.may_load(deps.storage)?.unwrap_or_default();
+                HELD_WITHHOLDING.save(deps.storage, &(held + withheld))?;
+                withholding = Some(HeldWithholding {
+                    destination,
+                    amount: withheld,
+                    year: super::calendar_year(env.block.time),
+                });
+            } else if !withheld.is_zero() {
+                payout_response = payout_response.add_message(BankMsg::Send {
+                    to_address: destination.to_string(),
+                    amount: coins(withheld.u128(), current_state.expected_denom.clone()),
+                });
+            }
+        }
+        let coin = Coin {
+            denom: current_state.expected_denom,
+            amount: payout,
         };
-        Ok(Response::new()
-            .add_message(bank_msg)
+        let response = match preference {
+            Some(preference) => {
+                super::screen(deps.as_ref(), &[preference.remote_address.as_str()])?;
+                // The packet sequence is only known in the reply.
+                OUTGOING_TRANSFER.save(
+                    deps.storage,
+                    &PendingTransfer {
+                        spender: info.sender.clone(),
+                        channel_id: preference.channel_id.clone(),
+                        amount: payout,
+                        vault_channel_id: None,
+                        withholding,
+                    },
+                )?;
+                let ibc_msg = crate::ics20::transfer_msg(
+                    &env.contract.address,
+                    &preference.channel_id,
+                    &preference.remote_address,
+                    &coin,
+                    env.block.time.plus_seconds(preference.timeout_seconds),
+                    &crate::ics20::callback_memo(&env.contract.address)?,
+                );
+                payout_response
+                    .add_submessage(SubMsg::reply_on_success(
+                        ibc_msg,
+                        super::IBC_TRANSFER_REPLY_ID,
+                    ))
+                    .add_attribute("channel_id", preference.channel_id)
+                    .add_attribute("remote_address", preference.remote_address)
+            }
+            None => payout_response.add_message(BankMsg::Send {
+                to_address: info.clone().sender.to_string(),
+                amount: vec![coin],
+            }),
+        };
+        // Claims in share mode use the allowance up, so the free funds of the
+        // share holders stay the same.
+        if SHARE_CONFIG.exists(deps.storage) {
+            super::remove_allowance(deps.storage, &info.sender)?;
+            PEGGED_ALLOWANCES.remove(deps.storage, info.sender.clone());
+        }
+        // A closed vault pays every reserved allowance one last time.
+        if let Some(mut closure) = CLOSURE.may_load(deps.storage)? {
+            super::remove_allowance(deps.storage, &info.sender)?;
+            finalize_if_settled(deps.storage, &mut closure)?;
+        }
+        // A used up allowance or a new price changes what the vault and the
+        // budget hold back.
+        super::refresh_pegged_reserve(deps.storage, &info.sender)?;
+        super::sync_budget(deps.storage, &info.sender)?;
+        let hooks = super::hook_messages(
+            deps.storage,
+            VaultHookMsg::Claim {
+                spender: info.sender.to_string(),
+                amount: allowance,
+            },
+        )?;
+        Ok(response
+            .add_submessages(hooks)
             .add_attribute("action", "retrieve_allowance")
             .add_attribute("address", info.sender.to_string())
             .add_attribute("amount", allowance))
@@ -296,6 +1422,7 @@ pub mod execute {
 
     pub fn update_owner(
         deps: DepsMut,
+        env: Env,
         info: MessageInfo,
         owner: String,
     ) -> Result<Response, ContractError> {
@@ -307,81 +1434,4315 @@ pub mod execute {
         }
 
         let new_owner = deps.api.addr_validate(owner.as_str())?;
-
-        DEPOSIT_ADDRESSES.remove(deps.storage, current_state.owner);
-        DEPOSIT_ADDRESSES.save(deps.storage, new_owner.clone(), &Uint128::zero())?;
-
-        current_state.owner = new_owner;
-        STATE.save(deps.storage, &current_state)?;
+        super::change_owner(deps.storage, &mut current_state, new_owner, env.block.time)?;
 
         Ok(Response::new()
             .add_attribute("action", "update_owner")
             .add_attribute("owner", owner))
     }
-}
 
-pub mod query {
-    use crate::{
-        msg::{
-            AllowanceResponse, AllowancesResponse, CanDepositResponse, DepositAddressesResponse,
-            StateResponse,
-        },
-        state::{ALLOWANCES, DEPOSIT_ADDRESSES, STATE},
-    };
-    use cosmwasm_std::{Addr, Deps, StdResult, Uint128};
+    pub fn add_vendor(
+        deps: DepsMut,
+        info: MessageInfo,
+        vendor: String,
+        name: String,
+        default_denom: String,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let address = match deps.api.addr_validate(vendor.as_str()) {
+            Ok(value) => value,
+            Err(_) => return Err(ContractError::NotValidAddress { address: vendor }),
+        };
+        VENDORS.save(
+            deps.storage,
+            address,
+            &Vendor {
+                name: name.clone(),
+                default_denom,
+            },
+        )?;
+        Ok(Response::new()
+            .add_attribute("action", "add_vendor")
+            .add_attribute("vendor", vendor)
+            .add_attribute("name", name))
+    }
 
-    pub fn get_state(deps: Deps) -> StdResult<StateResponse> {
+    pub fn remove_vendor(
+        deps: DepsMut,
+        info: MessageInfo,
+        vendor: String,
+    ) -> Result<Response, ContractError> {
         let current_state = STATE.load(deps.storage)?;
-        Ok(StateResponse {
-            owner: current_state.owner.to_string(),
-            name: current_state.name,
-            total_amount: current_state.total_amount,
-            expected_denom: current_state.expected_denom,
-        })
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let address = match deps.api.addr_validate(vendor.as_str()) {
+            Ok(value) => value,
+            Err(_) => return Err(ContractError::NotValidAddress { address: vendor }),
+        };
+        VENDORS.remove(deps.storage, address);
+        Ok(Response::new()
+            .add_attribute("action", "remove_vendor")
+            .add_attribute("vendor", vendor))
     }
 
-    pub fn get_allowance(deps: Deps, spender: String) -> StdResult<AllowanceResponse> {
-        let address = deps.api.addr_validate(spender.as_str())?;
-        let amount = ALLOWANCES.load(deps.storage, address)?;
-        Ok(AllowanceResponse { spender, amount })
+    pub fn create_invoice(
+        deps: DepsMut,
+        info: MessageInfo,
+        vendor: String,
+        amount: Uint128,
+        denom: Option<String>,
+        due_date: Timestamp,
+        reference_id: String,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let address = match deps.api.addr_validate(vendor.as_str()) {
+            Ok(value) => value,
+            Err(_) => return Err(ContractError::NotValidAddress { address: vendor }),
+        };
+        let registered_vendor = match VENDORS.may_load(deps.storage, address.clone())? {
+            Some(value) => value,
+            None => return Err(ContractError::VendorNotFound { address: vendor }),
+        };
+        // Invoices are paid out of `total_amount`, so they can only be in the vault denom.
+        let denom = denom.unwrap_or(registered_vendor.default_denom);
+        if denom != current_state.expected_denom {
+            return Err(ContractError::InvalidDenom { denom });
+        }
+        if amount.is_zero() {
+            return Err(ContractError::ZeroInvoiceAmount {});
+        }
+
+        let id = INVOICE_COUNT.may_load(deps.storage)?.unwrap_or_default() + 1;
+        INVOICE_COUNT.save(deps.storage, &id)?;
+        INVOICES.save(
+            deps.storage,
+            id,
+            &Invoice {
+                vendor: address,
+                amount,
+                denom,
+                due_date,
+                reference_id: reference_id.clone(),
+                receipt: None,
+            },
+        )?;
+        UNPAID_INVOICES.save(deps.storage, (due_date.nanos(), id), &true)?;
+        Ok(Response::new()
+            .add_attribute("action", "create_invoice")
+            .add_attribute("invoice_id", id.to_string())
+            .add_attribute("vendor", vendor)
+            .add_attribute("amount", amount)
+            .add_attribute("reference_id", reference_id))
     }
 
-    pub fn get_allowances(deps: Deps) -> StdResult<AllowancesResponse> {
-        let spenders = ALLOWANCES
-            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
-            .map(|item| {
-                let (address, _) = item?;
-                Ok(address)
+    pub fn cancel_invoice(
+        deps: DepsMut,
+        info: MessageInfo,
+        id: u64,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let invoice = match INVOICES.may_load(deps.storage, id)? {
+            Some(value) => value,
+            None => return Err(ContractError::InvoiceNotFound { id }),
+        };
+        if invoice.receipt.is_some() {
+            return Err(ContractError::InvoiceAlreadyPaid { id });
+        }
+        INVOICES.remove(deps.storage, id);
+        UNPAID_INVOICES.remove(deps.storage, (invoice.due_date.nanos(), id));
+        Ok(Response::new()
+            .add_attribute("action", "cancel_invoice")
+            .add_attribute("invoice_id", id.to_string()))
+    }
+
+    /// Pays every unpaid invoice whose due date has been reached, earliest due first.
+    /// Anyone can trigger it so payments can be automated. Invoices that the
+    /// free funds cannot cover yet are left for a later call, and none are
+    /// paid while shares are outstanding.
+    pub fn pay_due_invoices(
+        deps: DepsMut,
+        env: Env,
+        limit: Option<u32>,
+    ) -> Result<Response, ContractError> {
+        super::check_no_shares(deps.storage)?;
+        let mut current_state = STATE.load(deps.storage)?;
+        let limit = limit
+            .unwrap_or(DEFAULT_INVOICE_LIMIT)
+            .min(MAX_INVOICE_LIMIT) as usize;
+
+        // Only paid invoices count against the limit, so skipped ones cannot
+        // hold back the invoices due after them.
+        let due_before = Bound::exclusive((env.block.time.nanos() + 1, 0));
+        let mut cursor: Option<(u64, u64)> = None;
+        let mut response = Response::new().add_attribute("action", "pay_due_invoices");
+        let mut paid_count = 0u32;
+        while (paid_count as usize) < limit {
+            let key = UNPAID_INVOICES
+                .keys(
+                    deps.storage,
+                    cursor.map(Bound::exclusive),
+                    Some(due_before.clone()),
+                    cosmwasm_std::Order::Ascending,
+                )
+                .next()
+                .transpose()?;
+            let (due_date, id) = match key {
+                Some(value) => value,
+                None => break,
+            };
+            cursor = Some((due_date, id));
+            let mut invoice = INVOICES.load(deps.storage, id)?;
+            // Flagged vendors are not paid, the other invoices go on.
+            if super::screen(deps.as_ref(), &[invoice.vendor.as_str()]).is_err() {
+                response = response.add_attribute("skipped_invoice", id.to_string());
+                continue;
+            }
+            // Whatever does not fit the free funds or the outflow limits waits
+            // for a later call.
+            if invoice.amount > super::free_amount(deps.storage, &current_state)?
+                || super::record_outflow(deps.storage, None, invoice.amount, env.block.time)
+                    .is_err()
+            {
+                continue;
+            }
+            current_state.total_amount -= invoice.amount;
+            invoice.receipt = Some(InvoiceReceipt {
+                paid_at: env.block.time,
+                block_height: env.block.height,
+                amount: invoice.amount,
+            });
+            INVOICES.save(deps.storage, id, &invoice)?;
+            UNPAID_INVOICES.remove(deps.storage, (invoice.due_date.nanos(), id));
+            response = response
+                .add_message(BankMsg::Send {
+                    to_address: invoice.vendor.to_string(),
+                    amount: vec![Coin {
+                        denom: invoice.denom,
+                        amount: invoice.amount,
+                    }],
+                })
+                .add_submessages(super::hook_messages(
+                    deps.storage,
+                    VaultHookMsg::Claim {
+                        spender: invoice.vendor.to_string(),
+                        amount: invoice.amount,
+                    },
+                )?);
+            response = response.add_attribute("paid_invoice", id.to_string());
+            paid_count += 1;
+        }
+        STATE.save(deps.storage, &current_state)?;
+
+        Ok(response.add_attribute("paid_count", paid_count.to_string()))
+    }
+
+    pub fn create_grant(
+        deps: DepsMut,
+        info: MessageInfo,
+        grantee: String,
+        arbiter: String,
+        milestones: Vec<MilestoneMsg>,
+    ) -> Result<Response, ContractError> {
+        let mut current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let grantee_address = match deps.api.addr_validate(grantee.as_str()) {
+            Ok(value) => value,
+            Err(_) => return Err(ContractError::NotValidAddress { address: grantee }),
+        };
+        let arbiter_address = match deps.api.addr_validate(arbiter.as_str()) {
+            Ok(value) => value,
+            Err(_) => return Err(ContractError::NotValidAddress { address: arbiter }),
+        };
+        if milestones.is_empty() {
+            return Err(ContractError::NoMilestones {});
+        }
+        super::check_no_shares(deps.storage)?;
+
+        let total = milestones
+            .iter()
+            .try_fold(Uint128::zero(), |total, milestone| {
+                total.checked_add(milestone.amount)
             })
-            .collect::<StdResult<Vec<Addr>>>()?;
-        let amounts = spenders
+            .map_err(StdError::from)?;
+        // Funds promised as allowance cannot be escrowed a second time.
+        let free = super::free_amount(deps.storage, &current_state)?;
+        if total > free {
+            return Err(ContractError::NotEnoughFunds {
+                total_amount: free.u128(),
+            });
+        }
+        // A grantee with a budget is paid from what the budget has left.
+        let budget = match ALLOWANCE_BUDGETS.may_load(deps.storage, grantee_address.clone())? {
+            Some(link) => {
+                let mut budget = BUDGETS.load(deps.storage, link.budget.clone())?;
+                let available = budget
+                    .allocated
+                    .saturating_sub(budget.spent)
+                    .saturating_sub(budget.reserved);
+                if total > available {
+                    return Err(ContractError::BudgetExceeded {
+                        name: link.budget,
+                        available: available.u128(),
+                    });
+                }
+                budget.spent += total;
+                BUDGETS.save(deps.storage, link.budget.clone(), &budget)?;
+                Some(link.budget)
+            }
+            None => None,
+        };
+        current_state.total_amount -= total;
+        STATE.save(deps.storage, &current_state)?;
+        let escrow = ESCROW.may_load(deps.storage)?.unwrap_or_default();
+        ESCROW.save(deps.storage, &(escrow + total))?;
+
+        let id = GRANT_COUNT.may_load(deps.storage)?.unwrap_or_default() + 1;
+        GRANT_COUNT.save(deps.storage, &id)?;
+        GRANTS.save(
+            deps.storage,
+            id,
+            &Grant {
+                grantee: grantee_address,
+                arbiter: arbiter_address,
+                milestones: milestones
+                    .into_iter()
+                    .map(|milestone| Milestone {
+                        amount: milestone.amount,
+                        description: milestone.description,
+                        completed_at: None,
+                    })
+                    .collect(),
+                cancelled: false,
+                budget,
+            },
+        )?;
+        Ok(Response::new()
+            .add_attribute("action", "create_grant")
+            .add_attribute("grant_id", id.to_string())
+            .add_attribute("grantee", grantee)
+            .add_attribute("arbiter", arbiter)
+            .add_attribute("amount", total))
+    }
+
+    pub fn complete_milestone(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        grant_id: u64,
+        milestone: u32,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        let mut grant = match GRANTS.may_load(deps.storage, grant_id)? {
+            Some(value) => value,
+            None => return Err(ContractError::GrantNotFound { id: grant_id }),
+        };
+        if info.sender != grant.arbiter {
+            return Err(ContractError::NotArbiter {
+                arbiter: grant.arbiter.to_string(),
+            });
+        }
+        if grant.cancelled {
+            return Err(ContractError::GrantClosed { id: grant_id });
+        }
+        let next = match grant
+            .milestones
             .iter()
-            .map(|spender| ALLOWANCES.load(deps.storage, spender.clone()))
-            .collect::<StdResult<Vec<Uint128>>>()?;
-        Ok(AllowancesResponse {
-            spenders: spenders.iter().map(|x| x.to_string()).collect(),
-            amounts,
-        })
+            .position(|milestone| milestone.completed_at.is_none())
+        {
+            Some(value) => value as u32,
+            None => return Err(ContractError::GrantClosed { id: grant_id }),
+        };
+        if milestone != next {
+            return Err(ContractError::MilestoneOutOfOrder { expected: next });
+        }
+        super::screen(deps.as_ref(), &[grant.grantee.as_str()])?;
+
+        let tranche = &mut grant.milestones[next as usize];
+        tranche.completed_at = Some(env.block.time);
+        let amount = tranche.amount;
+        super::record_outflow(deps.storage, Some(&grant.grantee), amount, env.block.time)?;
+        GRANTS.save(deps.storage, grant_id, &grant)?;
+        let escrow = ESCROW.load(deps.storage)?;
+        ESCROW.save(deps.storage, &escrow.saturating_sub(amount))?;
+
+        let bank_msg = BankMsg::Send {
+            to_address: grant.grantee.to_string(),
+            amount: vec![Coin {
+                denom: current_state.expected_denom,
+                amount,
+            }],
+        };
+        let hooks = super::hook_messages(
+            deps.storage,
+            VaultHookMsg::Claim {
+                spender: grant.grantee.to_string(),
+                amount,
+            },
+        )?;
+        Ok(Response::new()
+            .add_message(bank_msg)
+            .add_submessages(hooks)
+            .add_attribute("action", "complete_milestone")
+            .add_attribute("grant_id", grant_id.to_string())
+            .add_attribute("milestone", milestone.to_string())
+            .add_attribute("amount", amount))
     }
 
-    pub fn can_deposit(deps: Deps, address: String) -> StdResult<CanDepositResponse> {
-        let address = deps.api.addr_validate(address.as_str())?;
-        let address_amount = DEPOSIT_ADDRESSES.may_load(deps.storage, address)?;
-        match address_amount {
-            Some(_) => Ok(CanDepositResponse { can_deposit: true }),
-            None => Ok(CanDepositResponse { can_deposit: false }),
+    pub fn cancel_grant(
+        deps: DepsMut,
+        info: MessageInfo,
+        grant_id: u64,
+    ) -> Result<Response, ContractError> {
+        let mut current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let mut grant = match GRANTS.may_load(deps.storage, grant_id)? {
+            Some(value) => value,
+            None => return Err(ContractError::GrantNotFound { id: grant_id }),
+        };
+        if grant.cancelled {
+            return Err(ContractError::GrantClosed { id: grant_id });
         }
+
+        let unreleased = grant
+            .milestones
+            .iter()
+            .filter(|milestone| milestone.completed_at.is_none())
+            .map(|milestone| milestone.amount)
+            .sum::<Uint128>();
+        grant.cancelled = true;
+        GRANTS.save(deps.storage, grant_id, &grant)?;
+        if let Some(name) = grant.budget {
+            let mut budget = BUDGETS.load(deps.storage, name.clone())?;
+            budget.spent = budget.spent.saturating_sub(unreleased);
+            BUDGETS.save(deps.storage, name, &budget)?;
+        }
+        let escrow = ESCROW.load(deps.storage)?;
+        ESCROW.save(deps.storage, &escrow.saturating_sub(unreleased))?;
+        current_state.total_amount += unreleased;
+        STATE.save(deps.storage, &current_state)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "cancel_grant")
+            .add_attribute("grant_id", grant_id.to_string())
+            .add_attribute("returned_amount", unreleased))
     }
-    pub fn get_deposit_addresses(deps: Deps) -> StdResult<DepositAddressesResponse> {
-        let addresses = DEPOSIT_ADDRESSES
-            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
-            .map(|item| {
-                let (address, _) = item?;
-                Ok(address)
-            })
-            .collect::<StdResult<Vec<Addr>>>()?;
-        Ok(DepositAddressesResponse {
-            addresses: addresses.iter().map(|x| x.to_string()).collect(),
-        })
+
+    pub fn set_voucher_pubkey(
+        deps: DepsMut,
+        info: MessageInfo,
+        pubkey: Binary,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        VOUCHER_PUBKEY.save(deps.storage, &pubkey)?;
+        Ok(Response::new()
+            .add_attribute("action", "set_voucher_pubkey")
+            .add_attribute("pubkey", pubkey.to_base64()))
+    }
+
+    pub fn redeem_voucher(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        voucher: Voucher,
+        signature: Binary,
+    ) -> Result<Response, ContractError> {
+        super::screen(deps.as_ref(), &[info.sender.as_str()])?;
+        super::check_no_shares(deps.storage)?;
+        let mut current_state = STATE.load(deps.storage)?;
+        let pubkey = match VOUCHER_PUBKEY.may_load(deps.storage)? {
+            Some(value) => value,
+            None => return Err(ContractError::NoVoucherPubkey {}),
+        };
+
+        let hash = Sha256::digest(to_json_vec(&voucher)?);
+        if !deps
+            .api
+            .secp256k1_verify(&hash, &signature, &pubkey)
+            .unwrap_or(false)
+        {
+            return Err(ContractError::InvalidVoucherSignature {});
+        }
+        if voucher.vault != env.contract.address {
+            return Err(ContractError::VoucherWrongVault {
+                vault: voucher.vault,
+            });
+        }
+        if voucher.recipient != info.sender {
+            return Err(ContractError::VoucherWrongRecipient {
+                recipient: voucher.recipient,
+            });
+        }
+        if voucher.expires <= env.block.time {
+            return Err(ContractError::VoucherExpired {
+                nonce: voucher.nonce,
+            });
+        }
+        if USED_NONCES.has(deps.storage, voucher.nonce) {
+            return Err(ContractError::VoucherNonceUsed {
+                nonce: voucher.nonce,
+            });
+        }
+        if voucher.denom != current_state.expected_denom {
+            return Err(ContractError::InvalidDenom {
+                denom: voucher.denom,
+            });
+        }
+        // Vouchers must not spend what is reserved for allowances.
+        let free = super::free_amount(deps.storage, &current_state)?;
+        if voucher.amount > free {
+            return Err(ContractError::NotEnoughFunds {
+                total_amount: free.u128(),
+            });
+        }
+
+        super::record_outflow(
+            deps.storage,
+            Some(&info.sender),
+            voucher.amount,
+            env.block.time,
+        )?;
+        USED_NONCES.save(deps.storage, voucher.nonce, &true)?;
+        current_state.total_amount -= voucher.amount;
+        STATE.save(deps.storage, &current_state)?;
+
+        let bank_msg = BankMsg::Send {
+            to_address: info.sender.to_string(),
+            amount: vec![Coin {
+                denom: voucher.denom,
+                amount: voucher.amount,
+            }],
+        };
+        let hooks = super::hook_messages(
+            deps.storage,
+            VaultHookMsg::Claim {
+                spender: info.sender.to_string(),
+                amount: voucher.amount,
+            },
+        )?;
+        Ok(Response::new()
+            .add_message(bank_msg)
+            .add_submessages(hooks)
+            .add_attribute("action", "redeem_voucher")
+            .add_attribute("address", info.sender.to_string())
+            .add_attribute("nonce", voucher.nonce.to_string())
+            .add_attribute("amount", voucher.amount))
+    }
+
+    fn parse_hash(hash: &str) -> Result<Hash, ContractError> {
+        let mut bytes = [0u8; 32];
+        hex::decode_to_slice(hash, &mut bytes).map_err(|_| ContractError::InvalidMerkleHash {
+            hash: hash.to_string(),
+        })?;
+        Ok(bytes)
+    }
+
+    pub fn register_distribution(
+        deps: DepsMut,
+        info: MessageInfo,
+        merkle_root: String,
+        total_amount: Uint128,
+        expiration: Option<Timestamp>,
+    ) -> Result<Response, ContractError> {
+        let mut current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        parse_hash(&merkle_root)?;
+        super::check_no_shares(deps.storage)?;
+        // Funds promised as allowance cannot be escrowed a second time.
+        let free = super::free_amount(deps.storage, &current_state)?;
+        if total_amount > free {
+            return Err(ContractError::NotEnoughFunds {
+                total_amount: free.u128(),
+            });
+        }
+        current_state.total_amount -= total_amount;
+        STATE.save(deps.storage, &current_state)?;
+        let escrow = ESCROW.may_load(deps.storage)?.unwrap_or_default();
+        ESCROW.save(deps.storage, &(escrow + total_amount))?;
+
+        let round = DISTRIBUTION_COUNT
+            .may_load(deps.storage)?
+            .unwrap_or_default()
+            + 1;
+        DISTRIBUTION_COUNT.save(deps.storage, &round)?;
+        DISTRIBUTIONS.save(
+            deps.storage,
+            round,
+            &Distribution {
+                merkle_root: merkle_root.clone(),
+                total_amount,
+                claimed_amount: Uint128::zero(),
+                expiration,
+                clawed_back: false,
+            },
+        )?;
+        Ok(Response::new()
+            .add_attribute("action", "register_distribution")
+            .add_attribute("round", round.to_string())
+            .add_attribute("merkle_root", merkle_root)
+            .add_attribute("amount", total_amount))
+    }
+
+    pub fn claim_distribution(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        round: u64,
+        amount: Uint128,
+        proof: Vec<String>,
+    ) -> Result<Response, ContractError> {
+        super::screen(deps.as_ref(), &[info.sender.as_str()])?;
+        let current_state = STATE.load(deps.storage)?;
+        let mut distribution = match DISTRIBUTIONS.may_load(deps.storage, round)? {
+            Some(value) => value,
+            None => return Err(ContractError::DistributionNotFound { round }),
+        };
+        if distribution.clawed_back
+            || distribution
+                .expiration
+                .is_some_and(|expiration| expiration <= env.block.time)
+        {
+            return Err(ContractError::DistributionExpired { round });
+        }
+        if DISTRIBUTION_CLAIMS.has(deps.storage, (round, info.sender.clone())) {
+            return Err(ContractError::DistributionAlreadyClaimed {
+                address: info.sender.to_string(),
+            });
+        }
+
+        let root = parse_hash(&distribution.merkle_root)?;
+        let proof = proof
+            .iter()
+            .map(|hash| parse_hash(hash))
+            .collect::<Result<Vec<Hash>, ContractError>>()?;
+        if !merkle::verify_proof(
+            &root,
+            merkle::leaf_hash(info.sender.as_str(), amount),
+            &proof,
+        ) {
+            return Err(ContractError::InvalidMerkleProof {});
+        }
+
+        distribution.claimed_amount += amount;
+        if distribution.claimed_amount > distribution.total_amount {
+            return Err(ContractError::DistributionBudgetExceeded { round });
+        }
+        super::record_outflow(deps.storage, Some(&info.sender), amount, env.block.time)?;
+        DISTRIBUTIONS.save(deps.storage, round, &distribution)?;
+        DISTRIBUTION_CLAIMS.save(deps.storage, (round, info.sender.clone()), &true)?;
+        let escrow = ESCROW.load(deps.storage)?;
+        ESCROW.save(deps.storage, &escrow.saturating_sub(amount))?;
+
+        let bank_msg = BankMsg::Send {
+            to_address: info.sender.to_string(),
+            amount: vec![Coin {
+                denom: current_state.expected_denom,
+                amount,
+            }],
+        };
+        let hooks = super::hook_messages(
+            deps.storage,
+            VaultHookMsg::Claim {
+                spender: info.sender.to_string(),
+                amount,
+            },
+        )?;
+        Ok(Response::new()
+            .add_message(bank_msg)
+            .add_submessages(hooks)
+            .add_attribute("action", "claim_distribution")
+            .add_attribute("round", round.to_string())
+            .add_attribute("address", info.sender.to_string())
+            .add_attribute("amount", amount))
+    }
+
+    pub fn clawback_distribution(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        round: u64,
+    ) -> Result<Response, ContractError> {
+        let mut current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let mut distribution = match DISTRIBUTIONS.may_load(deps.storage, round)? {
+            Some(value) => value,
+            None => return Err(ContractError::DistributionNotFound { round }),
+        };
+        if distribution.clawed_back
+            || distribution
+                .expiration
+                .is_none_or(|expiration| expiration > env.block.time)
+        {
+            return Err(ContractError::DistributionNotExpired { round });
+        }
+
+        let unclaimed = distribution.total_amount - distribution.claimed_amount;
+        distribution.clawed_back = true;
+        DISTRIBUTIONS.save(deps.storage, round, &distribution)?;
+        let escrow = ESCROW.load(deps.storage)?;
+        ESCROW.save(deps.storage, &escrow.saturating_sub(unclaimed))?;
+        current_state.total_amount += unclaimed;
+        STATE.save(deps.storage, &current_state)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "clawback_distribution")
+            .add_attribute("round", round.to_string())
+            .add_attribute("returned_amount", unclaimed))
+    }
+
+    pub fn enable_share_mode(
+        deps: DepsMut,
+        info: MessageInfo,
+        token: String,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        if SHARE_CONFIG.exists(deps.storage) {
+            return Err(ContractError::ShareModeEnabled {});
+        }
+        // Funds deposited before share mode would otherwise go to the first share holders.
+        if !current_state.total_amount.is_zero() {
+            return Err(ContractError::VaultNotEmpty {
+                total_amount: current_state.total_amount.u128(),
+            });
+        }
+        let address = match deps.api.addr_validate(token.as_str()) {
+            Ok(value) => value,
+            Err(_) => return Err(ContractError::NotValidAddress { address: token }),
+        };
+        SHARE_CONFIG.save(
+            deps.storage,
+            &ShareConfig {
+                token: address,
+                total_supply: Uint128::zero(),
+            },
+        )?;
+        Ok(Response::new()
+            .add_attribute("action", "enable_share_mode")
+            .add_attribute("token", token))
+    }
+
+    pub fn receive(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        wrapper: Cw20ReceiveMsg,
+    ) -> Result<Response, ContractError> {
+        match from_json(&wrapper.msg)? {
+            ReceiveMsg::RedeemShares {} => {
+                redeem_shares(deps, env, info, wrapper.sender, wrapper.amount)
+            }
+        }
+    }
+
+    fn redeem_shares(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        owner: String,
+        shares: Uint128,
+    ) -> Result<Response, ContractError> {
+        let mut current_state = STATE.load(deps.storage)?;
+        let mut config = match SHARE_CONFIG.may_load(deps.storage)? {
+            Some(value) => value,
+            None => return Err(ContractError::ShareModeDisabled {}),
+        };
+        if info.sender != config.token {
+            return Err(ContractError::UnauthorizedShareToken {
+                token: config.token.to_string(),
+            });
+        }
+        let owner = deps.api.addr_validate(owner.as_str())?;
+        super::screen(deps.as_ref(), &[owner.as_str()])?;
+
+        let amount = super::amount_for_shares(
+            shares,
+            super::share_assets(deps.storage, &current_state)?,
+            config.total_supply,
+        );
+        if amount.is_zero() {
+            return Err(ContractError::ZeroShares {});
+        }
+        // Staked funds are paid out once they are unbonded.
+        let free = super::free_amount(deps.storage, &current_state)?;
+        if amount > free {
+            return Err(ContractError::NotEnoughFunds {
+                total_amount: free.u128(),
+            });
+        }
+        super::record_outflow(deps.storage, None, amount, env.block.time)?;
+        config.total_supply = config
+            .total_supply
+            .checked_sub(shares)
+            .map_err(StdError::from)?;
+        SHARE_CONFIG.save(deps.storage, &config)?;
+        current_state.total_amount -= amount;
+        STATE.save(deps.storage, &current_state)?;
+
+        let burn_msg = WasmMsg::Execute {
+            contract_addr: config.token.to_string(),
+            msg: to_json_binary(&Cw20ExecuteMsg::Burn { amount: shares })?,
+            funds: vec![],
+        };
+        let payout = vec![Coin {
+            denom: current_state.expected_denom,
+            amount,
+        }];
+        let bank_msg = BankMsg::Send {
+            to_address: owner.to_string(),
+            amount: payout.clone(),
+        };
+        let hooks = super::hook_messages(
+            deps.storage,
+            VaultHookMsg::Withdraw {
+                owner: owner.to_string(),
+                amount: payout,
+            },
+        )?;
+        Ok(Response::new()
+            .add_message(burn_msg)
+            .add_message(bank_msg)
+            .add_submessages(hooks)
+            .add_attribute("action", "redeem_shares")
+            .add_attribute("address", owner.to_string())
+            .add_attribute("shares", shares)
+            .add_attribute("amount", amount))
+    }
+
+    pub fn set_staking_config(
+        deps: DepsMut,
+        info: MessageInfo,
+        max_stake_ratio: Decimal,
+        unbonding_period: u64,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        if max_stake_ratio > Decimal::one() {
+            return Err(ContractError::InvalidStakeRatio {});
+        }
+        let bonded_denom = deps.querier.query_bonded_denom()?;
+        if bonded_denom != current_state.expected_denom {
+            return Err(ContractError::InvalidDenom {
+                denom: bonded_denom,
+            });
+        }
+        STAKING_CONFIG.save(
+            deps.storage,
+            &StakingConfig {
+                max_stake_ratio,
+                unbonding_period,
+            },
+        )?;
+        Ok(Response::new()
+            .add_attribute("action", "set_staking_config")
+            .add_attribute("max_stake_ratio", max_stake_ratio.to_string())
+            .add_attribute("unbonding_period", unbonding_period.to_string()))
+    }
+
+    /// Wraps a staking message so that rewards paid out by the chain while it
+    /// runs are added to the pool. `outflow` is what the message itself sends away.
+    fn track_rewards(
+        deps: &mut DepsMut,
+        env: &Env,
+        msg: CosmosMsg,
+        outflow: Uint128,
+    ) -> StdResult<SubMsg> {
+        let denom = STATE.load(deps.storage)?.expected_denom;
+        let balance = deps
+            .querier
+            .query_balance(&env.contract.address, denom)?
+            .amount;
+        STAKING_BALANCE.save(deps.storage, &balance.saturating_sub(outflow))?;
+        Ok(SubMsg::reply_on_success(msg, super::STAKING_REPLY_ID))
+    }
+
+    fn total_staked(storage: &dyn Storage) -> StdResult<Uint128> {
+        DELEGATIONS
+            .range(storage, None, None, cosmwasm_std::Order::Ascending)
+            .try_fold(Uint128::zero(), |total, item| {
+                let (_, amount) = item?;
+                Ok(total + amount)
+            })
+    }
+
+    pub fn delegate(
+        mut deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        validator: String,
+        amount: Uint128,
+    ) -> Result<Response, ContractError> {
+        let mut current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let config = match STAKING_CONFIG.may_load(deps.storage)? {
+            Some(value) => value,
+            None => return Err(ContractError::StakingNotConfigured {}),
+        };
+
+        // Allowances must stay liquid and the stake must stay under the configured ratio.
+        let staked = total_staked(deps.storage)?;
+        let free_amount = super::free_amount(deps.storage, &current_state)?;
+        let max_stake = (current_state.total_amount + staked) * config.max_stake_ratio;
+        let max_amount = free_amount.min(max_stake.saturating_sub(staked));
+        if amount > max_amount {
+            return Err(ContractError::OverStake {
+                max_amount: max_amount.u128(),
+            });
+        }
+
+        current_state.total_amount -= amount;
+        STATE.save(deps.storage, &current_state)?;
+        let delegated = DELEGATIONS
+            .may_load(deps.storage, validator.clone())?
+            .unwrap_or_default();
+        DELEGATIONS.save(deps.storage, validator.clone(), &(delegated + amount))?;
+
+        let msg = StakingMsg::Delegate {
+            validator: validator.clone(),
+            amount: Coin {
+                denom: current_state.expected_denom,
+                amount,
+            },
+        };
+        let sub_msg = track_rewards(&mut deps, &env, msg.into(), amount)?;
+        Ok(Response::new()
+            .add_submessage(sub_msg)
+            .add_attribute("action", "delegate")
+            .add_attribute("validator", validator)
+            .add_attribute("amount", amount))
+    }
+
+    pub fn undelegate(
+        mut deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        validator: String,
+        amount: Uint128,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let config = match STAKING_CONFIG.may_load(deps.storage)? {
+            Some(value) => value,
+            None => return Err(ContractError::StakingNotConfigured {}),
+        };
+        let delegated = DELEGATIONS
+            .may_load(deps.storage, validator.clone())?
+            .unwrap_or_default();
+        if amount > delegated {
+            return Err(ContractError::NotEnoughDelegation { validator });
+        }
+        if amount == delegated {
+            DELEGATIONS.remove(deps.storage, validator.clone());
+        } else {
+            DELEGATIONS.save(deps.storage, validator.clone(), &(delegated - amount))?;
+        }
+
+        let id = UNBONDING_COUNT.may_load(deps.storage)?.unwrap_or_default() + 1;
+        UNBONDING_COUNT.save(deps.storage, &id)?;
+        let completes_at = env.block.time.plus_seconds(config.unbonding_period);
+        UNBONDINGS.save(
+            deps.storage,
+            id,
+            &Unbonding {
+                validator: validator.clone(),
+                amount,
+                completes_at,
+            },
+        )?;
+
+        let msg = StakingMsg::Undelegate {
+            validator: validator.clone(),
+            amount: Coin {
+                denom: current_state.expected_denom,
+                amount,
+            },
+        };
+        let sub_msg = track_rewards(&mut deps, &env, msg.into(), Uint128::zero())?;
+        Ok(Response::new()
+            .add_submessage(sub_msg)
+            .add_attribute("action", "undelegate")
+            .add_attribute("validator", validator)
+            .add_attribute("amount", amount)
+            .add_attribute("completes_at", completes_at.to_string()))
+    }
+
+    pub fn withdraw_rewards(
+        mut deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let validators = DELEGATIONS
+            .keys(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .collect::<StdResult<Vec<String>>>()?;
+        let mut response = Response::new().add_attribute("action", "withdraw_rewards");
+        for validator in validators {
+            let msg = DistributionMsg::WithdrawDelegatorReward { validator };
+            response = response.add_submessage(track_rewards(
+                &mut deps,
+                &env,
+                msg.into(),
+                Uint128::zero(),
+            )?);
+        }
+        Ok(response)
+    }
+
+    pub fn claim_unbonded(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+    ) -> Result<Response, ContractError> {
+        let mut current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let matured = UNBONDINGS
+            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .filter(|item| match item {
+                Ok((_, unbonding)) => unbonding.completes_at <= env.block.time,
+                Err(_) => true,
+            })
+            .collect::<StdResult<Vec<(u64, Unbonding)>>>()?;
+
+        // The chain decides when unbonded tokens arrive, so only what is
+        // actually in the bank beyond the accounted funds is credited. The
+        // rest waits for a later call.
+        let balance = deps
+            .querier
+            .query_balance(&env.contract.address, &current_state.expected_denom)?
+            .amount;
+        let mut arrived = super::arrived_unbonded(deps.storage, &current_state, balance)?;
+        let mut claimed = Uint128::zero();
+        for (id, unbonding) in matured {
+            if unbonding.amount > arrived {
+                break;
+            }
+            arrived -= unbonding.amount;
+            claimed += unbonding.amount;
+            UNBONDINGS.remove(deps.storage, id);
+        }
+        current_state.total_amount += claimed;
+        STATE.save(deps.storage, &current_state)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "claim_unbonded")
+            .add_attribute("amount", claimed))
+    }
+
+    pub fn set_payout_preference(
+        deps: DepsMut,
+        info: MessageInfo,
+        channel_id: String,
+        remote_address: String,
+        timeout_seconds: u64,
+    ) -> Result<Response, ContractError> {
+        if !ALLOWANCES.has(deps.storage, info.sender.clone())
+            && !PEGGED_ALLOWANCES.has(deps.storage, info.sender.clone())
+        {
+            return Err(ContractError::NoAllowance {
+                address: info.sender.to_string(),
+            });
+        }
+        PAYOUT_PREFERENCES.save(
+            deps.storage,
+            info.sender.clone(),
+            &PayoutPreference {
+                channel_id: channel_id.clone(),
+                remote_address: remote_address.clone(),
+                timeout_seconds,
+            },
+        )?;
+        Ok(Response::new()
+            .add_attribute("action", "set_payout_preference")
+            .add_attribute("spender", info.sender.to_string())
+            .add_attribute("channel_id", channel_id)
+            .add_attribute("remote_address", remote_address))
+    }
+
+    pub fn remove_payout_preference(
+        deps: DepsMut,
+        info: MessageInfo,
+    ) -> Result<Response, ContractError> {
+        PAYOUT_PREFERENCES.remove(deps.storage, info.sender.clone());
+        Ok(Response::new()
+            .add_attribute("action", "remove_payout_preference")
+            .add_attribute("spender", info.sender.to_string()))
+    }
+
+    pub fn add_trusted_vault(
+        deps: DepsMut,
+        info: MessageInfo,
+        port_id: String,
+        connection_id: String,
+        remote_address: String,
+        transfer_channel_id: String,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        TRUSTED_VAULTS.save(
+            deps.storage,
+            port_id.clone(),
+            &TrustedVault {
+                connection_id: connection_id.clone(),
+                remote_address: remote_address.clone(),
+                transfer_channel_id,
+            },
+        )?;
+        Ok(Response::new()
+            .add_attribute("action", "add_trusted_vault")
+            .add_attribute("port_id", port_id)
+            .add_attribute("connection_id", connection_id)
+            .add_attribute("remote_address", remote_address))
+    }
+
+    pub fn remove_trusted_vault(
+        deps: DepsMut,
+        info: MessageInfo,
+        port_id: String,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        TRUSTED_VAULTS.remove(deps.storage, port_id.clone());
+        Ok(Response::new()
+            .add_attribute("action", "remove_trusted_vault")
+            .add_attribute("port_id", port_id))
+    }
+
+    fn vault_packet(
+        deps: &DepsMut,
+        env: &Env,
+        channel_id: String,
+        packet: &VaultPacket,
+    ) -> Result<IbcMsg, ContractError> {
+        if !VAULT_CHANNELS.has(deps.storage, channel_id.clone()) {
+            return Err(ContractError::UnknownVaultChannel { channel_id });
+        }
+        Ok(IbcMsg::SendPacket {
+            channel_id,
+            data: to_json_binary(packet)?,
+            timeout: IbcTimeout::with_timestamp(
+                env.block.time.plus_seconds(ibc::PACKET_TIMEOUT_SECONDS),
+            ),
+        })
+    }
+
+    pub fn request_funds(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        channel_id: String,
+        amount: Uint128,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let msg = vault_packet(
+            &deps,
+            &env,
+            channel_id.clone(),
+            &VaultPacket::FundRequest { amount },
+        )?;
+        Ok(Response::new()
+            .add_message(msg)
+            .add_attribute("action", "request_funds")
+            .add_attribute("channel_id", channel_id)
+            .add_attribute("amount", amount))
+    }
+
+    /// Takes the amount out of the pool and announces it to the remote vault.
+    /// The tokens themselves are sent once the announcement is acknowledged.
+    pub fn send_funds(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        channel_id: String,
+        amount: Uint128,
+    ) -> Result<Response, ContractError> {
+        let mut current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        // Funds promised as allowance or owned by share holders stay here.
+        let free = super::free_amount(deps.storage, &current_state)?;
+        if amount > free {
+            return Err(ContractError::NotEnoughFunds {
+                total_amount: free.u128(),
+            });
+        }
+        super::check_no_shares(deps.storage)?;
+        let msg = vault_packet(
+            &deps,
+            &env,
+            channel_id.clone(),
+            &VaultPacket::FundTransfer { amount },
+        )?;
+        super::record_outflow(deps.storage, None, amount, env.block.time)?;
+        current_state.total_amount -= amount;
+        STATE.save(deps.storage, &current_state)?;
+        Ok(Response::new()
+            .add_message(msg)
+            .add_attribute("action", "send_funds")
+            .add_attribute("channel_id", channel_id)
+            .add_attribute("amount", amount))
+    }
+
+    pub fn report_balance(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        channel_id: String,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let msg = vault_packet(
+            &deps,
+            &env,
+            channel_id.clone(),
+            &VaultPacket::BalanceReport {
+                total_amount: current_state.total_amount,
+            },
+        )?;
+        Ok(Response::new()
+            .add_message(msg)
+            .add_attribute("action", "report_balance")
+            .add_attribute("channel_id", channel_id)
+            .add_attribute("total_amount", current_state.total_amount))
+    }
+
+    /// Credits the tokens of a `FundTransfer` once the ICS20 transfer arrives.
+    /// The remote vault's transfer runs this through its memo, so a failure
+    /// here refunds the tokens to it.
+    pub fn receive_vault_funds(
+        deps: DepsMut,
+        info: MessageInfo,
+        channel_id: String,
+    ) -> Result<Response, ContractError> {
+        let mut current_state = STATE.load(deps.storage)?;
+        let mut channel = match VAULT_CHANNELS.may_load(deps.storage, channel_id.clone())? {
+            Some(value) => value,
+            None => return Err(ContractError::UnknownVaultChannel { channel_id }),
+        };
+        // The voucher the remote vault's tokens arrive as must be the payout denom.
+        let amount = match info.funds.as_slice() {
+            [coin] if coin.denom == current_state.expected_denom && !coin.amount.is_zero() => {
+                coin.amount
+            }
+            _ => {
+                return Err(ContractError::InvalidDenom {
+                    denom: current_state.expected_denom,
+                })
+            }
+        };
+        if amount > channel.incoming {
+            return Err(ContractError::UnannouncedVaultFunds {
+                incoming: channel.incoming.u128(),
+            });
+        }
+        channel.incoming -= amount;
+        channel.received += amount;
+        VAULT_CHANNELS.save(deps.storage, channel_id.clone(), &channel)?;
+        current_state.total_amount += amount;
+        STATE.save(deps.storage, &current_state)?;
+        Ok(Response::new()
+            .add_attribute("action", "receive_vault_funds")
+            .add_attribute("channel_id", channel_id)
+            .add_attribute("amount", amount))
+    }
+
+    pub fn set_swap_config(
+        deps: DepsMut,
+        info: MessageInfo,
+        router: String,
+        auto_swap: bool,
+        max_slippage: Decimal,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        if max_slippage > Decimal::one() {
+            return Err(ContractError::InvalidSlippage {});
+        }
+        let address = match deps.api.addr_validate(router.as_str()) {
+            Ok(value) => value,
+            Err(_) => return Err(ContractError::NotValidAddress { address: router }),
+        };
+        SWAP_CONFIG.save(
+            deps.storage,
+            &SwapConfig {
+                router: address,
+                auto_swap,
+                max_slippage,
+            },
+        )?;
+        Ok(Response::new()
+            .add_attribute("action", "set_swap_config")
+            .add_attribute("router", router)
+            .add_attribute("auto_swap", auto_swap.to_string())
+            .add_attribute("max_slippage", max_slippage.to_string()))
+    }
+
+    /// Converts an allowance in the quote currency into tokens at the oracle price.
+    fn pegged_amount(
+        deps: DepsMut,
+        env: &Env,
+        state: &State,
+        quote_amount: Uint128,
+    ) -> Result<Uint128, ContractError> {
+        let config = match ORACLE_CONFIG.may_load(deps.storage)? {
+            Some(value) => value,
+            None => return Err(ContractError::OracleNotConfigured {}),
+        };
+        let response: PriceResponse = deps.querier.query_wasm_smart(
+            config.oracle.to_string(),
+            &OracleQueryMsg::Price {
+                denom: state.expected_denom.clone(),
+                quote: config.quote,
+            },
+        )?;
+        if response.price.is_zero() {
+            return Err(ContractError::InvalidPrice {});
+        }
+        if response.updated_at.plus_seconds(config.max_staleness) < env.block.time {
+            return Err(ContractError::StalePrice {
+                updated_at: response.updated_at.seconds(),
+            });
+        }
+        // An old reference says nothing about today's price, so it only
+        // bounds the oracle for a limited time.
+        let reference = LAST_PRICE.may_load(deps.storage)?.filter(|reference| {
+            reference.time.plus_seconds(super::PRICE_REFERENCE_SECONDS) > env.block.time
+        });
+        if let Some(reference) = reference {
+            let difference = response.price.abs_diff(reference.price);
+            if difference > reference.price * config.max_deviation {
+                return Err(ContractError::PriceDeviation {
+                    price: response.price.to_string(),
+                    last_price: reference.price.to_string(),
+                });
+            }
+        }
+        LAST_PRICE.save(
+            deps.storage,
+            &ReferencePrice {
+                price: response.price,
+                time: env.block.time,
+            },
+        )?;
+
+        let amount = quote_amount
+            .checked_div_floor(response.price)
+            .map_err(|_| ContractError::InvalidPrice {})?;
+        Ok(amount.min(config.max_tokens_per_claim))
+    }
+
+    pub fn set_oracle_config(
+        deps: DepsMut,
+        info: MessageInfo,
+        oracle: String,
+        quote: String,
+        max_staleness: u64,
+        max_deviation: Decimal,
+        max_tokens_per_claim: Uint128,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let address = match deps.api.addr_validate(oracle.as_str()) {
+            Ok(value) => value,
+            Err(_) => return Err(ContractError::NotValidAddress { address: oracle }),
+        };
+        ORACLE_CONFIG.save(
+            deps.storage,
+            &OracleConfig {
+                oracle: address,
+                quote: quote.clone(),
+                max_staleness,
+                max_deviation,
+                max_tokens_per_claim,
+            },
+        )?;
+        // A new oracle starts without a reference price.
+        LAST_PRICE.remove(deps.storage);
+        super::refresh_pegged_reserves(deps.storage)?;
+        Ok(Response::new()
+            .add_attribute("action", "set_oracle_config")
+            .add_attribute("oracle", oracle)
+            .add_attribute("quote", quote)
+            .add_attribute("max_staleness", max_staleness.to_string())
+            .add_attribute("max_deviation", max_deviation.to_string())
+            .add_attribute("max_tokens_per_claim", max_tokens_per_claim))
+    }
+
+    pub fn add_pegged_allowance(
+        deps: DepsMut,
+        info: MessageInfo,
+        spender: String,
+        quote_amount: Uint128,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let address = match deps.api.addr_validate(spender.as_str()) {
+            Ok(value) => value,
+            Err(_) => return Err(ContractError::NotValidAddress { address: spender }),
+        };
+        // The tokens a pegged allowance takes move with the price.
+        if SHARE_CONFIG.exists(deps.storage) {
+            return Err(ContractError::ShareModeEnabled {});
+        }
+        PEGGED_ALLOWANCES.save(deps.storage, address.clone(), &quote_amount)?;
+        super::refresh_pegged_reserve(deps.storage, &address)?;
+        super::check_budget(deps.storage, &address)?;
+        let hooks = super::hook_messages(
+            deps.storage,
+            VaultHookMsg::AllowanceChanged {
+                spender: spender.clone(),
+                amount: Some(quote_amount),
+            },
+        )?;
+        Ok(Response::new()
+            .add_submessages(hooks)
+            .add_attribute("action", "add_pegged_allowance")
+            .add_attribute("spender", spender)
+            .add_attribute("quote_amount", quote_amount))
+    }
+
+    pub fn remove_pegged_allowance(
+        deps: DepsMut,
+        info: MessageInfo,
+        spender: String,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let address = match deps.api.addr_validate(spender.as_str()) {
+            Ok(value) => value,
+            Err(_) => return Err(ContractError::NotValidAddress { address: spender }),
+        };
+        PEGGED_ALLOWANCES.remove(deps.storage, address.clone());
+        super::refresh_pegged_reserve(deps.storage, &address)?;
+        super::release_budget(deps.storage, &address)?;
+        let hooks = super::hook_messages(
+            deps.storage,
+            VaultHookMsg::AllowanceChanged {
+                spender: spender.clone(),
+                amount: None,
+            },
+        )?;
+        Ok(Response::new()
+            .add_submessages(hooks)
+            .add_attribute("action", "remove_pegged_allowance")
+            .add_attribute("spender", spender))
+    }
+
+    pub fn add_hook(
+        deps: DepsMut,
+        info: MessageInfo,
+        address: String,
+        blocking: bool,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let hook = match deps.api.addr_validate(address.as_str()) {
+            Ok(value) => value,
+            Err(_) => return Err(ContractError::NotValidAddress { address }),
+        };
+        HOOKS.save(deps.storage, hook, &blocking)?;
+        Ok(Response::new()
+            .add_attribute("action", "add_hook")
+            .add_attribute("address", address)
+            .add_attribute("blocking", blocking.to_string()))
+    }
+
+    pub fn remove_hook(
+        deps: DepsMut,
+        info: MessageInfo,
+        address: String,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let hook = match deps.api.addr_validate(address.as_str()) {
+            Ok(value) => value,
+            Err(_) => return Err(ContractError::NotValidAddress { address }),
+        };
+        if !HOOKS.has(deps.storage, hook.clone()) {
+            return Err(ContractError::HookNotFound { address });
+        }
+        HOOKS.remove(deps.storage, hook);
+        Ok(Response::new()
+            .add_attribute("action", "remove_hook")
+            .add_attribute("address", address))
+    }
+
+    fn load_dead_man_switch(deps: Deps) -> Result<DeadManSwitch, ContractError> {
+        match DEAD_MAN_SWITCH.may_load(deps.storage)? {
+            Some(value) => Ok(value),
+            None => Err(ContractError::DeadManSwitchNotConfigured {}),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_dead_man_switch(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        interval: u64,
+        guardians: Vec<String>,
+        threshold: u32,
+        recovery_period: u64,
+        beneficiary: String,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let guardians = guardians
+            .into_iter()
+            .map(|guardian| match deps.api.addr_validate(guardian.as_str()) {
+                Ok(value) => Ok(value),
+                Err(_) => Err(ContractError::NotValidAddress { address: guardian }),
+            })
+            .collect::<Result<Vec<Addr>, ContractError>>()?;
+        if threshold == 0 || threshold as usize > guardians.len() {
+            return Err(ContractError::InvalidGuardianThreshold {});
+        }
+        let beneficiary_address = match deps.api.addr_validate(beneficiary.as_str()) {
+            Ok(value) => value,
+            Err(_) => {
+                return Err(ContractError::NotValidAddress {
+                    address: beneficiary,
+                })
+            }
+        };
+        DEAD_MAN_SWITCH.save(
+            deps.storage,
+            &DeadManSwitch {
+                interval,
+                guardians,
+                threshold,
+                recovery_period,
+                beneficiary: beneficiary_address,
+                last_heartbeat: env.block.time,
+                wind_down: false,
+            },
+        )?;
+        RECOVERY_VOTES.clear(deps.storage);
+        Ok(Response::new()
+            .add_attribute("action", "set_dead_man_switch")
+            .add_attribute("interval", interval.to_string())
+            .add_attribute("threshold", threshold.to_string())
+            .add_attribute("beneficiary", beneficiary))
+    }
+
+    pub fn heartbeat(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let mut switch = load_dead_man_switch(deps.as_ref())?;
+        switch.last_heartbeat = env.block.time;
+        DEAD_MAN_SWITCH.save(deps.storage, &switch)?;
+        // The owner is back, so pending recovery votes no longer apply.
+        RECOVERY_VOTES.clear(deps.storage);
+        Ok(Response::new()
+            .add_attribute("action", "heartbeat")
+            .add_attribute("time", env.block.time.seconds().to_string()))
+    }
+
+    pub fn vote_recovery(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        new_owner: String,
+    ) -> Result<Response, ContractError> {
+        let switch = load_dead_man_switch(deps.as_ref())?;
+        if !switch.guardians.contains(&info.sender) {
+            return Err(ContractError::NotGuardian {});
+        }
+        let deadline = switch.last_heartbeat.plus_seconds(switch.interval);
+        if env.block.time <= deadline {
+            return Err(ContractError::SwitchNotLapsed {
+                seconds_remaining: deadline.seconds() - env.block.time.seconds(),
+            });
+        }
+        let new_owner_address = match deps.api.addr_validate(new_owner.as_str()) {
+            Ok(value) => value,
+            Err(_) => return Err(ContractError::NotValidAddress { address: new_owner }),
+        };
+        RECOVERY_VOTES.save(deps.storage, info.sender.clone(), &new_owner_address)?;
+
+        let votes = RECOVERY_VOTES
+            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .filter(|item| {
+                item.as_ref()
+                    .is_ok_and(|(_, candidate)| *candidate == new_owner_address)
+            })
+            .count() as u32;
+        let response = Response::new()
+            .add_attribute("action", "vote_recovery")
+            .add_attribute("guardian", info.sender.to_string())
+            .add_attribute("new_owner", new_owner)
+            .add_attribute("votes", votes.to_string());
+        if votes < switch.threshold {
+            return Ok(response);
+        }
+
+        let mut current_state = STATE.load(deps.storage)?;
+        super::change_owner(
+            deps.storage,
+            &mut current_state,
+            new_owner_address,
+            env.block.time,
+        )?;
+        Ok(response.add_attribute("recovered", "true"))
+    }
+
+    /// Anyone can start the wind-down once the guardians had their chance.
+    pub fn start_wind_down(deps: DepsMut, env: Env) -> Result<Response, ContractError> {
+        let mut switch = load_dead_man_switch(deps.as_ref())?;
+        let deadline = switch.last_heartbeat.plus_seconds(switch.interval);
+        if env.block.time <= deadline {
+            return Err(ContractError::SwitchNotLapsed {
+                seconds_remaining: deadline.seconds() - env.block.time.seconds(),
+            });
+        }
+        let recovery_end = deadline.plus_seconds(switch.recovery_period);
+        if env.block.time <= recovery_end {
+            return Err(ContractError::RecoveryPeriodActive {
+                seconds_remaining: recovery_end.seconds() - env.block.time.seconds(),
+            });
+        }
+        switch.wind_down = true;
+        DEAD_MAN_SWITCH.save(deps.storage, &switch)?;
+        Ok(Response::new().add_attribute("action", "start_wind_down"))
+    }
+
+    /// Sends the part of the pool that is not promised as allowance to the
+    /// beneficiary, once the share holders have redeemed.
+    pub fn release_to_beneficiary(deps: DepsMut, env: Env) -> Result<Response, ContractError> {
+        let switch = load_dead_man_switch(deps.as_ref())?;
+        if !switch.wind_down {
+            return Err(ContractError::NotWindingDown {});
+        }
+        super::check_no_shares(deps.storage)?;
+        let mut current_state = STATE.load(deps.storage)?;
+        // Released in parts when the daily outflow limit is lower.
+        let mut amount = super::free_amount(deps.storage, &current_state)?;
+        if let Some(remaining) = super::daily_outflow_remaining(deps.storage, env.block.time)? {
+            amount = amount.min(remaining);
+        }
+        super::record_outflow(deps.storage, None, amount, env.block.time)?;
+        current_state.total_amount -= amount;
+        STATE.save(deps.storage, &current_state)?;
+
+        let mut response = Response::new()
+            .add_attribute("action", "release_to_beneficiary")
+            .add_attribute("beneficiary", switch.beneficiary.to_string())
+            .add_attribute("amount", amount);
+        if !amount.is_zero() {
+            response = response.add_message(BankMsg::Send {
+                to_address: switch.beneficiary.to_string(),
+                amount: coins(amount.u128(), current_state.expected_denom),
+            });
+        }
+        Ok(response)
+    }
+
+    /// Finalizes a closed vault once every allowance and refund is paid.
+    pub fn finalize_if_settled(
+        storage: &mut dyn Storage,
+        closure: &mut Closure,
+    ) -> Result<(), ContractError> {
+        // A payout in flight over IBC may still come back to the pool.
+        let unpaid = !ALLOWANCES.is_empty(storage)
+            || OUTGOING_TRANSFER.exists(storage)
+            || !PENDING_TRANSFERS.is_empty(storage);
+        closure.finalized = !unpaid && closure.unpaid_refunds == 0;
+        CLOSURE.save(storage, closure)?;
+        Ok(())
+    }
+
+    /// Whether a spender could never claim the allowance in a closed vault.
+    fn unpayable_allowance(
+        deps: Deps,
+        spender: &Addr,
+        amount: Uint128,
+        available: Uint128,
+    ) -> Result<bool, ContractError> {
+        if amount.is_zero() || amount > available {
+            return Ok(true);
+        }
+        if FROZEN_ALLOWANCES.has(deps.storage, spender.clone())
+            || super::screen(deps, &[spender.as_str()]).is_err()
+        {
+            return Ok(true);
+        }
+        Ok(
+            match ALLOWANCE_BUDGETS.may_load(deps.storage, spender.clone())? {
+                Some(link) => {
+                    let budget = BUDGETS.load(deps.storage, link.budget)?;
+                    amount > budget.allocated.saturating_sub(budget.spent)
+                }
+                None => false,
+            },
+        )
+    }
+
+    pub fn close_vault(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        if !ESCROW.may_load(deps.storage)?.unwrap_or_default().is_zero() {
+            return Err(ContractError::EscrowOutstanding {});
+        }
+        if !total_staked(deps.storage)?.is_zero() || !UNBONDINGS.is_empty(deps.storage) {
+            return Err(ContractError::StakeOutstanding {});
+        }
+        // Share holders leave by redeeming, refunds follow the deposits.
+        super::check_no_shares(deps.storage)?;
+
+        // Only the token allowances that can still be paid are honored, the
+        // rest goes to the depositors. Frozen, flagged, zero and allowances
+        // the pool or their budget cannot cover would block finalization.
+        PEGGED_ALLOWANCES.clear(deps.storage);
+        super::refresh_pegged_reserves(deps.storage)?;
+        let allowances = ALLOWANCES
+            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()?;
+        let mut available = current_state.total_amount;
+        let mut dropped = 0u64;
+        for (spender, amount) in allowances {
+            if unpayable_allowance(deps.as_ref(), &spender, amount, available)? {
+                super::remove_allowance(deps.storage, &spender)?;
+                super::unassign_budget(deps.storage, &spender)?;
+                dropped += 1;
+            } else {
+                available -= amount;
+            }
+        }
+        // Pegged allowances are gone too.
+        let linked = ALLOWANCE_BUDGETS
+            .keys(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .collect::<StdResult<Vec<Addr>>>()?;
+        for spender in linked {
+            super::release_budget(deps.storage, &spender)?;
+        }
+
+        let refund_pool = available;
+        let deposits = DEPOSITED
+            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()?;
+        let total_deposits = deposits
+            .iter()
+            .fold(Uint128::zero(), |total, (_, amount)| total + amount);
+        let mut closure = Closure {
+            refund_pool,
+            total_deposits,
+            unpaid_refunds: 0,
+            finalized: false,
+        };
+        closure.unpaid_refunds = deposits
+            .iter()
+            .filter(|(_, deposited)| !super::refund_amount(&closure, *deposited).is_zero())
+            .count() as u64;
+        finalize_if_settled(deps.storage, &mut closure)?;
+        Ok(Response::new()
+            .add_attribute("action", "close_vault")
+            .add_attribute("refund_pool", refund_pool)
+            .add_attribute("total_deposits", total_deposits)
+            .add_attribute("dropped_allowances", dropped.to_string())
+            .add_attribute("finalized", closure.finalized.to_string()))
+    }
+
+    pub fn claim_refund(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+    ) -> Result<Response, ContractError> {
+        super::screen(deps.as_ref(), &[info.sender.as_str()])?;
+        let mut closure = match CLOSURE.may_load(deps.storage)? {
+            Some(value) => value,
+            None => return Err(ContractError::VaultNotClosed {}),
+        };
+        if REFUND_CLAIMS.has(deps.storage, info.sender.clone()) {
+            return Err(ContractError::RefundAlreadyClaimed {});
+        }
+        let deposited = DEPOSITED
+            .may_load(deps.storage, info.sender.clone())?
+            .unwrap_or_default();
+        let amount = super::refund_amount(&closure, deposited);
+        if amount.is_zero() {
+            return Err(ContractError::NoRefund {});
+        }
+        super::record_outflow(deps.storage, None, amount, env.block.time)?;
+        REFUND_CLAIMS.save(deps.storage, info.sender.clone(), &amount)?;
+        closure.unpaid_refunds = closure.unpaid_refunds.saturating_sub(1);
+
+        let mut current_state = STATE.load(deps.storage)?;
+        current_state.total_amount = current_state.total_amount.saturating_sub(amount);
+        STATE.save(deps.storage, &current_state)?;
+        finalize_if_settled(deps.storage, &mut closure)?;
+
+        Ok(Response::new()
+            .add_message(BankMsg::Send {
+                to_address: info.sender.to_string(),
+                amount: coins(amount.u128(), current_state.expected_denom),
+            })
+            .add_attribute("action", "claim_refund")
+            .add_attribute("address", info.sender.to_string())
+            .add_attribute("amount", amount)
+            .add_attribute("finalized", closure.finalized.to_string()))
+    }
+
+    pub fn set_rate_limits(
+        deps: DepsMut,
+        info: MessageInfo,
+        max_claim_per_window: Option<Uint128>,
+        window_seconds: u64,
+        max_daily_outflow: Option<Uint128>,
+        bucket_seconds: u64,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        if bucket_seconds == 0 || bucket_seconds > window_seconds {
+            return Err(ContractError::InvalidRateLimits {});
+        }
+        // Counters of another bucket size cannot be compared with the new ones,
+        // otherwise they carry over so that changing a limit does not reset it.
+        if RATE_LIMITS
+            .may_load(deps.storage)?
+            .is_none_or(|limits| limits.bucket_seconds != bucket_seconds)
+        {
+            SPENDER_OUTFLOW.clear(deps.storage);
+            VAULT_OUTFLOW.clear(deps.storage);
+        }
+        RATE_LIMITS.save(
+            deps.storage,
+            &RateLimits {
+                max_claim_per_window,
+                window_seconds,
+                max_daily_outflow,
+                bucket_seconds,
+            },
+        )?;
+        let unlimited = || "none".to_string();
+        Ok(Response::new()
+            .add_attribute("action", "set_rate_limits")
+            .add_attribute(
+                "max_claim_per_window",
+                max_claim_per_window.map_or_else(unlimited, |x| x.to_string()),
+            )
+            .add_attribute("window_seconds", window_seconds.to_string())
+            .add_attribute(
+                "max_daily_outflow",
+                max_daily_outflow.map_or_else(unlimited, |x| x.to_string()),
+            )
+            .add_attribute("bucket_seconds", bucket_seconds.to_string()))
+    }
+
+    pub fn create_budget(
+        deps: DepsMut,
+        info: MessageInfo,
+        name: String,
+        allocated: Uint128,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        if BUDGETS.has(deps.storage, name.clone()) {
+            return Err(ContractError::BudgetExists { name });
+        }
+        BUDGETS.save(
+            deps.storage,
+            name.clone(),
+            &Budget {
+                allocated,
+                spent: Uint128::zero(),
+                reserved: Uint128::zero(),
+            },
+        )?;
+        Ok(Response::new()
+            .add_attribute("action", "create_budget")
+            .add_attribute("name", name)
+            .add_attribute("allocated", allocated))
+    }
+
+    pub fn assign_budget(
+        deps: DepsMut,
+        info: MessageInfo,
+        spender: String,
+        budget: String,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let address = match deps.api.addr_validate(spender.as_str()) {
+            Ok(value) => value,
+            Err(_) => return Err(ContractError::NotValidAddress { address: spender }),
+        };
+        if !BUDGETS.has(deps.storage, budget.clone()) {
+            return Err(ContractError::BudgetNotFound { name: budget });
+        }
+        super::unassign_budget(deps.storage, &address)?;
+        ALLOWANCE_BUDGETS.save(
+            deps.storage,
+            address.clone(),
+            &BudgetLink {
+                budget: budget.clone(),
+                reserved: Uint128::zero(),
+            },
+        )?;
+        super::check_budget(deps.storage, &address)?;
+        Ok(Response::new()
+            .add_attribute("action", "assign_budget")
+            .add_attribute("spender", spender)
+            .add_attribute("budget", budget))
+    }
+
+    pub fn unassign_budget(
+        deps: DepsMut,
+        info: MessageInfo,
+        spender: String,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let address = match deps.api.addr_validate(spender.as_str()) {
+            Ok(value) => value,
+            Err(_) => return Err(ContractError::NotValidAddress { address: spender }),
+        };
+        let budget = match super::unassign_budget(deps.storage, &address)? {
+            Some(value) => value,
+            None => return Err(ContractError::BudgetNotAssigned { spender }),
+        };
+        Ok(Response::new()
+            .add_attribute("action", "unassign_budget")
+            .add_attribute("spender", spender)
+            .add_attribute("budget", budget))
+    }
+
+    pub fn reallocate_budget(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        from: String,
+        to: String,
+        amount: Uint128,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let mut from_budget = match BUDGETS.may_load(deps.storage, from.clone())? {
+            Some(value) => value,
+            None => return Err(ContractError::BudgetNotFound { name: from }),
+        };
+        let mut to_budget = match BUDGETS.may_load(deps.storage, to.clone())? {
+            Some(value) => value,
+            None => return Err(ContractError::BudgetNotFound { name: to }),
+        };
+        // Only the part that is neither spent nor reserved can move.
+        let movable = from_budget
+            .allocated
+            .saturating_sub(from_budget.spent)
+            .saturating_sub(from_budget.reserved);
+        if amount > movable {
+            return Err(ContractError::BudgetExceeded {
+                name: from,
+                available: movable.u128(),
+            });
+        }
+        from_budget.allocated -= amount;
+        to_budget.allocated += amount;
+        BUDGETS.save(deps.storage, from.clone(), &from_budget)?;
+        BUDGETS.save(deps.storage, to.clone(), &to_budget)?;
+
+        let id = REALLOCATION_COUNT
+            .may_load(deps.storage)?
+            .unwrap_or_default()
+            + 1;
+        REALLOCATION_COUNT.save(deps.storage, &id)?;
+        REALLOCATIONS.save(
+            deps.storage,
+            id,
+            &Reallocation {
+                from: from.clone(),
+                to: to.clone(),
+                amount,
+                time: env.block.time,
+            },
+        )?;
+        Ok(Response::new()
+            .add_attribute("action", "reallocate_budget")
+            .add_attribute("id", id.to_string())
+            .add_attribute("from", from)
+            .add_attribute("to", to)
+            .add_attribute("amount", amount))
+    }
+
+    pub fn start_campaign(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        goal: Uint128,
+        deadline: Timestamp,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        if let Some(campaign) = CAMPAIGN.may_load(deps.storage)? {
+            // A failed campaign is also over once every contribution was reclaimed.
+            let settled = super::campaign_failed(&campaign, env.block.time)
+                && campaign.reclaimed >= campaign.raised;
+            if super::campaign_locked(&campaign, env.block.time) && !settled {
+                return Err(ContractError::CampaignActive {});
+            }
+        }
+        if goal.is_zero() {
+            return Err(ContractError::ZeroCampaignGoal {});
+        }
+        if deadline <= env.block.time {
+            return Err(ContractError::InvalidCampaignDeadline {});
+        }
+        // Refunds are paid per address, which share tokens cannot follow.
+        if SHARE_CONFIG.may_load(deps.storage)?.is_some() {
+            return Err(ContractError::ShareModeEnabled {});
+        }
+
+        // Contributions are counted from the start of the campaign.
+        CAMPAIGN_CONTRIBUTIONS.clear(deps.storage);
+        CAMPAIGN.save(
+            deps.storage,
+            &Campaign {
+                goal,
+                deadline,
+                raised: Uint128::zero(),
+                reclaimed: Uint128::zero(),
+                unlocked: false,
+            },
+        )?;
+        Ok(Response::new()
+            .add_attribute("action", "start_campaign")
+            .add_attribute("goal", goal)
+            .add_attribute("deadline", deadline.seconds().to_string()))
+    }
+
+    pub fn unlock_campaign(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let mut campaign = match CAMPAIGN.may_load(deps.storage)? {
+            Some(campaign) if !campaign.unlocked => campaign,
+            _ => return Err(ContractError::NoCampaign {}),
+        };
+        // Deposits stop at the deadline, so a goal met later was met in time.
+        if campaign.raised < campaign.goal {
+            return Err(ContractError::GoalNotMet {
+                missing: campaign.goal - campaign.raised,
+            });
+        }
+        campaign.unlocked = true;
+        CAMPAIGN.save(deps.storage, &campaign)?;
+        Ok(Response::new()
+            .add_attribute("action", "unlock_campaign")
+            .add_attribute("raised", campaign.raised))
+    }
+
+    pub fn reclaim_contribution(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+    ) -> Result<Response, ContractError> {
+        super::screen(deps.as_ref(), &[info.sender.as_str()])?;
+        let mut campaign = match CAMPAIGN.may_load(deps.storage)? {
+            Some(campaign) if !campaign.unlocked => campaign,
+            _ => return Err(ContractError::NoCampaign {}),
+        };
+        if !super::campaign_failed(&campaign, env.block.time) {
+            return Err(ContractError::CampaignNotFailed {});
+        }
+        if !super::campaign_locked(&campaign, env.block.time) {
+            return Err(ContractError::ReclaimPeriodOver {});
+        }
+        let amount = CAMPAIGN_CONTRIBUTIONS
+            .may_load(deps.storage, info.sender.clone())?
+            .unwrap_or_default();
+        if amount.is_zero() {
+            return Err(ContractError::NothingToReclaim {});
+        }
+        CAMPAIGN_CONTRIBUTIONS.remove(deps.storage, info.sender.clone());
+        campaign.reclaimed += amount;
+        CAMPAIGN.save(deps.storage, &campaign)?;
+        // The returned contribution no longer counts towards closure refunds.
+        let deposited = DEPOSITED
+            .may_load(deps.storage, info.sender.clone())?
+            .unwrap_or_default();
+        DEPOSITED.save(
+            deps.storage,
+            info.sender.clone(),
+            &deposited.saturating_sub(amount),
+        )?;
+        if let Some(deposited) = DEPOSIT_ADDRESSES.may_load(deps.storage, info.sender.clone())? {
+            DEPOSIT_ADDRESSES.save(
+                deps.storage,
+                info.sender.clone(),
+                &deposited.saturating_sub(amount),
+            )?;
+        }
+
+        let mut current_state = STATE.load(deps.storage)?;
+        current_state.total_amount = current_state.total_amount.saturating_sub(amount);
+        STATE.save(deps.storage, &current_state)?;
+        Ok(Response::new()
+            .add_message(BankMsg::Send {
+                to_address: info.sender.to_string(),
+                amount: coins(amount.u128(), current_state.expected_denom),
+            })
+            .add_attribute("action", "reclaim_contribution")
+            .add_attribute("address", info.sender.to_string())
+            .add_attribute("amount", amount))
+    }
+
+    pub fn pledge_match(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        ratio: Decimal,
+        cap_per_depositor: Uint128,
+        expires: Timestamp,
+    ) -> Result<Response, ContractError> {
+        super::screen(deps.as_ref(), &[info.sender.as_str()])?;
+        if PLEDGES.has(deps.storage, info.sender.clone()) {
+            return Err(ContractError::PledgeExists {});
+        }
+        let current_state = STATE.load(deps.storage)?;
+        let budget = match info.funds.as_slice() {
+            [coin] if coin.denom == current_state.expected_denom => coin.amount,
+            _ => {
+                return Err(ContractError::InvalidDenom {
+                    denom: current_state.expected_denom,
+                })
+            }
+        };
+        if ratio.is_zero()
+            || cap_per_depositor.is_zero()
+            || budget.is_zero()
+            || expires <= env.block.time
+        {
+            return Err(ContractError::InvalidPledge {});
+        }
+        let min_budget = MIN_PLEDGE_BUDGET
+            .may_load(deps.storage)?
+            .unwrap_or_default();
+        if budget < min_budget {
+            return Err(ContractError::PledgeBelowMinimum {
+                min_budget: min_budget.u128(),
+            });
+        }
+        if super::active_pledges(deps.storage, env.block.time)?.len() >= super::MAX_ACTIVE_PLEDGES {
+            return Err(ContractError::TooManyPledges {
+                max: super::MAX_ACTIVE_PLEDGES as u64,
+            });
+        }
+        PLEDGES.save(
+            deps.storage,
+            info.sender.clone(),
+            &Pledge {
+                ratio,
+                cap_per_depositor,
+                budget,
+                matched: Uint128::zero(),
+                expires,
+            },
+        )?;
+        PLEDGE_EXPIRIES.save(deps.storage, (expires.nanos(), info.sender.clone()), &true)?;
+        let pledged = super::pledged_amount(deps.storage)?;
+        PLEDGED_TOTAL.save(deps.storage, &(pledged + budget))?;
+        Ok(Response::new()
+            .add_attribute("action", "pledge_match")
+            .add_attribute("sponsor", info.sender.to_string())
+            .add_attribute("ratio", ratio.to_string())
+            .add_attribute("budget", budget)
+            .add_attribute("expires", expires.seconds().to_string()))
+    }
+
+    pub fn withdraw_pledge(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+    ) -> Result<Response, ContractError> {
+        let pledge = match PLEDGES.may_load(deps.storage, info.sender.clone())? {
+            Some(pledge) => pledge,
+            None => return Err(ContractError::PledgeNotFound {}),
+        };
+        if pledge.expires > env.block.time {
+            return Err(ContractError::PledgeNotExpired {
+                seconds_remaining: pledge.expires.seconds() - env.block.time.seconds(),
+            });
+        }
+        PLEDGES.remove(deps.storage, info.sender.clone());
+        PLEDGE_EXPIRIES.remove(deps.storage, (pledge.expires.nanos(), info.sender.clone()));
+        let pledged = super::pledged_amount(deps.storage)?;
+        PLEDGED_TOTAL.save(deps.storage, &(pledged - pledge.budget))?;
+        let matches = PLEDGE_MATCHES
+            .prefix(info.sender.clone())
+            .keys(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()?;
+        for depositor in matches {
+            PLEDGE_MATCHES.remove(deps.storage, (info.sender.clone(), depositor));
+        }
+
+        let mut response = Response::new()
+            .add_attribute("action", "withdraw_pledge")
+            .add_attribute("sponsor", info.sender.to_string())
+            .add_attribute("amount", pledge.budget);
+        if !pledge.budget.is_zero() {
+            let current_state = STATE.load(deps.storage)?;
+            response = response.add_message(BankMsg::Send {
+                to_address: info.sender.to_string(),
+                amount: coins(pledge.budget.u128(), current_state.expected_denom),
+            });
+        }
+        Ok(response)
+    }
+
+    pub fn set_min_pledge_budget(
+        deps: DepsMut,
+        info: MessageInfo,
+        amount: Uint128,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        MIN_PLEDGE_BUDGET.save(deps.storage, &amount)?;
+        Ok(Response::new()
+            .add_attribute("action", "set_min_pledge_budget")
+            .add_attribute("amount", amount))
+    }
+
+    pub fn set_early_exit_penalty(
+        deps: DepsMut,
+        info: MessageInfo,
+        penalty: Decimal,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        if penalty > Decimal::one() {
+            return Err(ContractError::InvalidPenalty {});
+        }
+        EARLY_EXIT_PENALTY.save(deps.storage, &penalty)?;
+        Ok(Response::new()
+            .add_attribute("action", "set_early_exit_penalty")
+            .add_attribute("penalty", penalty.to_string()))
+    }
+
+    pub fn deposit_locked(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        lock_seconds: u64,
+    ) -> Result<Response, ContractError> {
+        super::screen(deps.as_ref(), &[info.sender.as_str()])?;
+        let penalty = match EARLY_EXIT_PENALTY.may_load(deps.storage)? {
+            Some(penalty) => penalty,
+            None => return Err(ContractError::SavingsNotEnabled {}),
+        };
+        if !DEPOSIT_ADDRESSES.has(deps.storage, info.sender.clone()) {
+            return Err(UnauthorizedDepositAddress {
+                address: info.sender.to_string(),
+            });
+        }
+        let current_state = STATE.load(deps.storage)?;
+        let amount = match info.funds.as_slice() {
+            [coin] if coin.denom == current_state.expected_denom && !coin.amount.is_zero() => {
+                coin.amount
+            }
+            _ => {
+                return Err(ContractError::InvalidDenom {
+                    denom: current_state.expected_denom,
+                })
+            }
+        };
+        if lock_seconds == 0 {
+            return Err(ContractError::ZeroLockPeriod {});
+        }
+
+        let id = POSITION_COUNT.may_load(deps.storage)?.unwrap_or_default() + 1;
+        POSITION_COUNT.save(deps.storage, &id)?;
+        let unlocks_at = env.block.time.plus_seconds(lock_seconds);
+        POSITIONS.save(
+            deps.storage,
+            (info.sender.clone(), id),
+            &Position {
+                amount,
+                deposited_at: env.block.time,
+                unlocks_at,
+                penalty,
+            },
+        )?;
+        Ok(Response::new()
+            .add_attribute("action", "deposit_locked")
+            .add_attribute("address", info.sender.to_string())
+            .add_attribute("id", id.to_string())
+            .add_attribute("amount", amount)
+            .add_attribute("unlocks_at", unlocks_at.seconds().to_string()))
+    }
+
+    pub fn close_position(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        id: u64,
+    ) -> Result<Response, ContractError> {
+        super::screen(deps.as_ref(), &[info.sender.as_str()])?;
+        let key = (info.sender.clone(), id);
+        let position = match POSITIONS.may_load(deps.storage, key.clone())? {
+            Some(position) => position,
+            None => return Err(ContractError::PositionNotFound { id }),
+        };
+        POSITIONS.remove(deps.storage, key);
+
+        // Early exits leave the penalty in the pool.
+        let mut current_state = STATE.load(deps.storage)?;
+        let penalty = if env.block.time < position.unlocks_at {
+            position.amount * position.penalty
+        } else {
+            Uint128::zero()
+        };
+        current_state.total_amount += penalty;
+        STATE.save(deps.storage, &current_state)?;
+
+        let payout = position.amount - penalty;
+        let mut response = Response::new()
+            .add_attribute("action", "close_position")
+            .add_attribute("address", info.sender.to_string())
+            .add_attribute("id", id.to_string())
+            .add_attribute("amount", payout)
+            .add_attribute("penalty", penalty);
+        if !payout.is_zero() {
+            response = response.add_message(BankMsg::Send {
+                to_address: info.sender.to_string(),
+                amount: coins(payout.u128(), current_state.expected_denom),
+            });
+        }
+        Ok(response)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_fee_config(
+        deps: DepsMut,
+        info: MessageInfo,
+        deposit_bps: u16,
+        claim_bps: u16,
+        min_fee: Uint128,
+        max_fee: Option<Uint128>,
+        treasury: Option<String>,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        if deposit_bps > 10_000
+            || claim_bps > 10_000
+            || max_fee.is_some_and(|max_fee| max_fee < min_fee)
+        {
+            return Err(ContractError::InvalidFeeConfig {});
+        }
+        let treasury = match treasury {
+            Some(address) => match deps.api.addr_validate(address.as_str()) {
+                Ok(value) => Some(value),
+                Err(_) => return Err(ContractError::NotValidAddress { address }),
+            },
+            None => None,
+        };
+        FEE_CONFIG.save(
+            deps.storage,
+            &FeeConfig {
+                deposit_bps,
+                claim_bps,
+                min_fee,
+                max_fee,
+                treasury,
+            },
+        )?;
+        Ok(Response::new()
+            .add_attribute("action", "set_fee_config")
+            .add_attribute("deposit_bps", deposit_bps.to_string())
+            .add_attribute("claim_bps", claim_bps.to_string()))
+    }
+
+    pub fn remove_fee_config(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        FEE_CONFIG.remove(deps.storage);
+        Ok(Response::new().add_attribute("action", "remove_fee_config"))
+    }
+
+    pub fn collect_fees(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let accrued = ACCRUED_FEES.may_load(deps.storage)?.unwrap_or_default();
+        if accrued.is_zero() {
+            return Err(ContractError::NoFees {});
+        }
+        ACCRUED_FEES.remove(deps.storage);
+        Ok(Response::new()
+            .add_message(BankMsg::Send {
+                to_address: info.sender.to_string(),
+                amount: coins(accrued.u128(), current_state.expected_denom),
+            })
+            .add_attribute("action", "collect_fees")
+            .add_attribute("amount", accrued))
+    }
+
+    pub fn set_withholding(
+        deps: DepsMut,
+        info: MessageInfo,
+        spender: Option<String>,
+        rate: Decimal,
+        destination: String,
+        brackets: Vec<WithholdingBracketMsg>,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        // A rate of 1 would leave nothing to pay out.
+        let rates_valid =
+            rate < Decimal::one() && brackets.iter().all(|bracket| bracket.rate < Decimal::one());
+        let thresholds_increase = brackets
+            .windows(2)
+            .all(|pair| pair[0].threshold < pair[1].threshold);
+        if !rates_valid || !thresholds_increase {
+            return Err(ContractError::InvalidWithholding {});
+        }
+        let destination = match deps.api.addr_validate(destination.as_str()) {
+            Ok(value) => value,
+            Err(_) => {
+                return Err(ContractError::NotValidAddress {
+                    address: destination,
+                })
+            }
+        };
+        let rule = WithholdingRule {
+            rate,
+            destination,
+            brackets: brackets
+                .into_iter()
+                .map(|bracket| WithholdingBracket {
+                    threshold: bracket.threshold,
+                    rate: bracket.rate,
+                })
+                .collect(),
+        };
+        let response = Response::new()
+            .add_attribute("action", "set_withholding")
+            .add_attribute("rate", rate.to_string());
+        match spender {
+            Some(spender) => {
+                let address = match deps.api.addr_validate(spender.as_str()) {
+                    Ok(value) => value,
+                    Err(_) => return Err(ContractError::NotValidAddress { address: spender }),
+                };
+                WITHHOLDING_RULES.save(deps.storage, address, &rule)?;
+                Ok(response.add_attribute("spender", spender))
+            }
+            None => {
+                GLOBAL_WITHHOLDING.save(deps.storage, &rule)?;
+                Ok(response)
+            }
+        }
+    }
+
+    pub fn remove_withholding(
+        deps: DepsMut,
+        info: MessageInfo,
+        spender: Option<String>,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        match spender {
+            Some(spender) => {
+                let address = deps.api.addr_validate(spender.as_str())?;
+                if !WITHHOLDING_RULES.has(deps.storage, address.clone()) {
+                    return Err(ContractError::WithholdingNotFound {});
+                }
+                WITHHOLDING_RULES.remove(deps.storage, address);
+            }
+            None => {
+                if !GLOBAL_WITHHOLDING.exists(deps.storage) {
+                    return Err(ContractError::WithholdingNotFound {});
+                }
+                GLOBAL_WITHHOLDING.remove(deps.storage);
+            }
+        }
+        Ok(Response::new().add_attribute("action", "remove_withholding"))
+    }
+
+    pub fn set_compliance_registry(
+        deps: DepsMut,
+        info: MessageInfo,
+        registry: Option<String>,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        match registry {
+            Some(address) => {
+                let registry = match deps.api.addr_validate(address.as_str()) {
+                    Ok(value) => value,
+                    Err(_) => return Err(ContractError::NotValidAddress { address }),
+                };
+                COMPLIANCE_REGISTRY.save(deps.storage, &registry)?;
+                Ok(Response::new()
+                    .add_attribute("action", "set_compliance_registry")
+                    .add_attribute("registry", registry.to_string()))
+            }
+            None => {
+                COMPLIANCE_REGISTRY.remove(deps.storage);
+                Ok(Response::new().add_attribute("action", "remove_compliance_registry"))
+            }
+        }
+    }
+
+    pub fn update_deny_list(
+        deps: DepsMut,
+        info: MessageInfo,
+        address: String,
+        denied: bool,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let value = match deps.api.addr_validate(address.as_str()) {
+            Ok(value) => value,
+            Err(_) => return Err(ContractError::NotValidAddress { address }),
+        };
+        let action = if denied {
+            DENY_LIST.save(deps.storage, value, &true)?;
+            "add_to_deny_list"
+        } else {
+            DENY_LIST.remove(deps.storage, value);
+            "remove_from_deny_list"
+        };
+        Ok(Response::new()
+            .add_attribute("action", action)
+            .add_attribute("address", address))
+    }
+
+    pub fn freeze_allowance(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        spender: String,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let address = match deps.api.addr_validate(spender.as_str()) {
+            Ok(value) => value,
+            Err(_) => return Err(ContractError::NotValidAddress { address: spender }),
+        };
+        FROZEN_ALLOWANCES.save(deps.storage, address, &env.block.time)?;
+        Ok(Response::new()
+            .add_attribute("action", "freeze_allowance")
+            .add_attribute("spender", spender))
+    }
+
+    pub fn unfreeze_allowance(
+        deps: DepsMut,
+        info: MessageInfo,
+        spender: String,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let address = deps.api.addr_validate(spender.as_str())?;
+        if !FROZEN_ALLOWANCES.has(deps.storage, address.clone()) {
+            return Err(ContractError::AllowanceNotFrozen { spender });
+        }
+        FROZEN_ALLOWANCES.remove(deps.storage, address);
+        Ok(Response::new()
+            .add_attribute("action", "unfreeze_allowance")
+            .add_attribute("spender", spender))
+    }
+
+    pub fn clawback_allowance(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        spender: String,
+        amount: Uint128,
+        reason: String,
+    ) -> Result<Response, ContractError> {
+        let current_state = STATE.load(deps.storage)?;
+        if info.sender != current_state.owner {
+            return Err(ContractError::NotOwner {
+                owner: current_state.owner.to_string(),
+            });
+        }
+        let address = match deps.api.addr_validate(spender.as_str()) {
+            Ok(value) => value,
+            Err(_) => return Err(ContractError::NotValidAddress { address: spender }),
+        };
+        let allowance = match ALLOWANCES.may_load(deps.storage, address.clone())? {
+            Some(value) => value,
+            None => return Err(ContractError::NoAllowance { address: spender }),
+        };
+        if amount > allowance {
+            return Err(ContractError::ClawbackExceedsAllowance { allowance });
+        }
+        // The allowance is kept at zero so its clawbacks stay visible.
+        let remaining = allowance - amount;
+        super::save_allowance(deps.storage, &address, remaining)?;
+        super::sync_budget(deps.storage, &address)?;
+        let id = CLAWBACK_COUNT.may_load(deps.storage)?.unwrap_or_default() + 1;
+        CLAWBACK_COUNT.save(deps.storage, &id)?;
+        CLAWBACKS.save(
+            deps.storage,
+            (address, id),
+            &Clawback {
+                amount,
+                reason: reason.clone(),
+                time: env.block.time,
+            },
+        )?;
+
+        let hooks = super::hook_messages(
+            deps.storage,
+            VaultHookMsg::AllowanceChanged {
+                spender: spender.clone(),
+                amount: Some(remaining),
+            },
+        )?;
+        Ok(Response::new()
+            .add_submessages(hooks)
+            .add_attribute("action", "clawback_allowance")
+            .add_attribute("spender", spender)
+            .add_attribute("amount", amount)
+            .add_attribute("remaining", remaining)
+            .add_attribute("reason", reason))
+    }
+}
+
+pub mod query {
+    use crate::{
+        msg::{
+            AllowanceResponse, AllowancesResponse, BudgetResponse, BudgetsResponse,
+            CampaignResponse, CanDepositResponse, ClawbackResponse, ClosureResponse,
+            ComplianceResponse, DeadManSwitchResponse, DelegationResponse,
+            DepositAddressesResponse, DistributionClaimedResponse, DistributionResponse,
+            FeeConfigResponse, GrantResponse, GrantsResponse, HookInfo, HooksResponse,
+            InvoiceReceiptResponse, InvoiceResponse, InvoicesResponse, MilestoneResponse,
+            NonceUsedResponse, OracleConfigResponse, PausedResponse, PayoutPreferenceResponse,
+            PeggedAllowanceResponse, PendingTransferResponse, PendingTransfersResponse,
+            PledgeResponse, PledgesResponse, PositionResponse, PositionsResponse,
+            PreviewDepositResponse, PreviewRedeemResponse, ReallocationResponse,
+            ReallocationsResponse, RecoveryVote, RefundResponse, RemainingQuotaResponse,
+            ShareConfigResponse, StakingInfoResponse, StateResponse, SwapConfigResponse,
+            TrustedVaultResponse, TrustedVaultsResponse, UnbondingResponse, VaultChannelResponse,
+            VaultChannelsResponse, VendorResponse, VendorsResponse, VoucherPubkeyResponse,
+            WithholdingBracketMsg, WithholdingReportResponse,
+        },
+        state::{
+            Budget, Grant, Invoice, Pledge, ACCRUED_FEES, ALLOWANCES, BUDGETS, CAMPAIGN, CLAWBACKS,
+            CLOSURE, COMPLIANCE_REGISTRY, DEAD_MAN_SWITCH, DELEGATIONS, DENY_LIST, DEPOSITED,
+            DEPOSIT_ADDRESSES, DISTRIBUTIONS, DISTRIBUTION_CLAIMS, EARLY_EXIT_PENALTY, FEE_CONFIG,
+            FROZEN_ALLOWANCES, GLOBAL_WITHHOLDING, GRANTS, HOOKS, INVOICES, LAST_PRICE,
+            ORACLE_CONFIG, PAUSED, PAYOUT_PREFERENCES, PEGGED_ALLOWANCES, PENDING_TRANSFERS,
+            PLEDGES, PLEDGE_MATCHES, POSITIONS, RATE_LIMITS, REALLOCATIONS, RECOVERY_VOTES,
+            REFUND_CLAIMS, SHARE_CONFIG, STAKING_CONFIG, STATE, SWAP_CONFIG, TRUSTED_VAULTS,
+            UNBONDINGS, USED_NONCES, VAULT_CHANNELS, VENDORS, VOUCHER_PUBKEY, WITHHOLDING_RULES,
+            WITHHOLDING_TOTALS,
+        },
+    };
+    use cosmwasm_std::{Addr, Decimal, Deps, Env, StdResult, Timestamp, Uint128};
+
+    pub fn get_state(deps: Deps) -> StdResult<StateResponse> {
+        let current_state = STATE.load(deps.storage)?;
+        Ok(StateResponse {
+            owner: current_state.owner.to_string(),
+            name: current_state.name,
+            total_amount: current_state.total_amount,
+            expected_denom: current_state.expected_denom,
+        })
+    }
+
+    pub fn get_allowance(deps: Deps, spender: String) -> StdResult<AllowanceResponse> {
+        let address = deps.api.addr_validate(spender.as_str())?;
+        let mut clawbacks = CLAWBACKS
+            .prefix(address.clone())
+            .range(deps.storage, None, None, cosmwasm_std::Order::Descending)
+            .take(super::CLAWBACKS_SHOWN)
+            .map(|item| {
+                let (_, clawback) = item?;
+                Ok(ClawbackResponse {
+                    amount: clawback.amount,
+                    reason: clawback.reason,
+                    time: clawback.time,
+                })
+            })
+            .collect::<StdResult<Vec<ClawbackResponse>>>()?;
+        clawbacks.reverse();
+        let amount = match ALLOWANCES.may_load(deps.storage, address.clone())? {
+            Some(value) => value,
+            // A pegged allowance shows the tokens it currently holds back.
+            None if PEGGED_ALLOWANCES.has(deps.storage, address.clone()) => {
+                super::allowance_reserve(deps.storage, &address)?
+            }
+            // A closed vault drops zero allowances, their clawbacks remain.
+            None if !clawbacks.is_empty() => Uint128::zero(),
+            None => ALLOWANCES.load(deps.storage, address.clone())?,
+        };
+        Ok(AllowanceResponse {
+            spender,
+            amount,
+            frozen_since: FROZEN_ALLOWANCES.may_load(deps.storage, address)?,
+            clawbacks,
+        })
+    }
+
+    pub fn get_allowances(deps: Deps) -> StdResult<AllowancesResponse> {
+        let spenders = ALLOWANCES
+            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .map(|item| {
+                let (address, _) = item?;
+                Ok(address)
+            })
+            .collect::<StdResult<Vec<Addr>>>()?;
+        let amounts = spenders
+            .iter()
+            .map(|spender| ALLOWANCES.load(deps.storage, spender.clone()))
+            .collect::<StdResult<Vec<Uint128>>>()?;
+        Ok(AllowancesResponse {
+            spenders: spenders.iter().map(|x| x.to_string()).collect(),
+            amounts,
+        })
+    }
+
+    pub fn can_deposit(deps: Deps, address: String) -> StdResult<CanDepositResponse> {
+        let address = deps.api.addr_validate(address.as_str())?;
+        let address_amount = DEPOSIT_ADDRESSES.may_load(deps.storage, address)?;
+        match address_amount {
+            Some(_) => Ok(CanDepositResponse { can_deposit: true }),
+            None => Ok(CanDepositResponse { can_deposit: false }),
+        }
+    }
+    pub fn get_deposit_addresses(deps: Deps) -> StdResult<DepositAddressesResponse> {
+        let addresses = DEPOSIT_ADDRESSES
+            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .map(|item| {
+                let (address, _) = item?;
+                Ok(address)
+            })
+            .collect::<StdResult<Vec<Addr>>>()?;
+        Ok(DepositAddressesResponse {
+            addresses: addresses.iter().map(|x| x.to_string()).collect(),
+        })
+    }
+
+    pub fn get_vendor(deps: Deps, address: String) -> StdResult<VendorResponse> {
+        let vendor = VENDORS.load(deps.storage, deps.api.addr_validate(address.as_str())?)?;
+        Ok(VendorResponse {
+            address,
+            name: vendor.name,
+            default_denom: vendor.default_denom,
+        })
+    }
+
+    pub fn get_vendors(deps: Deps) -> StdResult<VendorsResponse> {
+        let vendors = VENDORS
+            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .map(|item| {
+                let (address, vendor) = item?;
+                Ok(VendorResponse {
+                    address: address.to_string(),
+                    name: vendor.name,
+                    default_denom: vendor.default_denom,
+                })
+            })
+            .collect::<StdResult<Vec<VendorResponse>>>()?;
+        Ok(VendorsResponse { vendors })
+    }
+
+    fn to_invoice_response(id: u64, invoice: Invoice) -> InvoiceResponse {
+        InvoiceResponse {
+            id,
+            vendor: invoice.vendor.to_string(),
+            amount: invoice.amount,
+            denom: invoice.denom,
+            due_date: invoice.due_date,
+            reference_id: invoice.reference_id,
+            receipt: invoice.receipt.map(|receipt| InvoiceReceiptResponse {
+                paid_at: receipt.paid_at,
+                block_height: receipt.block_height,
+                amount: receipt.amount,
+            }),
+        }
+    }
+
+    pub fn get_invoice(deps: Deps, id: u64) -> StdResult<InvoiceResponse> {
+        let invoice = INVOICES.load(deps.storage, id)?;
+        Ok(to_invoice_response(id, invoice))
+    }
+
+    pub fn get_invoices(deps: Deps) -> StdResult<InvoicesResponse> {
+        let invoices = INVOICES
+            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .map(|item| {
+                let (id, invoice) = item?;
+                Ok(to_invoice_response(id, invoice))
+            })
+            .collect::<StdResult<Vec<InvoiceResponse>>>()?;
+        Ok(InvoicesResponse { invoices })
+    }
+
+    fn to_grant_response(id: u64, grant: Grant) -> GrantResponse {
+        let (released_amount, remaining_amount) = grant.milestones.iter().fold(
+            (Uint128::zero(), Uint128::zero()),
+            |(released, remaining), milestone| match milestone.completed_at {
+                Some(_) => (released + milestone.amount, remaining),
+                None => (released, remaining + milestone.amount),
+            },
+        );
+        GrantResponse {
+            id,
+            grantee: grant.grantee.to_string(),
+            arbiter: grant.arbiter.to_string(),
+            milestones: grant
+                .milestones
+                .into_iter()
+                .map(|milestone| MilestoneResponse {
+                    amount: milestone.amount,
+                    description: milestone.description,
+                    completed_at: milestone.completed_at,
+                })
+                .collect(),
+            released_amount,
+            remaining_amount: if grant.cancelled {
+                Uint128::zero()
+            } else {
+                remaining_amount
+            },
+            cancelled: grant.cancelled,
+        }
+    }
+
+    pub fn get_grant(deps: Deps, id: u64) -> StdResult<GrantResponse> {
+        let grant = GRANTS.load(deps.storage, id)?;
+        Ok(to_grant_response(id, grant))
+    }
+
+    pub fn get_grants(deps: Deps) -> StdResult<GrantsResponse> {
+        let grants = GRANTS
+            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .map(|item| {
+                let (id, grant) = item?;
+                Ok(to_grant_response(id, grant))
+            })
+            .collect::<StdResult<Vec<GrantResponse>>>()?;
+        Ok(GrantsResponse { grants })
+    }
+
+    pub fn get_voucher_pubkey(deps: Deps) -> StdResult<VoucherPubkeyResponse> {
+        Ok(VoucherPubkeyResponse {
+            pubkey: VOUCHER_PUBKEY.may_load(deps.storage)?,
+        })
+    }
+
+    pub fn is_nonce_used(deps: Deps, nonce: u64) -> StdResult<NonceUsedResponse> {
+        Ok(NonceUsedResponse {
+            used: USED_NONCES.has(deps.storage, nonce),
+        })
+    }
+
+    pub fn get_distribution(deps: Deps, round: u64) -> StdResult<DistributionResponse> {
+        let distribution = DISTRIBUTIONS.load(deps.storage, round)?;
+        Ok(DistributionResponse {
+            round,
+            merkle_root: distribution.merkle_root,
+            total_amount: distribution.total_amount,
+            claimed_amount: distribution.claimed_amount,
+            expiration: distribution.expiration,
+            clawed_back: distribution.clawed_back,
+        })
+    }
+
+    pub fn is_distribution_claimed(
+        deps: Deps,
+        round: u64,
+        address: String,
+    ) -> StdResult<DistributionClaimedResponse> {
+        let address = deps.api.addr_validate(address.as_str())?;
+        Ok(DistributionClaimedResponse {
+            claimed: DISTRIBUTION_CLAIMS.has(deps.storage, (round, address)),
+        })
+    }
+
+    pub fn get_share_config(deps: Deps) -> StdResult<ShareConfigResponse> {
+        let current_state = STATE.load(deps.storage)?;
+        let config = SHARE_CONFIG.may_load(deps.storage)?;
+        Ok(ShareConfigResponse {
+            token: config.as_ref().map(|config| config.token.to_string()),
+            total_supply: config.map(|config| config.total_supply).unwrap_or_default(),
+            free_amount: super::free_amount(deps.storage, &current_state)?,
+        })
+    }
+
+    pub fn preview_deposit(deps: Deps, amount: Uint128) -> StdResult<PreviewDepositResponse> {
+        let current_state = STATE.load(deps.storage)?;
+        let config = SHARE_CONFIG.load(deps.storage)?;
+        Ok(PreviewDepositResponse {
+            shares: super::shares_for_deposit(
+                amount,
+                super::share_assets(deps.storage, &current_state)?,
+                config.total_supply,
+            ),
+        })
+    }
+
+    pub fn preview_redeem(deps: Deps, shares: Uint128) -> StdResult<PreviewRedeemResponse> {
+        let current_state = STATE.load(deps.storage)?;
+        let config = SHARE_CONFIG.load(deps.storage)?;
+        Ok(PreviewRedeemResponse {
+            amount: super::amount_for_shares(
+                shares,
+                super::share_assets(deps.storage, &current_state)?,
+                config.total_supply,
+            ),
+        })
+    }
+
+    pub fn get_staking_info(deps: Deps) -> StdResult<StakingInfoResponse> {
+        let config = STAKING_CONFIG.load(deps.storage)?;
+        let delegations = DELEGATIONS
+            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .map(|item| {
+                let (validator, amount) = item?;
+                Ok(DelegationResponse { validator, amount })
+            })
+            .collect::<StdResult<Vec<DelegationResponse>>>()?;
+        let unbondings = UNBONDINGS
+            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .map(|item| {
+                let (id, unbonding) = item?;
+                Ok(UnbondingResponse {
+                    id,
+                    validator: unbonding.validator,
+                    amount: unbonding.amount,
+                    completes_at: unbonding.completes_at,
+                })
+            })
+            .collect::<StdResult<Vec<UnbondingResponse>>>()?;
+        Ok(StakingInfoResponse {
+            max_stake_ratio: config.max_stake_ratio,
+            unbonding_period: config.unbonding_period,
+            total_staked: delegations.iter().map(|delegation| delegation.amount).sum(),
+            delegations,
+            unbondings,
+        })
+    }
+
+    pub fn get_payout_preference(
+        deps: Deps,
+        spender: String,
+    ) -> StdResult<PayoutPreferenceResponse> {
+        let address = deps.api.addr_validate(spender.as_str())?;
+        let preference = PAYOUT_PREFERENCES.may_load(deps.storage, address)?;
+        Ok(PayoutPreferenceResponse {
+            channel_id: preference.as_ref().map(|p| p.channel_id.clone()),
+            remote_address: preference.as_ref().map(|p| p.remote_address.clone()),
+            timeout_seconds: preference.map(|p| p.timeout_seconds),
+        })
+    }
+
+    pub fn get_pending_transfers(deps: Deps) -> StdResult<PendingTransfersResponse> {
+        let transfers = PENDING_TRANSFERS
+            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .map(|item| {
+                let ((channel_id, sequence), transfer) = item?;
+                Ok(PendingTransferResponse {
+                    channel_id,
+                    sequence,
+                    spender: transfer.spender.to_string(),
+                    amount: transfer.amount,
+                    vault_channel_id: transfer.vault_channel_id,
+                    withheld: transfer
+                        .withholding
+                        .map(|withholding| withholding.amount)
+                        .unwrap_or_default(),
+                })
+            })
+            .collect::<StdResult<Vec<PendingTransferResponse>>>()?;
+        Ok(PendingTransfersResponse { transfers })
+    }
+
+    pub fn get_trusted_vaults(deps: Deps) -> StdResult<TrustedVaultsResponse> {
+        let vaults = TRUSTED_VAULTS
+            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .map(|item| {
+                let (port_id, vault) = item?;
+                Ok(TrustedVaultResponse {
+                    port_id,
+                    connection_id: vault.connection_id,
+                    remote_address: vault.remote_address,
+                    transfer_channel_id: vault.transfer_channel_id,
+                })
+            })
+            .collect::<StdResult<Vec<TrustedVaultResponse>>>()?;
+        Ok(TrustedVaultsResponse { vaults })
+    }
+
+    pub fn get_vault_channels(deps: Deps) -> StdResult<VaultChannelsResponse> {
+        let channels = VAULT_CHANNELS
+            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .map(|item| {
+                let (channel_id, channel) = item?;
+                Ok(VaultChannelResponse {
+                    channel_id,
+                    counterparty_port_id: channel.counterparty_port_id,
+                    requested_by_peer: channel.requested_by_peer,
+                    incoming: channel.incoming,
+                    sent: channel.sent,
+                    received: channel.received,
+                    reported_balance: channel.reported_balance,
+                    reported_at: channel.reported_at,
+                })
+            })
+            .collect::<StdResult<Vec<VaultChannelResponse>>>()?;
+        Ok(VaultChannelsResponse { channels })
+    }
+
+    pub fn get_swap_config(deps: Deps) -> StdResult<SwapConfigResponse> {
+        Ok(match SWAP_CONFIG.may_load(deps.storage)? {
+            Some(config) => SwapConfigResponse {
+                router: Some(config.router.to_string()),
+                auto_swap: config.auto_swap,
+                max_slippage: config.max_slippage,
+            },
+            None => SwapConfigResponse {
+                router: None,
+                auto_swap: false,
+                max_slippage: Decimal::zero(),
+            },
+        })
+    }
+
+    pub fn get_oracle_config(deps: Deps) -> StdResult<OracleConfigResponse> {
+        let config = ORACLE_CONFIG.load(deps.storage)?;
+        let reference = LAST_PRICE.may_load(deps.storage)?;
+        Ok(OracleConfigResponse {
+            oracle: config.oracle.to_string(),
+            quote: config.quote,
+            max_staleness: config.max_staleness,
+            max_deviation: config.max_deviation,
+            max_tokens_per_claim: config.max_tokens_per_claim,
+            last_price: reference.as_ref().map(|reference| reference.price),
+            last_price_at: reference.map(|reference| reference.time),
+        })
+    }
+
+    pub fn get_pegged_allowance(deps: Deps, spender: String) -> StdResult<PeggedAllowanceResponse> {
+        let address = deps.api.addr_validate(spender.as_str())?;
+        let quote_amount = PEGGED_ALLOWANCES.load(deps.storage, address)?;
+        let quote = ORACLE_CONFIG
+            .may_load(deps.storage)?
+            .map(|config| config.quote)
+            .unwrap_or_default();
+        Ok(PeggedAllowanceResponse {
+            spender,
+            quote_amount,
+            quote,
+        })
+    }
+
+    pub fn get_hooks(deps: Deps) -> StdResult<HooksResponse> {
+        let hooks = HOOKS
+            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .map(|item| {
+                let (address, blocking) = item?;
+                Ok(HookInfo {
+                    address: address.to_string(),
+                    blocking,
+                })
+            })
+            .collect::<StdResult<Vec<HookInfo>>>()?;
+        Ok(HooksResponse { hooks })
+    }
+
+    pub fn is_paused(deps: Deps) -> StdResult<PausedResponse> {
+        Ok(PausedResponse {
+            paused: PAUSED.may_load(deps.storage)?.unwrap_or(false),
+        })
+    }
+
+    pub fn get_dead_man_switch(deps: Deps, env: Env) -> StdResult<DeadManSwitchResponse> {
+        let switch = DEAD_MAN_SWITCH.load(deps.storage)?;
+        let deadline = switch.last_heartbeat.plus_seconds(switch.interval);
+        let votes = RECOVERY_VOTES
+            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .map(|item| {
+                let (guardian, new_owner) = item?;
+                Ok(RecoveryVote {
+                    guardian: guardian.to_string(),
+                    new_owner: new_owner.to_string(),
+                })
+            })
+            .collect::<StdResult<Vec<RecoveryVote>>>()?;
+        Ok(DeadManSwitchResponse {
+            interval: switch.interval,
+            guardians: switch.guardians.iter().map(|x| x.to_string()).collect(),
+            threshold: switch.threshold,
+            recovery_period: switch.recovery_period,
+            beneficiary: switch.beneficiary.to_string(),
+            last_heartbeat: switch.last_heartbeat,
+            seconds_remaining: deadline.seconds().saturating_sub(env.block.time.seconds()),
+            lapsed: env.block.time > deadline,
+            wind_down: switch.wind_down,
+            votes,
+        })
+    }
+
+    pub fn get_closure(deps: Deps) -> StdResult<ClosureResponse> {
+        Ok(match CLOSURE.may_load(deps.storage)? {
+            Some(closure) => ClosureResponse {
+                closed: true,
+                finalized: closure.finalized,
+                refund_pool: closure.refund_pool,
+                total_deposits: closure.total_deposits,
+            },
+            None => ClosureResponse {
+                closed: false,
+                finalized: false,
+                refund_pool: Uint128::zero(),
+                total_deposits: Uint128::zero(),
+            },
+        })
+    }
+
+    pub fn get_refund(deps: Deps, address: String) -> StdResult<RefundResponse> {
+        let address = deps.api.addr_validate(address.as_str())?;
+        if let Some(amount) = REFUND_CLAIMS.may_load(deps.storage, address.clone())? {
+            return Ok(RefundResponse {
+                amount,
+                claimed: true,
+            });
+        }
+        let amount = match CLOSURE.may_load(deps.storage)? {
+            Some(closure) => super::refund_amount(
+                &closure,
+                DEPOSITED
+                    .may_load(deps.storage, address)?
+                    .unwrap_or_default(),
+            ),
+            None => Uint128::zero(),
+        };
+        Ok(RefundResponse {
+            amount,
+            claimed: false,
+        })
+    }
+
+    pub fn get_remaining_quota(
+        deps: Deps,
+        env: Env,
+        spender: Option<String>,
+    ) -> StdResult<RemainingQuotaResponse> {
+        let limits = match RATE_LIMITS.may_load(deps.storage)? {
+            Some(value) => value,
+            None => {
+                return Ok(RemainingQuotaResponse {
+                    spender_remaining: None,
+                    daily_outflow_remaining: None,
+                })
+            }
+        };
+        let spender_remaining = match (spender, limits.max_claim_per_window) {
+            (Some(spender), Some(max_amount)) => {
+                let address = deps.api.addr_validate(spender.as_str())?;
+                let used = super::spender_outflow(deps.storage, &address, env.block.time, &limits)?;
+                Some(max_amount.saturating_sub(used))
+            }
+            _ => None,
+        };
+        let daily_outflow_remaining = super::daily_outflow_remaining(deps.storage, env.block.time)?;
+        Ok(RemainingQuotaResponse {
+            spender_remaining,
+            daily_outflow_remaining,
+        })
+    }
+
+    fn budget_response(name: String, budget: Budget) -> BudgetResponse {
+        BudgetResponse {
+            name,
+            allocated: budget.allocated,
+            spent: budget.spent,
+            reserved: budget.reserved,
+        }
+    }
+
+    pub fn get_budget(deps: Deps, name: String) -> StdResult<BudgetResponse> {
+        let budget = BUDGETS.load(deps.storage, name.clone())?;
+        Ok(budget_response(name, budget))
+    }
+
+    pub fn get_budgets(deps: Deps) -> StdResult<BudgetsResponse> {
+        let budgets = BUDGETS
+            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .map(|item| {
+                let (name, budget) = item?;
+                Ok(budget_response(name, budget))
+            })
+            .collect::<StdResult<Vec<BudgetResponse>>>()?;
+        Ok(BudgetsResponse { budgets })
+    }
+
+    pub fn get_reallocations(deps: Deps) -> StdResult<ReallocationsResponse> {
+        let reallocations = REALLOCATIONS
+            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .map(|item| {
+                let (id, reallocation) = item?;
+                Ok(ReallocationResponse {
+                    id,
+                    from: reallocation.from,
+                    to: reallocation.to,
+                    amount: reallocation.amount,
+                    time: reallocation.time,
+                })
+            })
+            .collect::<StdResult<Vec<ReallocationResponse>>>()?;
+        Ok(ReallocationsResponse { reallocations })
+    }
+
+    pub fn get_campaign(deps: Deps, env: Env) -> StdResult<CampaignResponse> {
+        Ok(match CAMPAIGN.may_load(deps.storage)? {
+            Some(campaign) => CampaignResponse {
+                active: super::campaign_locked(&campaign, env.block.time),
+                failed: super::campaign_failed(&campaign, env.block.time),
+                goal: campaign.goal,
+                deadline: campaign.deadline,
+                reclaim_deadline: campaign
+                    .deadline
+                    .plus_seconds(super::CAMPAIGN_RECLAIM_SECONDS),
+                raised: campaign.raised,
+                reclaimed: campaign.reclaimed,
+                unlocked: campaign.unlocked,
+            },
+            None => CampaignResponse {
+                active: false,
+                goal: Uint128::zero(),
+                deadline: Timestamp::default(),
+                reclaim_deadline: Timestamp::default(),
+                raised: Uint128::zero(),
+                reclaimed: Uint128::zero(),
+                unlocked: false,
+                failed: false,
+            },
+        })
+    }
+
+    fn pledge_response(
+        deps: Deps,
+        env: &Env,
+        sponsor: Addr,
+        pledge: Pledge,
+        depositor: Option<Addr>,
+    ) -> StdResult<PledgeResponse> {
+        let expired = pledge.expires <= env.block.time;
+        let depositor_remaining = match depositor {
+            Some(depositor) => {
+                let matched = PLEDGE_MATCHES
+                    .may_load(deps.storage, (sponsor.clone(), depositor))?
+                    .unwrap_or_default();
+                Some(if expired {
+                    Uint128::zero()
+                } else {
+                    pledge
+                        .cap_per_depositor
+                        .saturating_sub(matched)
+                        .min(pledge.budget)
+                })
+            }
+            None => None,
+        };
+        Ok(PledgeResponse {
+            sponsor: sponsor.to_string(),
+            ratio: pledge.ratio,
+            cap_per_depositor: pledge.cap_per_depositor,
+            budget: pledge.budget,
+            matched: pledge.matched,
+            expires: pledge.expires,
+            expired,
+            depositor_remaining,
+        })
+    }
+
+    pub fn get_pledge(
+        deps: Deps,
+        env: Env,
+        sponsor: String,
+        depositor: Option<String>,
+    ) -> StdResult<PledgeResponse> {
+        let sponsor = deps.api.addr_validate(sponsor.as_str())?;
+        let depositor = depositor
+            .map(|depositor| deps.api.addr_validate(depositor.as_str()))
+            .transpose()?;
+        let pledge = PLEDGES.load(deps.storage, sponsor.clone())?;
+        pledge_response(deps, &env, sponsor, pledge, depositor)
+    }
+
+    pub fn get_pledges(deps: Deps, env: Env) -> StdResult<PledgesResponse> {
+        let pledges = PLEDGES
+            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .map(|item| {
+                let (sponsor, pledge) = item?;
+                pledge_response(deps, &env, sponsor, pledge, None)
+            })
+            .collect::<StdResult<Vec<_>>>()?;
+        Ok(PledgesResponse { pledges })
+    }
+
+    pub fn get_positions(deps: Deps, env: Env, owner: String) -> StdResult<PositionsResponse> {
+        let owner = deps.api.addr_validate(owner.as_str())?;
+        let positions = POSITIONS
+            .prefix(owner)
+            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .map(|item| {
+                let (id, position) = item?;
+                Ok(PositionResponse {
+                    id,
+                    amount: position.amount,
+                    deposited_at: position.deposited_at,
+                    unlocks_at: position.unlocks_at,
+                    penalty: position.penalty,
+                    unlocked: env.block.time >= position.unlocks_at,
+                })
+            })
+            .collect::<StdResult<Vec<_>>>()?;
+        Ok(PositionsResponse {
+            early_exit_penalty: EARLY_EXIT_PENALTY.may_load(deps.storage)?,
+            positions,
+        })
+    }
+
+    pub fn get_fee_config(deps: Deps) -> StdResult<FeeConfigResponse> {
+        let accrued = ACCRUED_FEES.may_load(deps.storage)?.unwrap_or_default();
+        Ok(match FEE_CONFIG.may_load(deps.storage)? {
+            Some(config) => FeeConfigResponse {
+                deposit_bps: config.deposit_bps,
+                claim_bps: config.claim_bps,
+                min_fee: config.min_fee,
+                max_fee: config.max_fee,
+                treasury: config.treasury.map(|treasury| treasury.to_string()),
+                accrued,
+            },
+            None => FeeConfigResponse {
+                deposit_bps: 0,
+                claim_bps: 0,
+                min_fee: Uint128::zero(),
+                max_fee: None,
+                treasury: None,
+                accrued,
+            },
+        })
+    }
+
+    pub fn get_withholding_report(
+        deps: Deps,
+        env: Env,
+        spender: String,
+        year: Option<u32>,
+    ) -> StdResult<WithholdingReportResponse> {
+        let address = deps.api.addr_validate(spender.as_str())?;
+        let year = year.unwrap_or_else(|| super::calendar_year(env.block.time));
+        let totals = WITHHOLDING_TOTALS
+            .may_load(deps.storage, (address.clone(), year))?
+            .unwrap_or_default();
+        let rule = match WITHHOLDING_RULES.may_load(deps.storage, address)? {
+            Some(rule) => Some(rule),
+            None => GLOBAL_WITHHOLDING.may_load(deps.storage)?,
+        };
+        Ok(WithholdingReportResponse {
+            spender,
+            year,
+            claimed: totals.claimed,
+            withheld: totals.withheld,
+            rate: rule.as_ref().map(|rule| rule.rate),
+            destination: rule.as_ref().map(|rule| rule.destination.to_string()),
+            brackets: rule
+                .map(|rule| {
+                    rule.brackets
+                        .into_iter()
+                        .map(|bracket| WithholdingBracketMsg {
+                            threshold: bracket.threshold,
+                            rate: bracket.rate,
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+        })
+    }
+
+    pub fn get_compliance(deps: Deps) -> StdResult<ComplianceResponse> {
+        let denied = DENY_LIST
+            .keys(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .map(|item| item.map(|address| address.to_string()))
+            .collect::<StdResult<Vec<_>>>()?;
+        let frozen = FROZEN_ALLOWANCES
+            .keys(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .map(|item| item.map(|address| address.to_string()))
+            .collect::<StdResult<Vec<_>>>()?;
+        Ok(ComplianceResponse {
+            registry: COMPLIANCE_REGISTRY
+                .may_load(deps.storage)?
+                .map(|registry| registry.to_string()),
+            denied,
+            frozen,
+        })
+    }
+}
+
+pub mod reply {
+    use super::execute;
+    use crate::error::ContractError;
+    use crate::state::{
+        OUTGOING_TRANSFER, PENDING_SWAP, PENDING_TRANSFERS, STAKING_BALANCE, STATE,
+    };
+    use cosmwasm_std::{DepsMut, Env, Reply, Response, SubMsgResult, Uint128};
+
+    pub fn staking_reply(deps: DepsMut, env: Env) -> Result<Response, ContractError> {
+        let mut current_state = STATE.load(deps.storage)?;
+        let expected = STAKING_BALANCE.load(deps.storage)?;
+        let balance = deps
+            .querier
+            .query_balance(&env.contract.address, current_state.expected_denom.clone())?
+            .amount;
+        let rewards = balance.saturating_sub(expected);
+        current_state.total_amount += rewards;
+        STATE.save(deps.storage, &current_state)?;
+        // The next staking message in the same transaction starts from here.
+        STAKING_BALANCE.save(deps.storage, &balance)?;
+        Ok(Response::new()
+            .add_attribute("action", "staking_reply")
+            .add_attribute("rewards", rewards))
+    }
+
+    /// Reads the `sequence` field of the protobuf `MsgTransferResponse`.
+    fn parse_transfer_sequence(data: &[u8]) -> Option<u64> {
+        if data.first() != Some(&0x08) {
+            return None;
+        }
+        let mut sequence = 0u64;
+        for (index, byte) in data[1..].iter().take(10).enumerate() {
+            sequence |= ((byte & 0x7f) as u64) << (7 * index);
+            if byte & 0x80 == 0 {
+                return Some(sequence);
+            }
+        }
+        None
+    }
+
+    pub fn ibc_transfer_reply(deps: DepsMut, msg: Reply) -> Result<Response, ContractError> {
+        let transfer = OUTGOING_TRANSFER.load(deps.storage)?;
+        OUTGOING_TRANSFER.remove(deps.storage);
+        let sequence = match msg.result.into_result() {
+            Ok(response) => response
+                .data
+                .and_then(|data| parse_transfer_sequence(data.as_slice())),
+            Err(_) => None,
+        };
+        let sequence = match sequence {
+            Some(value) => value,
+            None => return Err(ContractError::MissingTransferSequence {}),
+        };
+        PENDING_TRANSFERS.save(
+            deps.storage,
+            (transfer.channel_id.clone(), sequence),
+            &transfer,
+        )?;
+        Ok(Response::new()
+            .add_attribute("action", "ibc_transfer_reply")
+            .add_attribute("channel_id", transfer.channel_id)
+            .add_attribute("sequence", sequence.to_string()))
+    }
+
+    /// Credits a swapped deposit with the `return_amount` reported by the pair.
+    pub fn swap_reply(mut deps: DepsMut, env: Env, msg: Reply) -> Result<Response, ContractError> {
+        let pending = PENDING_SWAP.load(deps.storage)?;
+        PENDING_SWAP.remove(deps.storage);
+        // Only the pair's own event counts, contracts it calls cannot fake the amount.
+        let router = pending.router.as_str();
+        let return_amount = msg
+            .result
+            .into_result()
+            .ok()
+            .and_then(|response| {
+                response
+                    .events
+                    .iter()
+                    .filter(|event| {
+                        event.ty == "wasm"
+                            && event.attributes.iter().any(|attribute| {
+                                attribute.key == "_contract_address" && attribute.value == router
+                            })
+                    })
+                    .flat_map(|event| event.attributes.iter())
+                    .find(|attribute| attribute.key == "return_amount")
+                    .and_then(|attribute| attribute.value.parse::<u128>().ok())
+            })
+            .map(Uint128::from);
+        let return_amount = match return_amount {
+            Some(value) => value,
+            None => return Err(ContractError::MissingSwapReturn {}),
+        };
+        if return_amount < pending.min_return {
+            return Err(ContractError::SlippageExceeded {
+                min_return: pending.min_return.u128(),
+            });
+        }
+        let response = execute::credit_deposit(
+            deps.branch(),
+            &env,
+            pending.depositor.clone(),
+            return_amount,
+        )?
+        .add_attribute("offer_amount", pending.offer.amount)
+        .add_attribute("offer_denom", pending.offer.denom);
+        execute::match_deposit(deps, &env, pending.depositor, return_amount, response)
+    }
+
+    /// A non-blocking hook failed. Its changes are reverted and the action goes on.
+    pub fn hook_reply(msg: Reply) -> Result<Response, ContractError> {
+        let error = match msg.result {
+            SubMsgResult::Err(error) => error,
+            SubMsgResult::Ok(_) => return Err(ContractError::UnknownReplyId { id: msg.id }),
+        };
+        Ok(Response::new()
+            .add_attribute("action", "hook_failed")
+            .add_attribute("error", error))
+    }
+}
+
+pub mod sudo {
+    use crate::error::ContractError;
+    use crate::msg::{IbcLifecycleComplete, VaultHookMsg};
+    use crate::state::{
+        State, ACCRUED_FEES, ALLOWANCES, CLOSURE, DISTRIBUTIONS, ESCROW, GRANTS, HELD_WITHHOLDING,
+        PAUSED, PEGGED_ALLOWANCES, PENDING_TRANSFERS, PLEDGED_TOTAL, PLEDGES, PLEDGE_EXPIRIES,
+        PLEDGE_MATCHES, POSITIONS, STATE, VAULTS, VAULT_CHANNELS, WITHHOLDING_TOTALS,
+    };
+    use cosmwasm_std::{coins, BankMsg, DepsMut, Env, Event, Response, StdResult, Uint128};
+
+    /// Failed and timed out transfers are refunded to the vault by the transfer
+    /// module, so the amount goes back to the pool and can be claimed again.
+    pub fn ibc_lifecycle_complete(
+        deps: DepsMut,
+        lifecycle: IbcLifecycleComplete,
+    ) -> Result<Response, ContractError> {
+        let (channel, sequence, success) = match lifecycle {
+            IbcLifecycleComplete::IbcAck {
+                channel,
+                sequence,
+                success,
+                ..
+            } => (channel, sequence, success),
+            IbcLifecycleComplete::IbcTimeout { channel, sequence } => (channel, sequence, false),
+        };
+        let transfer = match PENDING_TRANSFERS
+            .may_load(deps.storage, (channel.clone(), sequence))?
+        {
+            Some(value) => value,
+            None => return Ok(Response::new().add_attribute("action", "ibc_lifecycle_complete")),
+        };
+        PENDING_TRANSFERS.remove(deps.storage, (channel.clone(), sequence));
+
+        let mut response = Response::new()
+            .add_attribute("action", "ibc_lifecycle_complete")
+            .add_attribute("channel_id", channel)
+            .add_attribute("sequence", sequence.to_string())
+            .add_attribute("success", success.to_string());
+        let mut current_state = STATE.load(deps.storage)?;
+        let mut withheld = Uint128::zero();
+        if let Some(withholding) = &transfer.withholding {
+            withheld = withholding.amount;
+            let held = HELD_WITHHOLDING.may_load(deps.storage)?.unwrap_or_default();
+            HELD_WITHHOLDING.save(deps.storage, &held.saturating_sub(withheld))?;
+            if success && !withheld.is_zero() {
+                response = response.add_message(BankMsg::Send {
+                    to_address: withholding.destination.to_string(),
+                    amount: coins(withheld.u128(), current_state.expected_denom.clone()),
+                });
+            } else if !success {
+                // The claim did not happen, so it leaves the year's totals.
+                let key = (transfer.spender.clone(), withholding.year);
+                if let Some(mut totals) = WITHHOLDING_TOTALS.may_load(deps.storage, key.clone())? {
+                    totals.claimed = totals.claimed.saturating_sub(transfer.amount + withheld);
+                    totals.withheld = totals.withheld.saturating_sub(withheld);
+                    WITHHOLDING_TOTALS.save(deps.storage, key, &totals)?;
+                }
+            }
+        }
+        if !success {
+            let amount = transfer.amount + withheld;
+            current_state.total_amount += amount;
+            STATE.save(deps.storage, &current_state)?;
+            if let Some(channel_id) = transfer.vault_channel_id.clone() {
+                if let Some(mut channel) =
+                    VAULT_CHANNELS.may_load(deps.storage, channel_id.clone())?
+                {
+                    channel.sent = channel.sent.saturating_sub(transfer.amount);
+                    VAULT_CHANNELS.save(deps.storage, channel_id, &channel)?;
+                }
+            } else if !ALLOWANCES.has(deps.storage, transfer.spender.clone())
+                && !PEGGED_ALLOWANCES.has(deps.storage, transfer.spender.clone())
+            {
+                // A closed vault consumed the allowance on the claim, so it is
+                // handed back to be claimed again. A standing allowance is intact.
+                super::save_allowance(deps.storage, &transfer.spender, amount)?;
+                super::sync_budget(deps.storage, &transfer.spender)?;
+            }
+        } else if let Some(mut closure) = CLOSURE.may_load(deps.storage)? {
+            super::execute::finalize_if_settled(deps.storage, &mut closure)?;
+            response = response
+                .add_attribute("spender", transfer.spender.to_string())
+                .add_attribute("refunded_amount", transfer.amount);
+        }
+        Ok(response)
+    }
+
+    pub fn force_owner_change(
+        deps: DepsMut,
+        env: Env,
+        owner: String,
+    ) -> Result<Response, ContractError> {
+        let mut current_state = STATE.load(deps.storage)?;
+        let address = match deps.api.addr_validate(owner.as_str()) {
+            Ok(value) => value,
+            Err(_) => return Err(ContractError::NotValidAddress { address: owner }),
+        };
+        let previous_owner = current_state.owner.clone();
+        super::change_owner(deps.storage, &mut current_state, address, env.block.time)?;
+        Ok(Response::new().add_event(
+            Event::new("vault_sudo")
+                .add_attribute("action", "force_owner_change")
+                .add_attribute("previous_owner", previous_owner.to_string())
+                .add_attribute("owner", owner),
+        ))
+    }
+
+    pub fn set_paused(deps: DepsMut, paused: bool) -> Result<Response, ContractError> {
+        PAUSED.save(deps.storage, &paused)?;
+        let action = if paused { "pause" } else { "unpause" };
+        Ok(Response::new().add_event(Event::new("vault_sudo").add_attribute("action", action)))
+    }
+
+    /// Unlike `Withdraw`, recovery also takes the escrowed funds.
+    pub fn recover_funds(
+        deps: DepsMut,
+        env: Env,
+        recipient: String,
+    ) -> Result<Response, ContractError> {
+        let mut current_state = STATE.load(deps.storage)?;
+        if deps.api.addr_validate(recipient.as_str()).is_err() {
+            return Err(ContractError::NotValidAddress { address: recipient });
+        }
+        // The share holders' funds are not the owner's to recover.
+        super::check_no_shares(deps.storage)?;
+        let balance = deps.querier.query_all_balances(&env.contract.address)?;
+
+        current_state.total_amount = Uint128::zero();
+        STATE.save(deps.storage, &current_state)?;
+        // Grants and distribution rounds lose the escrow that backed them.
+        ESCROW.remove(deps.storage);
+        let grants = GRANTS
+            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()?;
+        for (id, mut grant) in grants {
+            if !grant.cancelled {
+                grant.cancelled = true;
+                GRANTS.save(deps.storage, id, &grant)?;
+            }
+        }
+        let distributions = DISTRIBUTIONS
+            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()?;
+        for (round, mut distribution) in distributions {
+            if !distribution.clawed_back {
+                distribution.clawed_back = true;
+                DISTRIBUTIONS.save(deps.storage, round, &distribution)?;
+            }
+        }
+        HELD_WITHHOLDING.remove(deps.storage);
+        PLEDGES.clear(deps.storage);
+        PLEDGE_EXPIRIES.clear(deps.storage);
+        PLEDGED_TOTAL.remove(deps.storage);
+        PLEDGE_MATCHES.clear(deps.storage);
+        POSITIONS.clear(deps.storage);
+        ACCRUED_FEES.remove(deps.storage);
+        let vaults = VAULTS
+            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .collect::<StdResult<Vec<(u64, State)>>>()?;
+        for (id, mut vault) in vaults {
+            vault.total_amount = Uint128::zero();
+            VAULTS.save(deps.storage, id, &vault)?;
+        }
+
+        let mut event = Event::new("vault_sudo")
+            .add_attribute("action", "recover_funds")
+            .add_attribute("recipient", recipient.clone());
+        for coin in balance.iter() {
+            event = event.add_attribute("amount", coin.to_string());
+        }
+        let mut response = Response::new().add_event(event);
+        if !balance.is_empty() {
+            response = response
+                .add_message(BankMsg::Send {
+                    to_address: recipient.clone(),
+                    amount: balance.clone(),
+                })
+                .add_submessages(super::hook_messages(
+                    deps.storage,
+                    VaultHookMsg::Withdraw {
+                        owner: recipient,
+                        amount: balance,
+                    },
+                )?);
+        }
+        Ok(response)
+    }
+}
+
+/// Sub-vaults with their own owner, denom, deposit addresses and allowances.
+/// The other features work on the vault created at instantiation.
+pub mod vaults {
+    use crate::error::ContractError;
+    use crate::msg::{
+        AllowanceResponse, AllowancesResponse, CanDepositResponse, DepositAddressesResponse,
+        StateResponse, VaultHookMsg, VaultInfo, VaultsResponse,
+    };
+    use crate::state::{
+        State, STATE, VAULTS, VAULT_ALLOWANCES, VAULT_COUNT, VAULT_DEPOSIT_ADDRESSES,
+    };
+    use cosmwasm_std::{
+        coins, Addr, BankMsg, Deps, DepsMut, Env, MessageInfo, Response, StdResult, Storage,
+        Uint128,
+    };
+
+    fn load_vault(storage: &dyn Storage, id: u64) -> Result<State, ContractError> {
+        match VAULTS.may_load(storage, id)? {
+            Some(value) => Ok(value),
+            None => Err(ContractError::VaultNotFound { id }),
+        }
+    }
+
+    fn load_owned_vault(
+        storage: &dyn Storage,
+        info: &MessageInfo,
+        id: u64,
+    ) -> Result<State, ContractError> {
+        let vault = load_vault(storage, id)?;
+        if info.sender != vault.owner {
+            return Err(ContractError::NotOwner {
+                owner: vault.owner.to_string(),
+            });
+        }
+        Ok(vault)
+    }
+
+    /// Vault 0 is the vault created at instantiation.
+    fn load_any_vault(storage: &dyn Storage, id: u64) -> Result<State, ContractError> {
+        if id == 0 {
+            return Ok(STATE.load(storage)?);
+        }
+        load_vault(storage, id)
+    }
+
+    fn save_any_vault(storage: &mut dyn Storage, id: u64, vault: &State) -> StdResult<()> {
+        if id == 0 {
+            return STATE.save(storage, vault);
+        }
+        VAULTS.save(storage, id, vault)
+    }
+
+    fn free_amount(storage: &dyn Storage, id: u64, vault: &State) -> StdResult<Uint128> {
+        if id == 0 {
+            return super::free_amount(storage, vault);
+        }
+        let reserved = VAULT_ALLOWANCES
+            .prefix(id)
+            .range(storage, None, None, cosmwasm_std::Order::Ascending)
+            .try_fold(Uint128::zero(), |total, item| {
+                let (_, amount) = item?;
+                StdResult::Ok(total + amount)
+            })?;
+        Ok(vault.total_amount.saturating_sub(reserved))
+    }
+
+    /// Funds of all sub-vaults in `denom`.
+    pub fn locked_amount(storage: &dyn Storage, denom: &str) -> StdResult<Uint128> {
+        VAULTS
+            .range(storage, None, None, cosmwasm_std::Order::Ascending)
+            .try_fold(Uint128::zero(), |total, item| {
+                let (_, vault) = item?;
+                if vault.expected_denom != denom {
+                    return Ok(total);
+                }
+                StdResult::Ok(total + vault.total_amount)
+            })
+    }
+
+    pub fn create_vault(
+        deps: DepsMut,
+        info: MessageInfo,
+        name: String,
+        expected_denom: String,
+    ) -> Result<Response, ContractError> {
+        let id = VAULT_COUNT.may_load(deps.storage)?.unwrap_or_default() + 1;
+        VAULT_COUNT.save(deps.storage, &id)?;
+        VAULTS.save(
+            deps.storage,
+            id,
+            &State {
+                owner: info.sender.clone(),
+                name,
+                total_amount: Uint128::zero(),
+                expected_denom,
+            },
+        )?;
+        VAULT_DEPOSIT_ADDRESSES.save(deps.storage, (id, info.sender.clone()), &Uint128::zero())?;
+        Ok(Response::new()
+            .add_attribute("action", "create_vault")
+            .add_attribute("vault_id", id.to_string())
+            .add_attribute("owner", info.sender.to_string()))
+    }
+
+    pub fn deposit(deps: DepsMut, info: MessageInfo, id: u64) -> Result<Response, ContractError> {
+        super::screen(deps.as_ref(), &[info.sender.as_str()])?;
+        let mut vault = load_vault(deps.storage, id)?;
+        let amount = match info
+            .funds
+            .iter()
+            .find(|coin| coin.denom == vault.expected_denom)
+        {
+            Some(coin) => coin.amount,
+            None => {
+                return Err(ContractError::InvalidDenom {
+                    denom: vault.expected_denom,
+                })
+            }
+        };
+        let key = (id, info.sender.clone());
+        let value = match VAULT_DEPOSIT_ADDRESSES.may_load(deps.storage, key.clone())? {
+            Some(value) => value,
+            None => {
+                return Err(ContractError::UnauthorizedDepositAddress {
+                    address: info.sender.to_string(),
+                })
+            }
+        };
+        VAULT_DEPOSIT_ADDRESSES.save(deps.storage, key, &(value + amount))?;
+        vault.total_amount += amount;
+        VAULTS.save(deps.storage, id, &vault)?;
+        Ok(Response::new()
+            .add_attribute("action", "deposit")
+            .add_attribute("vault_id", id.to_string())
+            .add_attribute("address", info.sender.to_string())
+            .add_attribute("amount", amount))
+    }
+
+    pub fn add_deposit_address(
+        deps: DepsMut,
+        info: MessageInfo,
+        id: u64,
+        deposit_address: String,
+    ) -> Result<Response, ContractError> {
+        load_owned_vault(deps.storage, &info, id)?;
+        let address = match deps.api.addr_validate(deposit_address.as_str()) {
+            Ok(value) => value,
+            Err(_) => {
+                return Err(ContractError::NotValidAddress {
+                    address: deposit_address,
+                })
+            }
+        };
+        VAULT_DEPOSIT_ADDRESSES.save(deps.storage, (id, address), &Uint128::zero())?;
+        Ok(Response::new()
+            .add_attribute("action", "add_deposit_address")
+            .add_attribute("vault_id", id.to_string())
+            .add_attribute("address", deposit_address))
+    }
+
+    pub fn remove_deposit_address(
+        deps: DepsMut,
+        info: MessageInfo,
+        id: u64,
+        deposit_address: String,
+    ) -> Result<Response, ContractError> {
+        load_owned_vault(deps.storage, &info, id)?;
+        let address = match deps.api.addr_validate(deposit_address.as_str()) {
+            Ok(value) => value,
+            Err(_) => {
+                return Err(ContractError::NotValidAddress {
+                    address: deposit_address,
+                })
+            }
+        };
+        VAULT_DEPOSIT_ADDRESSES.remove(deps.storage, (id, address));
+        Ok(Response::new()
+            .add_attribute("action", "remove_deposit_address")
+            .add_attribute("vault_id", id.to_string())
+            .add_attribute("address", deposit_address))
+    }
+
+    pub fn withdraw(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        id: u64,
+    ) -> Result<Response, ContractError> {
+        let mut vault = load_owned_vault(deps.storage, &info, id)?;
+        super::screen(deps.as_ref(), &[info.sender.as_str()])?;
+        let mut amount = vault.total_amount;
+        if let Some(remaining) = super::daily_outflow_remaining(deps.storage, env.block.time)? {
+            amount = amount.min(remaining);
+        }
+        super::record_outflow(deps.storage, None, amount, env.block.time)?;
+        vault.total_amount -= amount;
+        VAULTS.save(deps.storage, id, &vault)?;
+
+        let mut response = Response::new()
+            .add_attribute("action", "withdraw")
+            .add_attribute("vault_id", id.to_string())
+            .add_attribute("amount", amount);
+        if !amount.is_zero() {
+            response = response.add_message(BankMsg::Send {
+                to_address: info.sender.to_string(),
+                amount: coins(amount.u128(), vault.expected_denom),
+            });
+        }
+        Ok(response)
+    }
+
+    /// Adds or updates an allowance, `action` tells which.
+    pub fn save_allowance(
+        deps: DepsMut,
+        info: MessageInfo,
+        id: u64,
+        spender: String,
+        amount: Uint128,
+        action: &str,
+    ) -> Result<Response, ContractError> {
+        load_owned_vault(deps.storage, &info, id)?;
+        let address = match deps.api.addr_validate(spender.as_str()) {
+            Ok(value) => value,
+            Err(_) => return Err(ContractError::NotValidAddress { address: spender }),
+        };
+        VAULT_ALLOWANCES.save(deps.storage, (id, address), &amount)?;
+        Ok(Response::new()
+            .add_attribute("action", action)
+            .add_attribute("vault_id", id.to_string())
+            .add_attribute("spender", spender)
+            .add_attribute("amount", amount))
+    }
+
+    pub fn add_allowance_list(
+        deps: DepsMut,
+        info: MessageInfo,
+        id: u64,
+        spenders: Vec<String>,
+        amounts: Vec<Uint128>,
+    ) -> Result<Response, ContractError> {
+        load_owned_vault(deps.storage, &info, id)?;
+        if spenders.len() != amounts.len() {
+            return Err(ContractError::AllowanceAddressesAmountsNotEqual {});
+        }
+        for (spender, amount) in spenders.into_iter().zip(amounts) {
+            let address = match deps.api.addr_validate(spender.as_str()) {
+                Ok(value) => value,
+                Err(_) => return Err(ContractError::NotValidAddress { address: spender }),
+            };
+            VAULT_ALLOWANCES.save(deps.storage, (id, address), &amount)?;
+        }
+        Ok(Response::new()
+            .add_attribute("action", "add_allowance_list")
+            .add_attribute("vault_id", id.to_string()))
+    }
+
+    pub fn remove_allowance(
+        deps: DepsMut,
+        info: MessageInfo,
+        id: u64,
+        spender: String,
+    ) -> Result<Response, ContractError> {
+        load_owned_vault(deps.storage, &info, id)?;
+        let address = match deps.api.addr_validate(spender.as_str()) {
+            Ok(value) => value,
+            Err(_) => return Err(ContractError::NotValidAddress { address: spender }),
+        };
+        VAULT_ALLOWANCES.remove(deps.storage, (id, address));
+        Ok(Response::new()
+            .add_attribute("action", "remove_allowance")
+            .add_attribute("vault_id", id.to_string())
+            .add_attribute("spender", spender))
+    }
+
+    pub fn retrieve_allowance(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        id: u64,
+    ) -> Result<Response, ContractError> {
+        // Freezes are set by the owner of the default vault and only apply there.
+        super::screen(deps.as_ref(), &[info.sender.as_str()])?;
+        let mut vault = load_vault(deps.storage, id)?;
+        let allowance = match VAULT_ALLOWANCES.may_load(deps.storage, (id, info.sender.clone()))? {
+            Some(value) => value,
+            None => {
+                return Err(ContractError::NoAllowance {
+                    address: info.sender.to_string(),
+                })
+            }
+        };
+        // A sub-vault never pays with the funds of another vault.
+        if allowance > vault.total_amount {
+            return Err(ContractError::NotEnoughFunds {
+                total_amount: vault.total_amount.u128(),
+            });
+        }
+        super::record_outflow(deps.storage, Some(&info.sender), allowance, env.block.time)?;
+        vault.total_amount -= allowance;
+        VAULTS.save(deps.storage, id, &vault)?;
+        Ok(Response::new()
+            .add_message(BankMsg::Send {
+                to_address: info.sender.to_string(),
+                amount: coins(allowance.u128(), vault.expected_denom),
+            })
+            .add_attribute("action", "retrieve_allowance")
+            .add_attribute("vault_id", id.to_string())
+            .add_attribute("address", info.sender.to_string())
+            .add_attribute("amount", allowance))
+    }
+
+    pub fn update_name(
+        deps: DepsMut,
+        info: MessageInfo,
+        id: u64,
+        name: String,
+    ) -> Result<Response, ContractError> {
+        let mut vault = load_owned_vault(deps.storage, &info, id)?;
+        vault.name = name;
+        VAULTS.save(deps.storage, id, &vault)?;
+        Ok(Response::new()
+            .add_attribute("action", "update_name")
+            .add_attribute("vault_id", id.to_string()))
+    }
+
+    pub fn update_owner(
+        deps: DepsMut,
+        info: MessageInfo,
+        id: u64,
+        owner: String,
+    ) -> Result<Response, ContractError> {
+        let mut vault = load_owned_vault(deps.storage, &info, id)?;
+        let new_owner = deps.api.addr_validate(owner.as_str())?;
+
+        VAULT_DEPOSIT_ADDRESSES.remove(deps.storage, (id, vault.owner));
+        VAULT_DEPOSIT_ADDRESSES.save(deps.storage, (id, new_owner.clone()), &Uint128::zero())?;
+
+        vault.owner = new_owner;
+        VAULTS.save(deps.storage, id, &vault)?;
+        Ok(Response::new()
+            .add_attribute("action", "update_owner")
+            .add_attribute("vault_id", id.to_string())
+            .add_attribute("owner", owner))
+    }
+
+    pub fn transfer_between_vaults(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        from_vault: u64,
+        to_vault: u64,
+        amount: Uint128,
+    ) -> Result<Response, ContractError> {
+        let mut from = load_any_vault(deps.storage, from_vault)?;
+        let to = load_any_vault(deps.storage, to_vault)?;
+        for vault in [&from, &to] {
+            if info.sender != vault.owner {
+                return Err(ContractError::NotOwner {
+                    owner: vault.owner.to_string(),
+                });
+            }
+        }
+        if from.expected_denom != to.expected_denom {
+            return Err(ContractError::VaultDenomMismatch {});
+        }
+        // Funds promised as allowance stay in the vault.
+        let free = free_amount(deps.storage, from_vault, &from)?;
+        if amount > free {
+            return Err(ContractError::NotEnoughFunds {
+                total_amount: free.u128(),
+            });
+        }
+        // Leaving the default vault is a withdrawal: it counts against the
+        // outflow limits, cannot take the funds behind shares and is reported
+        // to the hooks.
+        let mut response = Response::new();
+        if from_vault == 0 && to_vault != 0 {
+            super::check_no_shares(deps.storage)?;
+            super::record_outflow(deps.storage, None, amount, env.block.time)?;
+            response = response.add_submessages(super::hook_messages(
+                deps.storage,
+                VaultHookMsg::Withdraw {
+                    owner: info.sender.to_string(),
+                    amount: coins(amount.u128(), from.expected_denom.clone()),
+                },
+            )?);
+        }
+        from.total_amount -= amount;
+        save_any_vault(deps.storage, from_vault, &from)?;
+        // Loaded again in case both IDs are the same vault.
+        let mut to = load_any_vault(deps.storage, to_vault)?;
+        to.total_amount += amount;
+        save_any_vault(deps.storage, to_vault, &to)?;
+
+        Ok(response
+            .add_attribute("action", "transfer_between_vaults")
+            .add_attribute("from_vault", from_vault.to_string())
+            .add_attribute("to_vault", to_vault.to_string())
+            .add_attribute("amount", amount))
+    }
+
+    pub fn get_state(deps: Deps, id: u64) -> StdResult<StateResponse> {
+        let vault = VAULTS.load(deps.storage, id)?;
+        Ok(StateResponse {
+            owner: vault.owner.to_string(),
+            name: vault.name,
+            total_amount: vault.total_amount,
+            expected_denom: vault.expected_denom,
+        })
+    }
+
+    pub fn get_allowance(deps: Deps, id: u64, spender: String) -> StdResult<AllowanceResponse> {
+        let address = deps.api.addr_validate(spender.as_str())?;
+        let amount = VAULT_ALLOWANCES.load(deps.storage, (id, address))?;
+        Ok(AllowanceResponse {
+            spender,
+            amount,
+            frozen_since: None,
+            clawbacks: vec![],
+        })
+    }
+
+    pub fn get_allowances(deps: Deps, id: u64) -> StdResult<AllowancesResponse> {
+        let allowances = VAULT_ALLOWANCES
+            .prefix(id)
+            .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .collect::<StdResult<Vec<(Addr, Uint128)>>>()?;
+        Ok(AllowancesResponse {
+            spenders: allowances.iter().map(|(x, _)| x.to_string()).collect(),
+            amounts: allowances.into_iter().map(|(_, amount)| amount).collect(),
+        })
+    }
+
+    pub fn can_deposit(deps: Deps, id: u64, address: String) -> StdResult<CanDepositResponse> {
+        let address = deps.api.addr_validate(address.as_str())?;
+        Ok(CanDepositResponse {
+            can_deposit: VAULT_DEPOSIT_ADDRESSES.has(deps.storage, (id, address)),
+        })
+    }
+
+    pub fn get_deposit_addresses(deps: Deps, id: u64) -> StdResult<DepositAddressesResponse> {
+        let addresses = VAULT_DEPOSIT_ADDRESSES
+            .prefix(id)
+            .keys(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+            .collect::<StdResult<Vec<Addr>>>()?;
+        Ok(DepositAddressesResponse {
+            addresses: addresses.iter().map(|x| x.to_string()).collect(),
+        })
+    }
+
+    /// Every vault, starting with the vault created at instantiation.
+    pub fn get_vaults(deps: Deps) -> StdResult<VaultsResponse> {
+        let vaults = std::iter::once(Ok((0, STATE.load(deps.storage)?)))
+            .chain(VAULTS.range(deps.storage, None, None, cosmwasm_std::Order::Ascending))
+            .map(|item| {
+                let (id, vault) = item?;
+                Ok(VaultInfo {
+                    id,
+                    owner: vault.owner.to_string(),
+                    name: vault.name,
+                    total_amount: vault.total_amount,
+                    expected_denom: vault.expected_denom,
+                })
+            })
+            .collect::<StdResult<Vec<VaultInfo>>>()?;
+        Ok(VaultsResponse { vaults })
     }
 }
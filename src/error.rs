@@ -1,4 +1,4 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{StdError, Uint128};
 use thiserror::Error;
 
 #[derive(Error, Debug, PartialEq)]
@@ -38,4 +38,307 @@ pub enum ContractError {
 
     #[error("Invalid coin denom. The given coin type is not supported")]
     InvalidDenom { denom: String },
+
+    #[error("This address is not a registered vendor")]
+    VendorNotFound { address: String },
+
+    #[error("Invoice does not exist")]
+    InvoiceNotFound { id: u64 },
+
+    #[error("Invoice has already been paid")]
+    InvoiceAlreadyPaid { id: u64 },
+
+    #[error("Grant does not exist")]
+    GrantNotFound { id: u64 },
+
+    #[error("A grant needs at least one milestone")]
+    NoMilestones {},
+
+    #[error("Unauthorized - only the arbiter {arbiter} can complete milestones")]
+    NotArbiter { arbiter: String },
+
+    #[error("Milestones must be completed in order, the next one is {expected}")]
+    MilestoneOutOfOrder { expected: u32 },
+
+    #[error("Grant is already closed")]
+    GrantClosed { id: u64 },
+
+    #[error("No voucher public key has been registered")]
+    NoVoucherPubkey {},
+
+    #[error("Voucher signature is not valid")]
+    InvalidVoucherSignature {},
+
+    #[error("Voucher was issued for another vault")]
+    VoucherWrongVault { vault: String },
+
+    #[error("Voucher was issued for another recipient")]
+    VoucherWrongRecipient { recipient: String },
+
+    #[error("Voucher has expired")]
+    VoucherExpired { nonce: u64 },
+
+    #[error("Voucher nonce has already been used")]
+    VoucherNonceUsed { nonce: u64 },
+
+    #[error("Merkle root or proof is not a valid 32 byte hex string")]
+    InvalidMerkleHash { hash: String },
+
+    #[error("Distribution round does not exist")]
+    DistributionNotFound { round: u64 },
+
+    #[error("Merkle proof is not valid")]
+    InvalidMerkleProof {},
+
+    #[error("This address has already claimed from the distribution round")]
+    DistributionAlreadyClaimed { address: String },
+
+    #[error("Distribution round has expired")]
+    DistributionExpired { round: u64 },
+
+    #[error("Distribution round has not expired yet")]
+    DistributionNotExpired { round: u64 },
+
+    #[error("Distribution round budget is exhausted")]
+    DistributionBudgetExceeded { round: u64 },
+
+    #[error("Share mode is not enabled")]
+    ShareModeDisabled {},
+
+    #[error("Share mode is already enabled")]
+    ShareModeEnabled {},
+
+    #[error("Share mode can only be enabled while the vault is empty")]
+    VaultNotEmpty { total_amount: u128 },
+
+    #[error("Only the share token {token} can call this function")]
+    UnauthorizedShareToken { token: String },
+
+    #[error("Amount is too small to mint or redeem any shares")]
+    ZeroShares {},
+
+    #[error("Staking has not been configured")]
+    StakingNotConfigured {},
+
+    #[error("Stake ratio must be between 0 and 1")]
+    InvalidStakeRatio {},
+
+    #[error("Delegating this amount would leave upcoming payments uncovered")]
+    OverStake { max_amount: u128 },
+
+    #[error("The vault has not delegated enough to this validator")]
+    NotEnoughDelegation { validator: String },
+
+    #[error("Unknown reply id")]
+    UnknownReplyId { id: u64 },
+
+    #[error("IBC transfer reply did not contain a packet sequence")]
+    MissingTransferSequence {},
+
+    #[error("Only unordered channels are supported")]
+    OrderedChannel {},
+
+    #[error("Invalid IBC channel version - expected {version}")]
+    InvalidIbcVersion { version: String },
+
+    #[error("Counterparty vault is not trusted")]
+    UntrustedVault { port_id: String },
+
+    #[error("Channel is not connected to a trusted vault")]
+    UnknownVaultChannel { channel_id: String },
+
+    #[error("Slippage must be between 0 and 1")]
+    InvalidSlippage {},
+
+    #[error("Swap returned less than the minimum of {min_return}")]
+    SlippageExceeded { min_return: u128 },
+
+    #[error("Swap reply did not contain the returned amount")]
+    MissingSwapReturn {},
+
+    #[error("No price oracle is configured")]
+    OracleNotConfigured {},
+
+    #[error("Oracle price from {updated_at} is older than the allowed staleness")]
+    StalePrice { updated_at: u64 },
+
+    #[error("Oracle price {price} deviates too much from the last price {last_price}")]
+    PriceDeviation { price: String, last_price: String },
+
+    #[error("Oracle returned a zero price")]
+    InvalidPrice {},
+
+    #[error("No hook is registered at {address}")]
+    HookNotFound { address: String },
+
+    #[error("The vault is paused by governance")]
+    Paused {},
+
+    #[error("No dead man switch is configured")]
+    DeadManSwitchNotConfigured {},
+
+    #[error("Threshold must be between 1 and the number of guardians")]
+    InvalidGuardianThreshold {},
+
+    #[error("This address is not a guardian")]
+    NotGuardian {},
+
+    #[error("The dead man switch triggers in {seconds_remaining} seconds")]
+    SwitchNotLapsed { seconds_remaining: u64 },
+
+    #[error("Guardians can still recover the vault for {seconds_remaining} seconds")]
+    RecoveryPeriodActive { seconds_remaining: u64 },
+
+    #[error("The vault is winding down")]
+    WindingDown {},
+
+    #[error("The vault is not winding down")]
+    NotWindingDown {},
+
+    #[error("The vault is closed")]
+    VaultClosed {},
+
+    #[error("The vault is finalized")]
+    VaultFinalized {},
+
+    #[error("The vault is not closed")]
+    VaultNotClosed {},
+
+    #[error("Escrowed funds must be paid out or clawed back first")]
+    EscrowOutstanding {},
+
+    #[error("Staked funds must be unbonded and claimed first")]
+    StakeOutstanding {},
+
+    #[error("This address has no refund")]
+    NoRefund {},
+
+    #[error("This address already claimed its refund")]
+    RefundAlreadyClaimed {},
+
+    #[error("The bucket must be between 1 second and the window")]
+    InvalidRateLimits {},
+
+    #[error("Claim exceeds the spender limit, {remaining} left in this window")]
+    SpenderLimitExceeded { remaining: u128 },
+
+    #[error("Outflow exceeds the daily limit, {remaining} left today")]
+    DailyOutflowExceeded { remaining: u128 },
+
+    #[error("Budget {name} already exists")]
+    BudgetExists { name: String },
+
+    #[error("Budget {name} does not exist")]
+    BudgetNotFound { name: String },
+
+    #[error("Budget {name} has only {available} left")]
+    BudgetExceeded { name: String, available: u128 },
+
+    #[error("Vault {id} does not exist")]
+    VaultNotFound { id: u64 },
+
+    #[error("Vaults with different denoms cannot transfer to each other")]
+    VaultDenomMismatch {},
+
+    #[error("A campaign is already running")]
+    CampaignActive {},
+
+    #[error("The campaign goal must be above zero")]
+    ZeroCampaignGoal {},
+
+    #[error("The campaign deadline must be in the future")]
+    InvalidCampaignDeadline {},
+
+    #[error("No campaign is running")]
+    NoCampaign {},
+
+    #[error("Funds are locked until the campaign goal is met")]
+    CampaignLocked {},
+
+    #[error("The campaign deadline has passed")]
+    CampaignEnded {},
+
+    #[error("The campaign goal is not met, {missing} missing")]
+    GoalNotMet { missing: Uint128 },
+
+    #[error("The campaign has not failed")]
+    CampaignNotFailed {},
+
+    #[error("Nothing to reclaim")]
+    NothingToReclaim {},
+
+    #[error("This sponsor already has a pledge")]
+    PledgeExists {},
+
+    #[error("No pledge for this sponsor")]
+    PledgeNotFound {},
+
+    #[error("A pledge needs a positive ratio and cap, a budget and a future expiry")]
+    InvalidPledge {},
+
+    #[error("The pledge is still running for {seconds_remaining} seconds")]
+    PledgeNotExpired { seconds_remaining: u64 },
+
+    #[error("Savings are not enabled")]
+    SavingsNotEnabled {},
+
+    #[error("Penalty must be between 0 and 1")]
+    InvalidPenalty {},
+
+    #[error("The lock period must be above zero")]
+    ZeroLockPeriod {},
+
+    #[error("Position {id} not found")]
+    PositionNotFound { id: u64 },
+
+    #[error("Fees must be at most 10000 bps and min_fee cannot be above max_fee")]
+    InvalidFeeConfig {},
+
+    #[error("The fee of {fee} takes the whole amount")]
+    FeeExceedsAmount { fee: Uint128 },
+
+    #[error("No fees to collect")]
+    NoFees {},
+
+    #[error("Withholding rates must be at most 1 and bracket thresholds must increase")]
+    InvalidWithholding {},
+
+    #[error("No withholding rule to remove")]
+    WithholdingNotFound {},
+
+    #[error("Address {address} is flagged by compliance screening")]
+    AddressFlagged { address: String },
+
+    #[error("The allowance of {spender} is frozen")]
+    AllowanceFrozen { spender: String },
+
+    #[error("The allowance of {spender} is not frozen")]
+    AllowanceNotFrozen { spender: String },
+
+    #[error("Cannot claw back more than the allowance of {allowance}")]
+    ClawbackExceedsAllowance { allowance: Uint128 },
+
+    #[error("Only {incoming} announced by the remote vault is still expected")]
+    UnannouncedVaultFunds { incoming: u128 },
+
+    #[error("The period to reclaim contributions is over")]
+    ReclaimPeriodOver {},
+
+    #[error("A pledge needs a budget of at least {min_budget}")]
+    PledgeBelowMinimum { min_budget: u128 },
+
+    #[error("There are already {max} running pledges")]
+    TooManyPledges { max: u64 },
+
+    #[error("A swap needs a positive minimum return")]
+    ZeroMinReturn {},
+
+    #[error("The vault's funds back its outstanding shares")]
+    SharesOutstanding {},
+
+    #[error("Spender {spender} is not assigned to a budget")]
+    BudgetNotAssigned { spender: String },
+
+    #[error("Invoice amount must be greater than zero")]
+    ZeroInvoiceAmount {},
 }
@@ -0,0 +1,223 @@
+//! IBC protocol between paired vault instances.
+//!
+//! Vaults open an unordered channel with version [`IBC_VERSION`] and exchange
+//! [`VaultPacket`]s. Tokens are moved with an ICS20 transfer that is only sent
+//! once the remote vault has acknowledged the matching `FundTransfer` packet.
+//! The receiving vault credits its pool when that transfer arrives, through
+//! the `ReceiveVaultFunds` call in the transfer's memo.
+
+use crate::contract::IBC_TRANSFER_REPLY_ID;
+use crate::error::ContractError;
+use crate::ics20;
+use crate::msg::{VaultAck, VaultPacket};
+use crate::state::{
+    PendingTransfer, VaultChannel, OUTGOING_TRANSFER, STATE, TRUSTED_VAULTS, VAULT_CHANNELS,
+};
+use cosmwasm_std::{
+    from_json, to_json_binary, Binary, Coin, DepsMut, Env, IbcBasicResponse, IbcChannel,
+    IbcChannelCloseMsg, IbcChannelConnectMsg, IbcChannelOpenMsg, IbcOrder, IbcPacket,
+    IbcPacketAckMsg, IbcPacketReceiveMsg, IbcPacketTimeoutMsg, IbcReceiveResponse, SubMsg, Uint128,
+};
+
+pub const IBC_VERSION: &str = "the-vault-1";
+pub const PACKET_TIMEOUT_SECONDS: u64 = 60 * 60;
+
+fn validate_channel(
+    deps: &DepsMut,
+    channel: &IbcChannel,
+    counterparty_version: Option<&str>,
+) -> Result<(), ContractError> {
+    if channel.order != IbcOrder::Unordered {
+        return Err(ContractError::OrderedChannel {});
+    }
+    if channel.version != IBC_VERSION || counterparty_version.is_some_and(|v| v != IBC_VERSION) {
+        return Err(ContractError::InvalidIbcVersion {
+            version: IBC_VERSION.to_string(),
+        });
+    }
+    let port_id = channel.counterparty_endpoint.port_id.clone();
+    // A port id alone can be claimed on any chain, so the connection is checked too.
+    match TRUSTED_VAULTS.may_load(deps.storage, port_id.clone())? {
+        Some(trusted) if trusted.connection_id == channel.connection_id => Ok(()),
+        _ => Err(ContractError::UntrustedVault { port_id }),
+    }
+}
+
+pub fn channel_open(deps: DepsMut, msg: IbcChannelOpenMsg) -> Result<(), ContractError> {
+    validate_channel(&deps, msg.channel(), msg.counterparty_version())
+}
+
+pub fn channel_connect(
+    deps: DepsMut,
+    msg: IbcChannelConnectMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    let channel = msg.channel();
+    validate_channel(&deps, channel, msg.counterparty_version())?;
+    VAULT_CHANNELS.save(
+        deps.storage,
+        channel.endpoint.channel_id.clone(),
+        &VaultChannel {
+            counterparty_port_id: channel.counterparty_endpoint.port_id.clone(),
+            requested_by_peer: Uint128::zero(),
+            incoming: Uint128::zero(),
+            sent: Uint128::zero(),
+            received: Uint128::zero(),
+            reported_balance: None,
+            reported_at: None,
+        },
+    )?;
+    Ok(IbcBasicResponse::new()
+        .add_attribute("action", "ibc_channel_connect")
+        .add_attribute("channel_id", channel.endpoint.channel_id.clone()))
+}
+
+pub fn channel_close(
+    deps: DepsMut,
+    msg: IbcChannelCloseMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    let channel_id = msg.channel().endpoint.channel_id.clone();
+    VAULT_CHANNELS.remove(deps.storage, channel_id.clone());
+    Ok(IbcBasicResponse::new()
+        .add_attribute("action", "ibc_channel_close")
+        .add_attribute("channel_id", channel_id))
+}
+
+/// Errors are returned to the sender as an error acknowledgement instead of
+/// failing the transaction, so the relayer still gets the packet through.
+pub fn packet_receive(
+    deps: DepsMut,
+    env: Env,
+    msg: IbcPacketReceiveMsg,
+) -> Result<IbcReceiveResponse, ContractError> {
+    match receive_vault_packet(deps, env, &msg.packet) {
+        Ok(response) => Ok(response),
+        Err(error) => Ok(IbcReceiveResponse::new()
+            .set_ack(to_json_binary(&VaultAck::Error(error.to_string()))?)
+            .add_attribute("action", "ibc_packet_receive")
+            .add_attribute("error", error.to_string())),
+    }
+}
+
+fn receive_vault_packet(
+    deps: DepsMut,
+    env: Env,
+    packet: &IbcPacket,
+) -> Result<IbcReceiveResponse, ContractError> {
+    let channel_id = packet.dest.channel_id.clone();
+    let mut channel = match VAULT_CHANNELS.may_load(deps.storage, channel_id.clone())? {
+        Some(value) => value,
+        None => return Err(ContractError::UnknownVaultChannel { channel_id }),
+    };
+    let response = IbcReceiveResponse::new()
+        .set_ack(to_json_binary(&VaultAck::Result(Binary::default()))?)
+        .add_attribute("action", "ibc_packet_receive")
+        .add_attribute("channel_id", channel_id.clone());
+
+    let response = match from_json(&packet.data)? {
+        VaultPacket::FundRequest { amount } => {
+            channel.requested_by_peer += amount;
+            response
+                .add_attribute("packet", "fund_request")
+                .add_attribute("amount", amount)
+        }
+        VaultPacket::FundTransfer { amount } => {
+            // The ICS20 transfer follows this packet. The pool is only credited
+            // once its tokens arrive.
+            channel.incoming += amount;
+            response
+                .add_attribute("packet", "fund_transfer")
+                .add_attribute("amount", amount)
+        }
+        VaultPacket::BalanceReport { total_amount } => {
+            channel.reported_balance = Some(total_amount);
+            channel.reported_at = Some(env.block.time);
+            response
+                .add_attribute("packet", "balance_report")
+                .add_attribute("total_amount", total_amount)
+        }
+    };
+    VAULT_CHANNELS.save(deps.storage, channel_id, &channel)?;
+    Ok(response)
+}
+
+pub fn packet_ack(
+    deps: DepsMut,
+    env: Env,
+    msg: IbcPacketAckMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    let success = matches!(from_json(&msg.acknowledgement.data)?, VaultAck::Result(_));
+    settle_packet(deps, env, &msg.original_packet, success)
+}
+
+pub fn packet_timeout(
+    deps: DepsMut,
+    env: Env,
+    msg: IbcPacketTimeoutMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    settle_packet(deps, env, &msg.packet, false)
+}
+
+fn settle_packet(
+    deps: DepsMut,
+    env: Env,
+    packet: &IbcPacket,
+    success: bool,
+) -> Result<IbcBasicResponse, ContractError> {
+    let channel_id = packet.src.channel_id.clone();
+    let response = IbcBasicResponse::new()
+        .add_attribute("action", "ibc_packet_settle")
+        .add_attribute("channel_id", channel_id.clone())
+        .add_attribute("success", success.to_string());
+
+    let amount = match from_json(&packet.data)? {
+        VaultPacket::FundTransfer { amount } => amount,
+        _ => return Ok(response),
+    };
+    let mut current_state = STATE.load(deps.storage)?;
+    if !success {
+        // Nothing has left the vault yet, so the amount goes back to the pool.
+        current_state.total_amount += amount;
+        STATE.save(deps.storage, &current_state)?;
+        return Ok(response.add_attribute("refunded_amount", amount));
+    }
+
+    let mut channel = match VAULT_CHANNELS.may_load(deps.storage, channel_id.clone())? {
+        Some(value) => value,
+        None => return Err(ContractError::UnknownVaultChannel { channel_id }),
+    };
+    let trusted = TRUSTED_VAULTS.load(deps.storage, channel.counterparty_port_id.clone())?;
+    channel.sent += amount;
+    channel.requested_by_peer = channel.requested_by_peer.saturating_sub(amount);
+    VAULT_CHANNELS.save(deps.storage, channel_id.clone(), &channel)?;
+
+    // Tracked like a payout, so a failed transfer returns to the pool.
+    OUTGOING_TRANSFER.save(
+        deps.storage,
+        &PendingTransfer {
+            spender: env.contract.address.clone(),
+            channel_id: trusted.transfer_channel_id.clone(),
+            amount,
+            vault_channel_id: Some(channel_id),
+            withholding: None,
+        },
+    )?;
+    let memo = ics20::vault_funds_memo(
+        &env.contract.address,
+        &trusted.remote_address,
+        &packet.dest.channel_id,
+    )?;
+    let transfer = ics20::transfer_msg(
+        &env.contract.address,
+        &trusted.transfer_channel_id,
+        &trusted.remote_address,
+        &Coin {
+            denom: current_state.expected_denom,
+            amount,
+        },
+        env.block.time.plus_seconds(PACKET_TIMEOUT_SECONDS),
+        &memo,
+    );
+    Ok(response
+        .add_submessage(SubMsg::reply_on_success(transfer, IBC_TRANSFER_REPLY_ID))
+        .add_attribute("amount", amount))
+}
@@ -0,0 +1,96 @@
+//! Protobuf encoding of the ICS20 `MsgTransfer`.
+//!
+//! `IbcMsg::Transfer` has no memo, so payouts are sent as a stargate message
+//! that asks the chain to call the vault back with the ack or timeout.
+
+use crate::msg::ExecuteMsg;
+use cosmwasm_std::{to_json_string, Addr, Binary, Coin, CosmosMsg, StdResult, Timestamp};
+use serde::Serialize;
+
+pub const MSG_TRANSFER_TYPE_URL: &str = "/ibc.applications.transfer.v1.MsgTransfer";
+
+fn encode_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn encode_bytes(buffer: &mut Vec<u8>, field: u8, bytes: &[u8]) {
+    if bytes.is_empty() {
+        return;
+    }
+    buffer.push(field << 3 | 2);
+    encode_varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+#[derive(Serialize)]
+struct WasmHook {
+    contract: String,
+    msg: ExecuteMsg,
+}
+
+#[derive(Serialize)]
+struct Memo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wasm: Option<WasmHook>,
+    ibc_callback: String,
+}
+
+/// Memo that makes the chain report the transfer's outcome to `contract`
+/// through the `IbcLifecycleComplete` sudo message.
+pub fn callback_memo(contract: &Addr) -> StdResult<String> {
+    to_json_string(&Memo {
+        wasm: None,
+        ibc_callback: contract.to_string(),
+    })
+}
+
+/// Callback memo that also has the receiving chain run `ReceiveVaultFunds` on
+/// the remote vault with the transferred tokens.
+pub fn vault_funds_memo(
+    contract: &Addr,
+    remote_vault: &str,
+    remote_channel_id: &str,
+) -> StdResult<String> {
+    to_json_string(&Memo {
+        wasm: Some(WasmHook {
+            contract: remote_vault.to_string(),
+            msg: ExecuteMsg::ReceiveVaultFunds {
+                channel_id: remote_channel_id.to_string(),
+            },
+        }),
+        ibc_callback: contract.to_string(),
+    })
+}
+
+/// `MsgTransfer` from `sender` on the `transfer` port.
+pub fn transfer_msg(
+    sender: &Addr,
+    channel_id: &str,
+    receiver: &str,
+    token: &Coin,
+    timeout: Timestamp,
+    memo: &str,
+) -> CosmosMsg {
+    let mut coin = Vec::new();
+    encode_bytes(&mut coin, 1, token.denom.as_bytes());
+    encode_bytes(&mut coin, 2, token.amount.to_string().as_bytes());
+
+    let mut value = Vec::new();
+    encode_bytes(&mut value, 1, b"transfer");
+    encode_bytes(&mut value, 2, channel_id.as_bytes());
+    encode_bytes(&mut value, 3, &coin);
+    encode_bytes(&mut value, 4, sender.as_bytes());
+    encode_bytes(&mut value, 5, receiver.as_bytes());
+    value.push(7 << 3);
+    encode_varint(&mut value, timeout.nanos());
+    encode_bytes(&mut value, 8, memo.as_bytes());
+
+    CosmosMsg::Stargate {
+        type_url: MSG_TRANSFER_TYPE_URL.to_string(),
+        value: Binary::from(value),
+    }
+}
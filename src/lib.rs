@@ -1,17 +1,29 @@
 use contract::instantiate_contract;
+#[cfg(not(feature = "library"))]
+use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    entry_point, to_json_binary, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult,
+    to_json_binary, Binary, Deps, DepsMut, Env, IbcBasicResponse, IbcChannelCloseMsg,
+    IbcChannelConnectMsg, IbcChannelOpenMsg, IbcChannelOpenResponse, IbcPacketAckMsg,
+    IbcPacketReceiveMsg, IbcPacketTimeoutMsg, IbcReceiveResponse, MessageInfo, Reply, Response,
+    StdResult,
 };
 use error::ContractError;
-use msg::{ExecuteMsg, InstantiateMsg};
+use msg::{ExecuteMsg, InstantiateMsg, MigrateMsg, SudoMsg};
 
+pub mod compliance;
 mod contract;
+pub mod dex;
 mod error;
+mod ibc;
+mod ics20;
+pub mod merkle;
 pub mod msg;
+pub mod oracle;
 mod state;
+#[cfg(test)]
 mod test;
 
-#[entry_point]
+#[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
     deps: DepsMut,
     _env: Env,
@@ -21,49 +33,607 @@ pub fn instantiate(
     instantiate_contract(deps, info, msg)
 }
 
-#[entry_point]
+#[cfg_attr(not(feature = "library"), entry_point)]
 pub fn execute(
     deps: DepsMut,
     env: Env,
     info: MessageInfo,
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
+    if state::PAUSED.may_load(deps.storage)?.unwrap_or(false) {
+        return Err(ContractError::Paused {});
+    }
+    // Wind-down and closure only concern the vault created at instantiation.
+    let sub_vault = msg.sub_vault_id();
+    let default_vault = sub_vault.is_none()
+        && !matches!(msg, ExecuteMsg::CreateVault { .. })
+        && !matches!(
+            msg,
+            ExecuteMsg::TransferBetweenVaults { from_vault, to_vault, .. }
+                if from_vault != 0 && to_vault != 0
+        );
+    if default_vault {
+        // Winding down vaults only pay out: allowances, share redemptions,
+        // the escrow of grants and distributions, and the beneficiary.
+        if state::DEAD_MAN_SWITCH
+            .may_load(deps.storage)?
+            .is_some_and(|switch| switch.wind_down)
+            && !matches!(
+                msg,
+                ExecuteMsg::RetrieveAllowance { .. }
+                    | ExecuteMsg::ReleaseToBeneficiary {}
+                    | ExecuteMsg::WithdrawPledge {}
+                    | ExecuteMsg::ClosePosition { .. }
+                    | ExecuteMsg::Receive(..)
+                    | ExecuteMsg::CompleteMilestone { .. }
+                    | ExecuteMsg::CancelGrant { .. }
+                    | ExecuteMsg::ClaimDistribution { .. }
+                    | ExecuteMsg::ClawbackDistribution { .. }
+                    | ExecuteMsg::ReclaimContribution {}
+            )
+        {
+            return Err(ContractError::WindingDown {});
+        }
+        // Closed vaults only pay the reserved allowances and the refunds.
+        if let Some(closure) = state::CLOSURE.may_load(deps.storage)? {
+            if closure.finalized {
+                return Err(ContractError::VaultFinalized {});
+            }
+            if !matches!(
+                msg,
+                ExecuteMsg::RetrieveAllowance { .. }
+                    | ExecuteMsg::ClaimRefund {}
+                    | ExecuteMsg::WithdrawPledge {}
+                    | ExecuteMsg::ClosePosition { .. }
+            ) {
+                return Err(ContractError::VaultClosed {});
+            }
+        }
+        // Until the goal is met, funds only come in or go back to contributors.
+        if let Some(campaign) = state::CAMPAIGN.may_load(deps.storage)? {
+            if contract::campaign_locked(&campaign, env.block.time) {
+                let ended = env.block.time > campaign.deadline;
+                match msg {
+                    ExecuteMsg::Deposit { .. } | ExecuteMsg::SwapDeposit { .. } if ended => {
+                        return Err(ContractError::CampaignEnded {})
+                    }
+                    ExecuteMsg::Deposit { .. }
+                    | ExecuteMsg::SwapDeposit { .. }
+                    | ExecuteMsg::AddDepositAddress { .. }
+                    | ExecuteMsg::AddAllowance { .. }
+                    | ExecuteMsg::AddAllowanceList { .. }
+                    | ExecuteMsg::RemoveAllowance { .. }
+                    | ExecuteMsg::UpdateAllowance { .. }
+                    | ExecuteMsg::UpdateName { .. }
+                    | ExecuteMsg::UpdateOwner { .. }
+                    | ExecuteMsg::AddHook { .. }
+                    | ExecuteMsg::RemoveHook { .. }
+                    | ExecuteMsg::StartCampaign { .. }
+                    | ExecuteMsg::UnlockCampaign {}
+                    | ExecuteMsg::ReclaimContribution {}
+                    | ExecuteMsg::PledgeMatch { .. }
+                    | ExecuteMsg::WithdrawPledge {}
+                    | ExecuteMsg::SetMinPledgeBudget { .. }
+                    | ExecuteMsg::ClosePosition { .. } => {}
+                    _ => return Err(ContractError::CampaignLocked {}),
+                }
+            }
+        }
+    }
     match msg {
-        ExecuteMsg::Deposit {} => contract::execute::deposit_token(deps, info),
-        ExecuteMsg::AddDepositAddress { address } => {
-            contract::execute::add_deposit_address(deps, info, address)
+        ExecuteMsg::Deposit { .. } => match sub_vault {
+            Some(id) => contract::vaults::deposit(deps, info, id),
+            None => contract::execute::deposit_token(deps, env, info),
+        },
+        ExecuteMsg::SwapDeposit { min_return } => {
+            contract::execute::swap_deposit(deps, env, info, min_return)
+        }
+        ExecuteMsg::AddDepositAddress { address, .. } => match sub_vault {
+            Some(id) => contract::vaults::add_deposit_address(deps, info, id, address),
+            None => contract::execute::add_deposit_address(deps, info, address),
+        },
+        ExecuteMsg::RemoveDepositAddress { address, .. } => match sub_vault {
+            Some(id) => contract::vaults::remove_deposit_address(deps, info, id, address),
+            None => contract::execute::remove_deposit_address(deps, info, address),
+        },
+        ExecuteMsg::Withdraw { .. } => match sub_vault {
+            Some(id) => contract::vaults::withdraw(deps, env, info, id),
+            None => contract::execute::withdraw(deps, env, info),
+        },
+        ExecuteMsg::AddAllowance {
+            spender, amount, ..
+        } => match sub_vault {
+            Some(id) => {
+                contract::vaults::save_allowance(deps, info, id, spender, amount, "add_allowance")
+            }
+            None => contract::execute::add_allowance(deps, info, spender, amount),
+        },
+        ExecuteMsg::AddAllowanceList {
+            spenders, amounts, ..
+        } => match sub_vault {
+            Some(id) => contract::vaults::add_allowance_list(deps, info, id, spenders, amounts),
+            None => contract::execute::add_allowance_list(deps, info, spenders, amounts),
+        },
+        ExecuteMsg::RemoveAllowance { spender, .. } => match sub_vault {
+            Some(id) => contract::vaults::remove_allowance(deps, info, id, spender),
+            None => contract::execute::remove_allowance(deps, info, spender),
+        },
+        ExecuteMsg::UpdateAllowance {
+            spender, amount, ..
+        } => match sub_vault {
+            Some(id) => contract::vaults::save_allowance(
+                deps,
+                info,
+                id,
+                spender,
+                amount,
+                "update_allowance",
+            ),
+            None => contract::execute::update_allowance(deps, info, spender, amount),
+        },
+        ExecuteMsg::RetrieveAllowance { .. } => match sub_vault {
+            Some(id) => contract::vaults::retrieve_allowance(deps, env, info, id),
+            None => contract::execute::retrieve_allowance(deps, env, info),
+        },
+        ExecuteMsg::UpdateName { name, .. } => match sub_vault {
+            Some(id) => contract::vaults::update_name(deps, info, id, name),
+            None => contract::execute::update_name(deps, info, name),
+        },
+        ExecuteMsg::UpdateOwner { owner, .. } => match sub_vault {
+            Some(id) => contract::vaults::update_owner(deps, info, id, owner),
+            None => contract::execute::update_owner(deps, env, info, owner),
+        },
+        ExecuteMsg::CreateVault {
+            name,
+            expected_denom,
+        } => contract::vaults::create_vault(deps, info, name, expected_denom),
+        ExecuteMsg::TransferBetweenVaults {
+            from_vault,
+            to_vault,
+            amount,
+        } => {
+            contract::vaults::transfer_between_vaults(deps, env, info, from_vault, to_vault, amount)
+        }
+        ExecuteMsg::AddVendor {
+            address,
+            name,
+            default_denom,
+        } => contract::execute::add_vendor(deps, info, address, name, default_denom),
+        ExecuteMsg::RemoveVendor { address } => {
+            contract::execute::remove_vendor(deps, info, address)
+        }
+        ExecuteMsg::CreateInvoice {
+            vendor,
+            amount,
+            denom,
+            due_date,
+            reference_id,
+        } => contract::execute::create_invoice(
+            deps,
+            info,
+            vendor,
+            amount,
+            denom,
+            due_date,
+            reference_id,
+        ),
+        ExecuteMsg::CancelInvoice { id } => contract::execute::cancel_invoice(deps, info, id),
+        ExecuteMsg::PayDueInvoices { limit } => {
+            contract::execute::pay_due_invoices(deps, env, limit)
+        }
+        ExecuteMsg::CreateGrant {
+            grantee,
+            arbiter,
+            milestones,
+        } => contract::execute::create_grant(deps, info, grantee, arbiter, milestones),
+        ExecuteMsg::CompleteMilestone {
+            grant_id,
+            milestone,
+        } => contract::execute::complete_milestone(deps, env, info, grant_id, milestone),
+        ExecuteMsg::CancelGrant { grant_id } => {
+            contract::execute::cancel_grant(deps, info, grant_id)
+        }
+        ExecuteMsg::SetVoucherPubkey { pubkey } => {
+            contract::execute::set_voucher_pubkey(deps, info, pubkey)
+        }
+        ExecuteMsg::RedeemVoucher { voucher, signature } => {
+            contract::execute::redeem_voucher(deps, env, info, voucher, signature)
+        }
+        ExecuteMsg::RegisterDistribution {
+            merkle_root,
+            total_amount,
+            expiration,
+        } => contract::execute::register_distribution(
+            deps,
+            info,
+            merkle_root,
+            total_amount,
+            expiration,
+        ),
+        ExecuteMsg::ClaimDistribution {
+            round,
+            amount,
+            proof,
+        } => contract::execute::claim_distribution(deps, env, info, round, amount, proof),
+        ExecuteMsg::ClawbackDistribution { round } => {
+            contract::execute::clawback_distribution(deps, env, info, round)
+        }
+        ExecuteMsg::EnableShareMode { token } => {
+            contract::execute::enable_share_mode(deps, info, token)
+        }
+        ExecuteMsg::Receive(wrapper) => contract::execute::receive(deps, env, info, wrapper),
+        ExecuteMsg::SetStakingConfig {
+            max_stake_ratio,
+            unbonding_period,
+        } => contract::execute::set_staking_config(deps, info, max_stake_ratio, unbonding_period),
+        ExecuteMsg::Delegate { validator, amount } => {
+            contract::execute::delegate(deps, env, info, validator, amount)
+        }
+        ExecuteMsg::Undelegate { validator, amount } => {
+            contract::execute::undelegate(deps, env, info, validator, amount)
+        }
+        ExecuteMsg::WithdrawRewards {} => contract::execute::withdraw_rewards(deps, env, info),
+        ExecuteMsg::ClaimUnbonded {} => contract::execute::claim_unbonded(deps, env, info),
+        ExecuteMsg::SetPayoutPreference {
+            channel_id,
+            remote_address,
+            timeout_seconds,
+        } => contract::execute::set_payout_preference(
+            deps,
+            info,
+            channel_id,
+            remote_address,
+            timeout_seconds,
+        ),
+        ExecuteMsg::RemovePayoutPreference {} => {
+            contract::execute::remove_payout_preference(deps, info)
+        }
+        ExecuteMsg::AddTrustedVault {
+            port_id,
+            connection_id,
+            remote_address,
+            transfer_channel_id,
+        } => contract::execute::add_trusted_vault(
+            deps,
+            info,
+            port_id,
+            connection_id,
+            remote_address,
+            transfer_channel_id,
+        ),
+        ExecuteMsg::RemoveTrustedVault { port_id } => {
+            contract::execute::remove_trusted_vault(deps, info, port_id)
+        }
+        ExecuteMsg::RequestFunds { channel_id, amount } => {
+            contract::execute::request_funds(deps, env, info, channel_id, amount)
+        }
+        ExecuteMsg::ReceiveVaultFunds { channel_id } => {
+            contract::execute::receive_vault_funds(deps, info, channel_id)
         }
-        ExecuteMsg::RemoveDepositAddress { address } => {
-            contract::execute::remove_deposit_address(deps, info, address)
+        ExecuteMsg::SendFunds { channel_id, amount } => {
+            contract::execute::send_funds(deps, env, info, channel_id, amount)
         }
-        ExecuteMsg::Withdraw {} => contract::execute::withdraw(deps, env, info),
-        ExecuteMsg::AddAllowance { spender, amount } => {
-            contract::execute::add_allowance(deps, info, spender, amount)
+        ExecuteMsg::ReportBalance { channel_id } => {
+            contract::execute::report_balance(deps, env, info, channel_id)
         }
-        ExecuteMsg::AddAllowanceList { spenders, amounts } => {
-            contract::execute::add_allowance_list(deps, info, spenders, amounts)
+        ExecuteMsg::SetSwapConfig {
+            router,
+            auto_swap,
+            max_slippage,
+        } => contract::execute::set_swap_config(deps, info, router, auto_swap, max_slippage),
+        ExecuteMsg::SetOracleConfig {
+            oracle,
+            quote,
+            max_staleness,
+            max_deviation,
+            max_tokens_per_claim,
+        } => contract::execute::set_oracle_config(
+            deps,
+            info,
+            oracle,
+            quote,
+            max_staleness,
+            max_deviation,
+            max_tokens_per_claim,
+        ),
+        ExecuteMsg::AddPeggedAllowance {
+            spender,
+            quote_amount,
+        } => contract::execute::add_pegged_allowance(deps, info, spender, quote_amount),
+        ExecuteMsg::RemovePeggedAllowance { spender } => {
+            contract::execute::remove_pegged_allowance(deps, info, spender)
         }
-        ExecuteMsg::RemoveAllowance { spender } => {
-            contract::execute::remove_allowance(deps, info, spender)
+        ExecuteMsg::AddHook { address, blocking } => {
+            contract::execute::add_hook(deps, info, address, blocking)
         }
-        ExecuteMsg::UpdateAllowance { spender, amount } => {
-            contract::execute::update_allowance(deps, info, spender, amount)
+        ExecuteMsg::RemoveHook { address } => contract::execute::remove_hook(deps, info, address),
+        ExecuteMsg::SetDeadManSwitch {
+            interval,
+            guardians,
+            threshold,
+            recovery_period,
+            beneficiary,
+        } => contract::execute::set_dead_man_switch(
+            deps,
+            env,
+            info,
+            interval,
+            guardians,
+            threshold,
+            recovery_period,
+            beneficiary,
+        ),
+        ExecuteMsg::Heartbeat {} => contract::execute::heartbeat(deps, env, info),
+        ExecuteMsg::VoteRecovery { new_owner } => {
+            contract::execute::vote_recovery(deps, env, info, new_owner)
         }
-        ExecuteMsg::RetrieveAllowance {} => contract::execute::retrieve_allowance(deps, info),
-        ExecuteMsg::UpdateName { name } => contract::execute::update_name(deps, info, name),
-        ExecuteMsg::UpdateOwner { owner } => contract::execute::update_owner(deps, info, owner),
+        ExecuteMsg::StartWindDown {} => contract::execute::start_wind_down(deps, env),
+        ExecuteMsg::ReleaseToBeneficiary {} => contract::execute::release_to_beneficiary(deps, env),
+        ExecuteMsg::CloseVault {} => contract::execute::close_vault(deps, info),
+        ExecuteMsg::ClaimRefund {} => contract::execute::claim_refund(deps, env, info),
+        ExecuteMsg::SetRateLimits {
+            max_claim_per_window,
+            window_seconds,
+            max_daily_outflow,
+            bucket_seconds,
+        } => contract::execute::set_rate_limits(
+            deps,
+            info,
+            max_claim_per_window,
+            window_seconds,
+            max_daily_outflow,
+            bucket_seconds,
+        ),
+        ExecuteMsg::CreateBudget { name, allocated } => {
+            contract::execute::create_budget(deps, info, name, allocated)
+        }
+        ExecuteMsg::AssignBudget { spender, budget } => {
+            contract::execute::assign_budget(deps, info, spender, budget)
+        }
+        ExecuteMsg::UnassignBudget { spender } => {
+            contract::execute::unassign_budget(deps, info, spender)
+        }
+        ExecuteMsg::ReallocateBudget { from, to, amount } => {
+            contract::execute::reallocate_budget(deps, env, info, from, to, amount)
+        }
+        ExecuteMsg::StartCampaign { goal, deadline } => {
+            contract::execute::start_campaign(deps, env, info, goal, deadline)
+        }
+        ExecuteMsg::UnlockCampaign {} => contract::execute::unlock_campaign(deps, info),
+        ExecuteMsg::ReclaimContribution {} => {
+            contract::execute::reclaim_contribution(deps, env, info)
+        }
+        ExecuteMsg::PledgeMatch {
+            ratio,
+            cap_per_depositor,
+            expires,
+        } => contract::execute::pledge_match(deps, env, info, ratio, cap_per_depositor, expires),
+        ExecuteMsg::WithdrawPledge {} => contract::execute::withdraw_pledge(deps, env, info),
+        ExecuteMsg::SetMinPledgeBudget { amount } => {
+            contract::execute::set_min_pledge_budget(deps, info, amount)
+        }
+        ExecuteMsg::SetEarlyExitPenalty { penalty } => {
+            contract::execute::set_early_exit_penalty(deps, info, penalty)
+        }
+        ExecuteMsg::DepositLocked { lock_seconds } => {
+            contract::execute::deposit_locked(deps, env, info, lock_seconds)
+        }
+        ExecuteMsg::ClosePosition { id } => contract::execute::close_position(deps, env, info, id),
+        ExecuteMsg::SetFeeConfig {
+            deposit_bps,
+            claim_bps,
+            min_fee,
+            max_fee,
+            treasury,
+        } => contract::execute::set_fee_config(
+            deps,
+            info,
+            deposit_bps,
+            claim_bps,
+            min_fee,
+            max_fee,
+            treasury,
+        ),
+        ExecuteMsg::RemoveFeeConfig {} => contract::execute::remove_fee_config(deps, info),
+        ExecuteMsg::CollectFees {} => contract::execute::collect_fees(deps, info),
+        ExecuteMsg::SetWithholding {
+            spender,
+            rate,
+            destination,
+            brackets,
+        } => contract::execute::set_withholding(deps, info, spender, rate, destination, brackets),
+        ExecuteMsg::RemoveWithholding { spender } => {
+            contract::execute::remove_withholding(deps, info, spender)
+        }
+        ExecuteMsg::SetComplianceRegistry { registry } => {
+            contract::execute::set_compliance_registry(deps, info, registry)
+        }
+        ExecuteMsg::AddToDenyList { address } => {
+            contract::execute::update_deny_list(deps, info, address, true)
+        }
+        ExecuteMsg::RemoveFromDenyList { address } => {
+            contract::execute::update_deny_list(deps, info, address, false)
+        }
+        ExecuteMsg::FreezeAllowance { spender } => {
+            contract::execute::freeze_allowance(deps, env, info, spender)
+        }
+        ExecuteMsg::UnfreezeAllowance { spender } => {
+            contract::execute::unfreeze_allowance(deps, info, spender)
+        }
+        ExecuteMsg::ClawbackAllowance {
+            spender,
+            amount,
+            reason,
+        } => contract::execute::clawback_allowance(deps, env, info, spender, amount, reason),
     }
 }
 
-#[entry_point]
-pub fn query(deps: Deps, _env: Env, msg: msg::QueryMsg) -> StdResult<Binary> {
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: msg::QueryMsg) -> StdResult<Binary> {
     use msg::QueryMsg::*;
 
     match msg {
-        GetState {} => to_json_binary(&contract::query::get_state(deps)?),
-        GetAllowance { spender } => to_json_binary(&contract::query::get_allowance(deps, spender)?),
-        GetAllowances {} => to_json_binary(&contract::query::get_allowances(deps)?),
-        CanDeposit { address } => to_json_binary(&contract::query::can_deposit(deps, address)?),
-        GetDepositAddresses {} => to_json_binary(&contract::query::get_deposit_addresses(deps)?),
+        GetState { vault_id } => match vault_id.filter(|id| *id != 0) {
+            Some(id) => to_json_binary(&contract::vaults::get_state(deps, id)?),
+            None => to_json_binary(&contract::query::get_state(deps)?),
+        },
+        GetAllowance { spender, vault_id } => match vault_id.filter(|id| *id != 0) {
+            Some(id) => to_json_binary(&contract::vaults::get_allowance(deps, id, spender)?),
+            None => to_json_binary(&contract::query::get_allowance(deps, spender)?),
+        },
+        GetAllowances { vault_id } => match vault_id.filter(|id| *id != 0) {
+            Some(id) => to_json_binary(&contract::vaults::get_allowances(deps, id)?),
+            None => to_json_binary(&contract::query::get_allowances(deps)?),
+        },
+        CanDeposit { address, vault_id } => match vault_id.filter(|id| *id != 0) {
+            Some(id) => to_json_binary(&contract::vaults::can_deposit(deps, id, address)?),
+            None => to_json_binary(&contract::query::can_deposit(deps, address)?),
+        },
+        GetDepositAddresses { vault_id } => match vault_id.filter(|id| *id != 0) {
+            Some(id) => to_json_binary(&contract::vaults::get_deposit_addresses(deps, id)?),
+            None => to_json_binary(&contract::query::get_deposit_addresses(deps)?),
+        },
+        GetVendor { address } => to_json_binary(&contract::query::get_vendor(deps, address)?),
+        GetVendors {} => to_json_binary(&contract::query::get_vendors(deps)?),
+        GetInvoice { id } => to_json_binary(&contract::query::get_invoice(deps, id)?),
+        GetInvoices {} => to_json_binary(&contract::query::get_invoices(deps)?),
+        GetGrant { id } => to_json_binary(&contract::query::get_grant(deps, id)?),
+        GetGrants {} => to_json_binary(&contract::query::get_grants(deps)?),
+        GetVoucherPubkey {} => to_json_binary(&contract::query::get_voucher_pubkey(deps)?),
+        IsNonceUsed { nonce } => to_json_binary(&contract::query::is_nonce_used(deps, nonce)?),
+        GetDistribution { round } => {
+            to_json_binary(&contract::query::get_distribution(deps, round)?)
+        }
+        IsDistributionClaimed { round, address } => to_json_binary(
+            &contract::query::is_distribution_claimed(deps, round, address)?,
+        ),
+        GetShareConfig {} => to_json_binary(&contract::query::get_share_config(deps)?),
+        PreviewDeposit { amount } => {
+            to_json_binary(&contract::query::preview_deposit(deps, amount)?)
+        }
+        PreviewRedeem { shares } => to_json_binary(&contract::query::preview_redeem(deps, shares)?),
+        GetStakingInfo {} => to_json_binary(&contract::query::get_staking_info(deps)?),
+        GetPayoutPreference { spender } => {
+            to_json_binary(&contract::query::get_payout_preference(deps, spender)?)
+        }
+        GetPendingTransfers {} => to_json_binary(&contract::query::get_pending_transfers(deps)?),
+        GetTrustedVaults {} => to_json_binary(&contract::query::get_trusted_vaults(deps)?),
+        GetVaultChannels {} => to_json_binary(&contract::query::get_vault_channels(deps)?),
+        GetSwapConfig {} => to_json_binary(&contract::query::get_swap_config(deps)?),
+        GetOracleConfig {} => to_json_binary(&contract::query::get_oracle_config(deps)?),
+        GetPeggedAllowance { spender } => {
+            to_json_binary(&contract::query::get_pegged_allowance(deps, spender)?)
+        }
+        GetHooks {} => to_json_binary(&contract::query::get_hooks(deps)?),
+        IsPaused {} => to_json_binary(&contract::query::is_paused(deps)?),
+        GetDeadManSwitch {} => to_json_binary(&contract::query::get_dead_man_switch(deps, env)?),
+        GetClosure {} => to_json_binary(&contract::query::get_closure(deps)?),
+        GetRefund { address } => to_json_binary(&contract::query::get_refund(deps, address)?),
+        GetRemainingQuota { spender } => {
+            to_json_binary(&contract::query::get_remaining_quota(deps, env, spender)?)
+        }
+        GetBudget { name } => to_json_binary(&contract::query::get_budget(deps, name)?),
+        GetBudgets {} => to_json_binary(&contract::query::get_budgets(deps)?),
+        GetReallocations {} => to_json_binary(&contract::query::get_reallocations(deps)?),
+        GetVaults {} => to_json_binary(&contract::vaults::get_vaults(deps)?),
+        GetCampaign {} => to_json_binary(&contract::query::get_campaign(deps, env)?),
+        GetPledge { sponsor, depositor } => {
+            to_json_binary(&contract::query::get_pledge(deps, env, sponsor, depositor)?)
+        }
+        GetPledges {} => to_json_binary(&contract::query::get_pledges(deps, env)?),
+        GetPositions { owner } => {
+            to_json_binary(&contract::query::get_positions(deps, env, owner)?)
+        }
+        GetFeeConfig {} => to_json_binary(&contract::query::get_fee_config(deps)?),
+        GetWithholdingReport { spender, year } => to_json_binary(
+            &contract::query::get_withholding_report(deps, env, spender, year)?,
+        ),
+        GetCompliance {} => to_json_binary(&contract::query::get_compliance(deps)?),
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    contract::recount_reserved(deps.storage)?;
+    contract::backfill_deposited(deps.storage)?;
+    Ok(Response::new().add_attribute("action", "migrate"))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> Result<Response, ContractError> {
+    match msg.id {
+        contract::STAKING_REPLY_ID => contract::reply::staking_reply(deps, env),
+        contract::IBC_TRANSFER_REPLY_ID => contract::reply::ibc_transfer_reply(deps, msg),
+        contract::SWAP_REPLY_ID => contract::reply::swap_reply(deps, env, msg),
+        contract::HOOK_REPLY_ID => contract::reply::hook_reply(msg),
+        id => Err(ContractError::UnknownReplyId { id }),
     }
 }
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn sudo(deps: DepsMut, env: Env, msg: SudoMsg) -> Result<Response, ContractError> {
+    match msg {
+        SudoMsg::IbcLifecycleComplete(lifecycle) => {
+            contract::sudo::ibc_lifecycle_complete(deps, lifecycle)
+        }
+        SudoMsg::ForceOwnerChange { owner } => contract::sudo::force_owner_change(deps, env, owner),
+        SudoMsg::Pause {} => contract::sudo::set_paused(deps, true),
+        SudoMsg::Unpause {} => contract::sudo::set_paused(deps, false),
+        SudoMsg::RecoverFunds { recipient } => contract::sudo::recover_funds(deps, env, recipient),
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_channel_open(
+    deps: DepsMut,
+    _env: Env,
+    msg: IbcChannelOpenMsg,
+) -> Result<IbcChannelOpenResponse, ContractError> {
+    ibc::channel_open(deps, msg)?;
+    Ok(None)
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_channel_connect(
+    deps: DepsMut,
+    _env: Env,
+    msg: IbcChannelConnectMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    ibc::channel_connect(deps, msg)
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_channel_close(
+    deps: DepsMut,
+    _env: Env,
+    msg: IbcChannelCloseMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    ibc::channel_close(deps, msg)
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_packet_receive(
+    deps: DepsMut,
+    env: Env,
+    msg: IbcPacketReceiveMsg,
+) -> Result<IbcReceiveResponse, ContractError> {
+    ibc::packet_receive(deps, env, msg)
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_packet_ack(
+    deps: DepsMut,
+    env: Env,
+    msg: IbcPacketAckMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    ibc::packet_ack(deps, env, msg)
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_packet_timeout(
+    deps: DepsMut,
+    env: Env,
+    msg: IbcPacketTimeoutMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    ibc::packet_timeout(deps, env, msg)
+}
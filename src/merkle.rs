@@ -0,0 +1,91 @@
+//! Merkle tree helpers for distribution rounds.
+//!
+//! Leaves are `sha256(0x00 || len(address) || address || amount)`, with the
+//! length as 4 big-endian bytes and the amount as 16 big-endian bytes. Every
+//! parent is `sha256(0x01 || a || b)` of its two children in ascending byte
+//! order, so proofs do not need to carry the position of each sibling. The
+//! prefixes keep a parent from passing as a leaf.
+
+use cosmwasm_std::Uint128;
+use sha2::{Digest, Sha256};
+
+pub type Hash = [u8; 32];
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+pub fn leaf_hash(address: &str, amount: Uint128) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update((address.len() as u32).to_be_bytes());
+    hasher.update(address.as_bytes());
+    hasher.update(amount.u128().to_be_bytes());
+    hasher.finalize().into()
+}
+
+fn hash_pair(a: &Hash, b: &Hash) -> Hash {
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(first);
+    hasher.update(second);
+    hasher.finalize().into()
+}
+
+/// Folds the proof into the leaf and compares the result with the root.
+pub fn verify_proof(root: &Hash, leaf: Hash, proof: &[Hash]) -> bool {
+    let computed = proof
+        .iter()
+        .fold(leaf, |hash, sibling| hash_pair(&hash, sibling));
+    &computed == root
+}
+
+/// Builds a tree of `(address, amount)` leaves. Used to prepare the root and
+/// proofs of a distribution round off-chain and in tests.
+pub struct MerkleTree {
+    layers: Vec<Vec<Hash>>,
+}
+
+impl MerkleTree {
+    pub fn new(entries: &[(&str, Uint128)]) -> Self {
+        let mut layers = vec![entries
+            .iter()
+            .map(|(address, amount)| leaf_hash(address, *amount))
+            .collect::<Vec<Hash>>()];
+        while layers.last().map_or(0, |layer| layer.len()) > 1 {
+            let next = layers
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| match pair {
+                    [a, b] => hash_pair(a, b),
+                    [a] => *a,
+                    _ => unreachable!(),
+                })
+                .collect();
+            layers.push(next);
+        }
+        MerkleTree { layers }
+    }
+
+    pub fn root(&self) -> Hash {
+        self.layers
+            .last()
+            .and_then(|layer| layer.first().copied())
+            .unwrap_or_default()
+    }
+
+    /// Sibling hashes from the leaf at `index` up to the root.
+    pub fn proof(&self, index: usize) -> Vec<Hash> {
+        let mut proof = vec![];
+        let mut index = index;
+        for layer in &self.layers[..self.layers.len() - 1] {
+            let sibling = index ^ 1;
+            if sibling < layer.len() {
+                proof.push(layer[sibling]);
+            }
+            index /= 2;
+        }
+        proof
+    }
+}
@@ -1,6 +1,7 @@
 use cosmwasm_schema::cw_serde;
 use cosmwasm_schema::QueryResponses;
-use cosmwasm_std::Uint128;
+use cosmwasm_std::{Binary, Coin, Decimal, Timestamp, Uint128};
+use cw20::Cw20ReceiveMsg;
 
 #[cw_serde]
 pub struct InstantiateMsg {
@@ -8,53 +9,502 @@ pub struct InstantiateMsg {
     pub expected_denom: String,
 }
 
+#[cw_serde]
+pub struct MigrateMsg {}
+
 #[cw_serde]
 pub enum ExecuteMsg {
-    Deposit {},
+    /// `vault_id` selects a sub-vault, the vault created at instantiation is `0`.
+    Deposit {
+        vault_id: Option<u64>,
+    },
     AddDepositAddress {
         address: String,
+        vault_id: Option<u64>,
     },
     RemoveDepositAddress {
         address: String,
+        vault_id: Option<u64>,
+    },
+    Withdraw {
+        vault_id: Option<u64>,
     },
-    Withdraw {},
     AddAllowance {
         spender: String,
         amount: Uint128,
+        vault_id: Option<u64>,
     },
     AddAllowanceList {
         spenders: Vec<String>,
         amounts: Vec<Uint128>,
+        vault_id: Option<u64>,
     },
     RemoveAllowance {
         spender: String,
+        vault_id: Option<u64>,
     },
     UpdateAllowance {
         spender: String,
         amount: Uint128,
+        vault_id: Option<u64>,
+    },
+    RetrieveAllowance {
+        vault_id: Option<u64>,
     },
-    RetrieveAllowance {},
     UpdateName {
         name: String,
+        vault_id: Option<u64>,
     },
     UpdateOwner {
         owner: String,
+        vault_id: Option<u64>,
+    },
+    AddVendor {
+        address: String,
+        name: String,
+        default_denom: String,
+    },
+    RemoveVendor {
+        address: String,
+    },
+    CreateInvoice {
+        vendor: String,
+        amount: Uint128,
+        denom: Option<String>,
+        due_date: Timestamp,
+        reference_id: String,
+    },
+    CancelInvoice {
+        id: u64,
+    },
+    PayDueInvoices {
+        limit: Option<u32>,
+    },
+    CreateGrant {
+        grantee: String,
+        arbiter: String,
+        milestones: Vec<MilestoneMsg>,
+    },
+    CompleteMilestone {
+        grant_id: u64,
+        milestone: u32,
+    },
+    CancelGrant {
+        grant_id: u64,
+    },
+    SetVoucherPubkey {
+        pubkey: Binary,
+    },
+    RedeemVoucher {
+        voucher: Voucher,
+        signature: Binary,
+    },
+    RegisterDistribution {
+        merkle_root: String,
+        total_amount: Uint128,
+        expiration: Option<Timestamp>,
+    },
+    ClaimDistribution {
+        round: u64,
+        amount: Uint128,
+        proof: Vec<String>,
+    },
+    ClawbackDistribution {
+        round: u64,
+    },
+    EnableShareMode {
+        token: String,
+    },
+    Receive(Cw20ReceiveMsg),
+    SetStakingConfig {
+        max_stake_ratio: Decimal,
+        unbonding_period: u64,
+    },
+    Delegate {
+        validator: String,
+        amount: Uint128,
+    },
+    Undelegate {
+        validator: String,
+        amount: Uint128,
+    },
+    WithdrawRewards {},
+    ClaimUnbonded {},
+    SetPayoutPreference {
+        channel_id: String,
+        remote_address: String,
+        timeout_seconds: u64,
+    },
+    RemovePayoutPreference {},
+    AddTrustedVault {
+        port_id: String,
+        connection_id: String,
+        remote_address: String,
+        transfer_channel_id: String,
+    },
+    RemoveTrustedVault {
+        port_id: String,
+    },
+    RequestFunds {
+        channel_id: String,
+        amount: Uint128,
+    },
+    /// Sent by the chain with the tokens of a remote vault's `FundTransfer`.
+    ReceiveVaultFunds {
+        channel_id: String,
+    },
+    SendFunds {
+        channel_id: String,
+        amount: Uint128,
+    },
+    ReportBalance {
+        channel_id: String,
+    },
+    /// Swaps the attached foreign denom into the payout denom and deposits
+    /// the result, reverting when the pair returns less than `min_return`.
+    SwapDeposit {
+        min_return: Uint128,
+    },
+    SetSwapConfig {
+        router: String,
+        auto_swap: bool,
+        max_slippage: Decimal,
+    },
+    SetOracleConfig {
+        oracle: String,
+        quote: String,
+        max_staleness: u64,
+        max_deviation: Decimal,
+        max_tokens_per_claim: Uint128,
+    },
+    AddPeggedAllowance {
+        spender: String,
+        quote_amount: Uint128,
+    },
+    RemovePeggedAllowance {
+        spender: String,
+    },
+    AddHook {
+        address: String,
+        blocking: bool,
+    },
+    RemoveHook {
+        address: String,
+    },
+    SetDeadManSwitch {
+        interval: u64,
+        guardians: Vec<String>,
+        threshold: u32,
+        recovery_period: u64,
+        beneficiary: String,
+    },
+    Heartbeat {},
+    VoteRecovery {
+        new_owner: String,
+    },
+    StartWindDown {},
+    ReleaseToBeneficiary {},
+    CloseVault {},
+    ClaimRefund {},
+    SetRateLimits {
+        max_claim_per_window: Option<Uint128>,
+        window_seconds: u64,
+        max_daily_outflow: Option<Uint128>,
+        bucket_seconds: u64,
+    },
+    CreateBudget {
+        name: String,
+        allocated: Uint128,
+    },
+    AssignBudget {
+        spender: String,
+        budget: String,
+    },
+    UnassignBudget {
+        spender: String,
+    },
+    ReallocateBudget {
+        from: String,
+        to: String,
+        amount: Uint128,
+    },
+    CreateVault {
+        name: String,
+        expected_denom: String,
+    },
+    /// Moves free funds between two vaults with the same owner and denom.
+    TransferBetweenVaults {
+        from_vault: u64,
+        to_vault: u64,
+        amount: Uint128,
+    },
+    StartCampaign {
+        goal: Uint128,
+        deadline: Timestamp,
+    },
+    UnlockCampaign {},
+    ReclaimContribution {},
+    /// Locks the attached funds to match deposits until `expires`.
+    PledgeMatch {
+        ratio: Decimal,
+        cap_per_depositor: Uint128,
+        expires: Timestamp,
+    },
+    WithdrawPledge {},
+    SetMinPledgeBudget {
+        amount: Uint128,
+    },
+    SetEarlyExitPenalty {
+        penalty: Decimal,
+    },
+    /// Opens a savings position with the attached funds.
+    DepositLocked {
+        lock_seconds: u64,
+    },
+    /// Returns the principal, minus the penalty before the lock ends.
+    ClosePosition {
+        id: u64,
+    },
+    SetFeeConfig {
+        deposit_bps: u16,
+        claim_bps: u16,
+        min_fee: Uint128,
+        max_fee: Option<Uint128>,
+        treasury: Option<String>,
+    },
+    RemoveFeeConfig {},
+    /// Sends the fees kept by the contract to the owner.
+    CollectFees {},
+    /// Sets the withholding of a spender, or the global one without a spender.
+    SetWithholding {
+        spender: Option<String>,
+        rate: Decimal,
+        destination: String,
+        brackets: Vec<WithholdingBracketMsg>,
+    },
+    RemoveWithholding {
+        spender: Option<String>,
+    },
+    SetComplianceRegistry {
+        registry: Option<String>,
+    },
+    AddToDenyList {
+        address: String,
+    },
+    RemoveFromDenyList {
+        address: String,
     },
+    /// Suspends the claims of a spender and keeps the allowance.
+    FreezeAllowance {
+        spender: String,
+    },
+    UnfreezeAllowance {
+        spender: String,
+    },
+    /// Reduces the allowance of a spender and records why.
+    ClawbackAllowance {
+        spender: String,
+        amount: Uint128,
+        reason: String,
+    },
+}
+
+impl ExecuteMsg {
+    /// Sub-vault the message is meant for, `None` for the vault created at
+    /// instantiation.
+    pub fn sub_vault_id(&self) -> Option<u64> {
+        let vault_id = match self {
+            ExecuteMsg::Deposit { vault_id }
+            | ExecuteMsg::AddDepositAddress { vault_id, .. }
+            | ExecuteMsg::RemoveDepositAddress { vault_id, .. }
+            | ExecuteMsg::Withdraw { vault_id }
+            | ExecuteMsg::AddAllowance { vault_id, .. }
+            | ExecuteMsg::AddAllowanceList { vault_id, .. }
+            | ExecuteMsg::RemoveAllowance { vault_id, .. }
+            | ExecuteMsg::UpdateAllowance { vault_id, .. }
+            | ExecuteMsg::RetrieveAllowance { vault_id }
+            | ExecuteMsg::UpdateName { vault_id, .. }
+            | ExecuteMsg::UpdateOwner { vault_id, .. } => *vault_id,
+            _ => None,
+        };
+        vault_id.filter(|id| *id != 0)
+    }
+}
+
+/// Packets exchanged between paired vaults.
+#[cw_serde]
+pub enum VaultPacket {
+    FundRequest { amount: Uint128 },
+    FundTransfer { amount: Uint128 },
+    BalanceReport { total_amount: Uint128 },
+}
+
+#[cw_serde]
+pub enum VaultAck {
+    Result(Binary),
+    Error(String),
+}
+
+/// Messages sent through the share token with `Cw20ExecuteMsg::Send`.
+#[cw_serde]
+pub enum ReceiveMsg {
+    RedeemShares {},
+}
+
+/// Off-chain payment signed by the owner. The signature is a secp256k1
+/// signature over the sha256 hash of the JSON encoded voucher.
+#[cw_serde]
+pub struct Voucher {
+    pub recipient: String,
+    pub amount: Uint128,
+    pub denom: String,
+    pub nonce: u64,
+    pub expires: Timestamp,
+    pub vault: String,
+}
+
+#[cw_serde]
+pub struct MilestoneMsg {
+    pub amount: Uint128,
+    pub description: String,
+}
+
+#[cw_serde]
+pub struct WithholdingBracketMsg {
+    pub threshold: Uint128,
+    pub rate: Decimal,
 }
 
 #[cw_serde]
 #[derive(QueryResponses)]
 pub enum QueryMsg {
     #[returns(StateResponse)]
-    GetState {},
+    GetState { vault_id: Option<u64> },
     #[returns(AllowanceResponse)]
-    GetAllowance { spender: String },
+    GetAllowance {
+        spender: String,
+        vault_id: Option<u64>,
+    },
     #[returns(AllowancesResponse)]
-    GetAllowances {},
+    GetAllowances { vault_id: Option<u64> },
     #[returns(CanDepositResponse)]
-    CanDeposit { address: String },
+    CanDeposit {
+        address: String,
+        vault_id: Option<u64>,
+    },
     #[returns(DepositAddressesResponse)]
-    GetDepositAddresses {},
+    GetDepositAddresses { vault_id: Option<u64> },
+    #[returns(VendorResponse)]
+    GetVendor { address: String },
+    #[returns(VendorsResponse)]
+    GetVendors {},
+    #[returns(InvoiceResponse)]
+    GetInvoice { id: u64 },
+    #[returns(InvoicesResponse)]
+    GetInvoices {},
+    #[returns(GrantResponse)]
+    GetGrant { id: u64 },
+    #[returns(GrantsResponse)]
+    GetGrants {},
+    #[returns(VoucherPubkeyResponse)]
+    GetVoucherPubkey {},
+    #[returns(NonceUsedResponse)]
+    IsNonceUsed { nonce: u64 },
+    #[returns(DistributionResponse)]
+    GetDistribution { round: u64 },
+    #[returns(DistributionClaimedResponse)]
+    IsDistributionClaimed { round: u64, address: String },
+    #[returns(ShareConfigResponse)]
+    GetShareConfig {},
+    #[returns(PreviewDepositResponse)]
+    PreviewDeposit { amount: Uint128 },
+    #[returns(PreviewRedeemResponse)]
+    PreviewRedeem { shares: Uint128 },
+    #[returns(StakingInfoResponse)]
+    GetStakingInfo {},
+    #[returns(PayoutPreferenceResponse)]
+    GetPayoutPreference { spender: String },
+    #[returns(PendingTransfersResponse)]
+    GetPendingTransfers {},
+    #[returns(TrustedVaultsResponse)]
+    GetTrustedVaults {},
+    #[returns(VaultChannelsResponse)]
+    GetVaultChannels {},
+    #[returns(SwapConfigResponse)]
+    GetSwapConfig {},
+    #[returns(OracleConfigResponse)]
+    GetOracleConfig {},
+    #[returns(PeggedAllowanceResponse)]
+    GetPeggedAllowance { spender: String },
+    #[returns(HooksResponse)]
+    GetHooks {},
+    #[returns(PausedResponse)]
+    IsPaused {},
+    #[returns(DeadManSwitchResponse)]
+    GetDeadManSwitch {},
+    #[returns(ClosureResponse)]
+    GetClosure {},
+    #[returns(RefundResponse)]
+    GetRefund { address: String },
+    #[returns(RemainingQuotaResponse)]
+    GetRemainingQuota { spender: Option<String> },
+    #[returns(BudgetResponse)]
+    GetBudget { name: String },
+    #[returns(BudgetsResponse)]
+    GetBudgets {},
+    #[returns(ReallocationsResponse)]
+    GetReallocations {},
+    #[returns(VaultsResponse)]
+    GetVaults {},
+    #[returns(CampaignResponse)]
+    GetCampaign {},
+    #[returns(PledgeResponse)]
+    GetPledge {
+        sponsor: String,
+        depositor: Option<String>,
+    },
+    #[returns(PledgesResponse)]
+    GetPledges {},
+    #[returns(PositionsResponse)]
+    GetPositions { owner: String },
+    #[returns(FeeConfigResponse)]
+    GetFeeConfig {},
+    /// Claims and withholding of a spender in a calendar year, by default the
+    /// current one.
+    #[returns(WithholdingReportResponse)]
+    GetWithholdingReport { spender: String, year: Option<u32> },
+    #[returns(ComplianceResponse)]
+    GetCompliance {},
+}
+
+/// Called by the chain. ICS20 acknowledgements and timeouts are reported by
+/// the IBC callback middleware (ibc-hooks) in this format.
+#[cw_serde]
+pub enum SudoMsg {
+    #[serde(rename = "ibc_lifecycle_complete")]
+    IbcLifecycleComplete(IbcLifecycleComplete),
+    ForceOwnerChange {
+        owner: String,
+    },
+    Pause {},
+    Unpause {},
+    /// Sends every token of the vault to `recipient` and empties the pool.
+    RecoverFunds {
+        recipient: String,
+    },
+}
+
+#[cw_serde]
+pub enum IbcLifecycleComplete {
+    #[serde(rename = "ibc_ack")]
+    IbcAck {
+        channel: String,
+        sequence: u64,
+        ack: String,
+        success: bool,
+    },
+    #[serde(rename = "ibc_timeout")]
+    IbcTimeout { channel: String, sequence: u64 },
 }
 
 #[cw_serde]
@@ -69,6 +519,15 @@ pub struct StateResponse {
 pub struct AllowanceResponse {
     pub spender: String,
     pub amount: Uint128,
+    pub frozen_since: Option<Timestamp>,
+    pub clawbacks: Vec<ClawbackResponse>,
+}
+
+#[cw_serde]
+pub struct ClawbackResponse {
+    pub amount: Uint128,
+    pub reason: String,
+    pub time: Timestamp,
 }
 
 #[cw_serde]
@@ -86,3 +545,415 @@ pub struct CanDepositResponse {
 pub struct DepositAddressesResponse {
     pub addresses: Vec<String>,
 }
+
+#[cw_serde]
+pub struct VendorResponse {
+    pub address: String,
+    pub name: String,
+    pub default_denom: String,
+}
+
+#[cw_serde]
+pub struct VendorsResponse {
+    pub vendors: Vec<VendorResponse>,
+}
+
+#[cw_serde]
+pub struct InvoiceReceiptResponse {
+    pub paid_at: Timestamp,
+    pub block_height: u64,
+    pub amount: Uint128,
+}
+
+#[cw_serde]
+pub struct InvoiceResponse {
+    pub id: u64,
+    pub vendor: String,
+    pub amount: Uint128,
+    pub denom: String,
+    pub due_date: Timestamp,
+    pub reference_id: String,
+    pub receipt: Option<InvoiceReceiptResponse>,
+}
+
+#[cw_serde]
+pub struct InvoicesResponse {
+    pub invoices: Vec<InvoiceResponse>,
+}
+
+#[cw_serde]
+pub struct MilestoneResponse {
+    pub amount: Uint128,
+    pub description: String,
+    pub completed_at: Option<Timestamp>,
+}
+
+#[cw_serde]
+pub struct GrantResponse {
+    pub id: u64,
+    pub grantee: String,
+    pub arbiter: String,
+    pub milestones: Vec<MilestoneResponse>,
+    pub released_amount: Uint128,
+    pub remaining_amount: Uint128,
+    pub cancelled: bool,
+}
+
+#[cw_serde]
+pub struct GrantsResponse {
+    pub grants: Vec<GrantResponse>,
+}
+
+#[cw_serde]
+pub struct VoucherPubkeyResponse {
+    pub pubkey: Option<Binary>,
+}
+
+#[cw_serde]
+pub struct NonceUsedResponse {
+    pub used: bool,
+}
+
+#[cw_serde]
+pub struct DistributionResponse {
+    pub round: u64,
+    pub merkle_root: String,
+    pub total_amount: Uint128,
+    pub claimed_amount: Uint128,
+    pub expiration: Option<Timestamp>,
+    pub clawed_back: bool,
+}
+
+#[cw_serde]
+pub struct DistributionClaimedResponse {
+    pub claimed: bool,
+}
+
+#[cw_serde]
+pub struct ShareConfigResponse {
+    pub token: Option<String>,
+    pub total_supply: Uint128,
+    pub free_amount: Uint128,
+}
+
+#[cw_serde]
+pub struct PreviewDepositResponse {
+    pub shares: Uint128,
+}
+
+#[cw_serde]
+pub struct PreviewRedeemResponse {
+    pub amount: Uint128,
+}
+
+#[cw_serde]
+pub struct DelegationResponse {
+    pub validator: String,
+    pub amount: Uint128,
+}
+
+#[cw_serde]
+pub struct UnbondingResponse {
+    pub id: u64,
+    pub validator: String,
+    pub amount: Uint128,
+    pub completes_at: Timestamp,
+}
+
+#[cw_serde]
+pub struct StakingInfoResponse {
+    pub max_stake_ratio: Decimal,
+    pub unbonding_period: u64,
+    pub total_staked: Uint128,
+    pub delegations: Vec<DelegationResponse>,
+    pub unbondings: Vec<UnbondingResponse>,
+}
+
+#[cw_serde]
+pub struct PayoutPreferenceResponse {
+    pub channel_id: Option<String>,
+    pub remote_address: Option<String>,
+    pub timeout_seconds: Option<u64>,
+}
+
+#[cw_serde]
+pub struct PendingTransferResponse {
+    pub channel_id: String,
+    pub sequence: u64,
+    pub spender: String,
+    pub amount: Uint128,
+    pub vault_channel_id: Option<String>,
+    pub withheld: Uint128,
+}
+
+#[cw_serde]
+pub struct PendingTransfersResponse {
+    pub transfers: Vec<PendingTransferResponse>,
+}
+
+#[cw_serde]
+pub struct TrustedVaultResponse {
+    pub port_id: String,
+    pub connection_id: String,
+    pub remote_address: String,
+    pub transfer_channel_id: String,
+}
+
+#[cw_serde]
+pub struct TrustedVaultsResponse {
+    pub vaults: Vec<TrustedVaultResponse>,
+}
+
+#[cw_serde]
+pub struct VaultChannelResponse {
+    pub channel_id: String,
+    pub counterparty_port_id: String,
+    pub requested_by_peer: Uint128,
+    pub incoming: Uint128,
+    pub sent: Uint128,
+    pub received: Uint128,
+    pub reported_balance: Option<Uint128>,
+    pub reported_at: Option<Timestamp>,
+}
+
+#[cw_serde]
+pub struct VaultChannelsResponse {
+    pub channels: Vec<VaultChannelResponse>,
+}
+
+#[cw_serde]
+pub struct SwapConfigResponse {
+    pub router: Option<String>,
+    pub auto_swap: bool,
+    pub max_slippage: Decimal,
+}
+
+#[cw_serde]
+pub struct OracleConfigResponse {
+    pub oracle: String,
+    pub quote: String,
+    pub max_staleness: u64,
+    pub max_deviation: Decimal,
+    pub max_tokens_per_claim: Uint128,
+    pub last_price: Option<Decimal>,
+    pub last_price_at: Option<Timestamp>,
+}
+
+#[cw_serde]
+pub struct PeggedAllowanceResponse {
+    pub spender: String,
+    pub quote_amount: Uint128,
+    pub quote: String,
+}
+
+/// Sent to every registered hook when money moves or an allowance changes.
+#[cw_serde]
+pub enum VaultHookMsg {
+    Deposit {
+        address: String,
+        amount: Uint128,
+    },
+    Claim {
+        spender: String,
+        amount: Uint128,
+    },
+    Withdraw {
+        owner: String,
+        amount: Vec<Coin>,
+    },
+    /// `amount` is `None` when the allowance was removed. Pegged allowances
+    /// report it in the oracle's quote currency.
+    AllowanceChanged {
+        spender: String,
+        amount: Option<Uint128>,
+    },
+}
+
+/// Execute message of the hook contracts.
+#[cw_serde]
+pub enum HookExecuteMsg {
+    VaultHook(VaultHookMsg),
+}
+
+#[cw_serde]
+pub struct HookInfo {
+    pub address: String,
+    pub blocking: bool,
+}
+
+#[cw_serde]
+pub struct HooksResponse {
+    pub hooks: Vec<HookInfo>,
+}
+
+#[cw_serde]
+pub struct PausedResponse {
+    pub paused: bool,
+}
+
+#[cw_serde]
+pub struct RecoveryVote {
+    pub guardian: String,
+    pub new_owner: String,
+}
+
+#[cw_serde]
+pub struct DeadManSwitchResponse {
+    pub interval: u64,
+    pub guardians: Vec<String>,
+    pub threshold: u32,
+    pub recovery_period: u64,
+    pub beneficiary: String,
+    pub last_heartbeat: Timestamp,
+    /// Seconds until the switch triggers, zero once it has lapsed.
+    pub seconds_remaining: u64,
+    pub lapsed: bool,
+    pub wind_down: bool,
+    pub votes: Vec<RecoveryVote>,
+}
+
+#[cw_serde]
+pub struct ClosureResponse {
+    pub closed: bool,
+    pub finalized: bool,
+    pub refund_pool: Uint128,
+    pub total_deposits: Uint128,
+}
+
+#[cw_serde]
+pub struct RefundResponse {
+    pub amount: Uint128,
+    pub claimed: bool,
+}
+
+/// `None` means there is no limit.
+#[cw_serde]
+pub struct RemainingQuotaResponse {
+    pub spender_remaining: Option<Uint128>,
+    pub daily_outflow_remaining: Option<Uint128>,
+}
+
+#[cw_serde]
+pub struct BudgetResponse {
+    pub name: String,
+    pub allocated: Uint128,
+    /// Sum of the allowances assigned to the budget.
+    pub reserved: Uint128,
+    pub spent: Uint128,
+}
+
+#[cw_serde]
+pub struct BudgetsResponse {
+    pub budgets: Vec<BudgetResponse>,
+}
+
+#[cw_serde]
+pub struct ReallocationResponse {
+    pub id: u64,
+    pub from: String,
+    pub to: String,
+    pub amount: Uint128,
+    pub time: Timestamp,
+}
+
+#[cw_serde]
+pub struct ReallocationsResponse {
+    pub reallocations: Vec<ReallocationResponse>,
+}
+
+#[cw_serde]
+pub struct VaultInfo {
+    pub id: u64,
+    pub owner: String,
+    pub name: String,
+    pub total_amount: Uint128,
+    pub expected_denom: String,
+}
+
+#[cw_serde]
+pub struct VaultsResponse {
+    pub vaults: Vec<VaultInfo>,
+}
+
+#[cw_serde]
+pub struct CampaignResponse {
+    pub active: bool,
+    pub goal: Uint128,
+    pub deadline: Timestamp,
+    /// End of the period in which contributions to a failed campaign can be reclaimed.
+    pub reclaim_deadline: Timestamp,
+    pub raised: Uint128,
+    pub reclaimed: Uint128,
+    pub unlocked: bool,
+    pub failed: bool,
+}
+
+#[cw_serde]
+pub struct PledgeResponse {
+    pub sponsor: String,
+    pub ratio: Decimal,
+    pub cap_per_depositor: Uint128,
+    pub budget: Uint128,
+    pub matched: Uint128,
+    pub expires: Timestamp,
+    pub expired: bool,
+    /// What can still be matched for the given depositor, if any.
+    pub depositor_remaining: Option<Uint128>,
+}
+
+#[cw_serde]
+pub struct PledgesResponse {
+    pub pledges: Vec<PledgeResponse>,
+}
+
+#[cw_serde]
+pub struct PositionResponse {
+    pub id: u64,
+    pub amount: Uint128,
+    pub deposited_at: Timestamp,
+    pub unlocks_at: Timestamp,
+    pub penalty: Decimal,
+    pub unlocked: bool,
+}
+
+#[cw_serde]
+pub struct PositionsResponse {
+    pub early_exit_penalty: Option<Decimal>,
+    pub positions: Vec<PositionResponse>,
+}
+
+#[cw_serde]
+pub struct FeeConfigResponse {
+    pub deposit_bps: u16,
+    pub claim_bps: u16,
+    pub min_fee: Uint128,
+    pub max_fee: Option<Uint128>,
+    pub treasury: Option<String>,
+    pub accrued: Uint128,
+}
+
+/// Set as response data of deposits and claims that pay a fee.
+#[cw_serde]
+pub struct FeeReceipt {
+    pub gross: Uint128,
+    pub fee: Uint128,
+    pub net: Uint128,
+}
+
+#[cw_serde]
+pub struct WithholdingReportResponse {
+    pub spender: String,
+    pub year: u32,
+    pub claimed: Uint128,
+    pub withheld: Uint128,
+    pub rate: Option<Decimal>,
+    pub destination: Option<String>,
+    pub brackets: Vec<WithholdingBracketMsg>,
+}
+
+#[cw_serde]
+pub struct ComplianceResponse {
+    pub registry: Option<String>,
+    pub denied: Vec<String>,
+    pub frozen: Vec<String>,
+}
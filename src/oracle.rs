@@ -0,0 +1,16 @@
+//! Messages of the price oracle used for allowances in a quote currency.
+
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Decimal, Timestamp};
+
+#[cw_serde]
+pub enum OracleQueryMsg {
+    /// Price of one unit of `denom` in `quote`.
+    Price { denom: String, quote: String },
+}
+
+#[cw_serde]
+pub struct PriceResponse {
+    pub price: Decimal,
+    pub updated_at: Timestamp,
+}
@@ -1,4 +1,4 @@
-use cosmwasm_std::{Addr, Uint128};
+use cosmwasm_std::{Addr, Binary, Coin, Decimal, Timestamp, Uint128};
 use cw_storage_plus::{Item, Map};
 use serde::{Deserialize, Serialize};
 
@@ -11,5 +11,421 @@ pub struct State {
 }
 
 pub const DEPOSIT_ADDRESSES: Map<Addr, Uint128> = Map::new("deposit_addresses");
+/// What each address deposited, the basis of the refunds of a closed vault.
+/// Unlike `DEPOSIT_ADDRESSES` it stays when the address may no longer deposit.
+pub const DEPOSITED: Map<Addr, Uint128> = Map::new("deposited");
 pub const ALLOWANCES: Map<Addr, Uint128> = Map::new("allowances");
 pub const STATE: Item<State> = Item::new("state");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Vendor {
+    pub name: String,
+    pub default_denom: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct InvoiceReceipt {
+    pub paid_at: Timestamp,
+    pub block_height: u64,
+    pub amount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Invoice {
+    pub vendor: Addr,
+    pub amount: Uint128,
+    pub denom: String,
+    pub due_date: Timestamp,
+    pub reference_id: String,
+    pub receipt: Option<InvoiceReceipt>,
+}
+
+pub const VENDORS: Map<Addr, Vendor> = Map::new("vendors");
+pub const INVOICES: Map<u64, Invoice> = Map::new("invoices");
+/// Unpaid invoices by (due date in nanoseconds, ID), so payments only visit due ones.
+pub const UNPAID_INVOICES: Map<(u64, u64), bool> = Map::new("unpaid_invoices");
+pub const INVOICE_COUNT: Item<u64> = Item::new("invoice_count");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Milestone {
+    pub amount: Uint128,
+    pub description: String,
+    pub completed_at: Option<Timestamp>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Grant {
+    pub grantee: Addr,
+    pub arbiter: Addr,
+    pub milestones: Vec<Milestone>,
+    pub cancelled: bool,
+    /// Budget of the grantee the grant was charged to.
+    pub budget: Option<String>,
+}
+
+pub const GRANTS: Map<u64, Grant> = Map::new("grants");
+pub const GRANT_COUNT: Item<u64> = Item::new("grant_count");
+/// Tokens locked for unreleased milestones and open distribution rounds. They
+/// are no longer part of `State.total_amount` but are still held by the contract.
+pub const ESCROW: Item<Uint128> = Item::new("escrow");
+
+/// secp256k1 public key of the owner used to sign off-chain vouchers.
+pub const VOUCHER_PUBKEY: Item<Binary> = Item::new("voucher_pubkey");
+pub const USED_NONCES: Map<u64, bool> = Map::new("used_nonces");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Distribution {
+    /// Hex encoded merkle root of the `(address, amount)` leaves.
+    pub merkle_root: String,
+    pub total_amount: Uint128,
+    pub claimed_amount: Uint128,
+    pub expiration: Option<Timestamp>,
+    pub clawed_back: bool,
+}
+
+pub const DISTRIBUTIONS: Map<u64, Distribution> = Map::new("distributions");
+pub const DISTRIBUTION_COUNT: Item<u64> = Item::new("distribution_count");
+pub const DISTRIBUTION_CLAIMS: Map<(u64, Addr), bool> = Map::new("distribution_claims");
+
+/// Share mode is enabled once this is set. Deposits then mint shares of the
+/// CW20 `token` and sending shares back to the vault redeems them.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ShareConfig {
+    pub token: Addr,
+    pub total_supply: Uint128,
+}
+
+pub const SHARE_CONFIG: Item<ShareConfig> = Item::new("share_config");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StakingConfig {
+    /// Maximum part of the vault funds (pool and stake) that can be delegated.
+    pub max_stake_ratio: Decimal,
+    /// Unbonding time of the chain in seconds.
+    pub unbonding_period: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Unbonding {
+    pub validator: String,
+    pub amount: Uint128,
+    pub completes_at: Timestamp,
+}
+
+pub const STAKING_CONFIG: Item<StakingConfig> = Item::new("staking_config");
+pub const DELEGATIONS: Map<String, Uint128> = Map::new("delegations");
+pub const UNBONDINGS: Map<u64, Unbonding> = Map::new("unbondings");
+pub const UNBONDING_COUNT: Item<u64> = Item::new("unbonding_count");
+/// Balance the vault expects after a staking message. Anything above it was
+/// paid out as staking rewards and is added to the pool in the reply.
+pub const STAKING_BALANCE: Item<Uint128> = Item::new("staking_balance");
+
+/// Pays a spender's allowance to an address on another chain over ICS20.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PayoutPreference {
+    pub channel_id: String,
+    pub remote_address: String,
+    pub timeout_seconds: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PendingTransfer {
+    pub spender: Addr,
+    pub channel_id: String,
+    pub amount: Uint128,
+    /// Vault channel whose `FundTransfer` this transfer pays, with the vault
+    /// itself as the spender.
+    pub vault_channel_id: Option<String>,
+    /// Withholding of the payout, kept in the vault until the ack.
+    pub withholding: Option<HeldWithholding>,
+}
+
+/// Withheld part of an IBC payout. It goes to the destination when the
+/// transfer succeeds and is undone, with the year's totals, when it fails.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HeldWithholding {
+    pub destination: Addr,
+    pub amount: Uint128,
+    pub year: u32,
+}
+
+pub const PAYOUT_PREFERENCES: Map<Addr, PayoutPreference> = Map::new("payout_preferences");
+/// Transfer sent in the current transaction that waits for its packet sequence.
+pub const OUTGOING_TRANSFER: Item<PendingTransfer> = Item::new("outgoing_transfer");
+/// Withholding of all IBC payouts waiting for their ack.
+pub const HELD_WITHHOLDING: Item<Uint128> = Item::new("held_withholding");
+/// Transfers waiting for their acknowledgement, keyed by channel and sequence.
+pub const PENDING_TRANSFERS: Map<(String, u64), PendingTransfer> = Map::new("pending_transfers");
+
+/// Vault on another chain that may open a channel to this one.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TrustedVault {
+    /// Local connection the remote vault's channels must be opened on.
+    pub connection_id: String,
+    /// Address of the remote vault that receives ICS20 transfers.
+    pub remote_address: String,
+    /// ICS20 channel used to move tokens to the remote vault.
+    pub transfer_channel_id: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct VaultChannel {
+    pub counterparty_port_id: String,
+    /// Amount the remote vault asked for that has not been sent yet.
+    pub requested_by_peer: Uint128,
+    /// Amount the remote vault announced whose ICS20 transfer has not arrived.
+    pub incoming: Uint128,
+    pub sent: Uint128,
+    pub received: Uint128,
+    pub reported_balance: Option<Uint128>,
+    pub reported_at: Option<Timestamp>,
+}
+
+/// Trusted remote vaults keyed by their IBC port id, each bound to a connection.
+pub const TRUSTED_VAULTS: Map<String, TrustedVault> = Map::new("trusted_vaults");
+pub const VAULT_CHANNELS: Map<String, VaultChannel> = Map::new("vault_channels");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SwapConfig {
+    pub router: Addr,
+    pub auto_swap: bool,
+    pub max_slippage: Decimal,
+}
+
+/// Deposit waiting for the swap reply before it is credited.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PendingSwap {
+    pub router: Addr,
+    pub depositor: Addr,
+    pub offer: Coin,
+    pub min_return: Uint128,
+}
+
+pub const SWAP_CONFIG: Item<SwapConfig> = Item::new("swap_config");
+pub const PENDING_SWAP: Item<PendingSwap> = Item::new("pending_swap");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct OracleConfig {
+    pub oracle: Addr,
+    pub quote: String,
+    pub max_staleness: u64,
+    pub max_deviation: Decimal,
+    pub max_tokens_per_claim: Uint128,
+}
+
+pub const ORACLE_CONFIG: Item<OracleConfig> = Item::new("oracle_config");
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ReferencePrice {
+    pub price: Decimal,
+    pub time: Timestamp,
+}
+
+/// Last price a pegged allowance was paid at, the base of the deviation guard
+/// while it is younger than `PRICE_REFERENCE_SECONDS`.
+pub const LAST_PRICE: Item<ReferencePrice> = Item::new("last_price");
+/// Allowances in the quote currency of the oracle.
+pub const PEGGED_ALLOWANCES: Map<Addr, Uint128> = Map::new("pegged_allowances");
+/// Tokens each pegged allowance held back at the last price it was valued at.
+pub const PEGGED_RESERVES: Map<Addr, Uint128> = Map::new("pegged_reserves");
+/// Running sum of `ALLOWANCES` and `PEGGED_RESERVES`.
+pub const RESERVED_TOTAL: Item<Uint128> = Item::new("reserved_total");
+
+/// Registered hook contracts and whether a failing hook blocks the action.
+pub const HOOKS: Map<Addr, bool> = Map::new("hooks");
+
+/// Set by chain governance through `sudo`; blocks every execute message.
+pub const PAUSED: Item<bool> = Item::new("paused");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DeadManSwitch {
+    pub interval: u64,
+    pub guardians: Vec<Addr>,
+    pub threshold: u32,
+    /// Time after the lapse in which only the guardians can act.
+    pub recovery_period: u64,
+    pub beneficiary: Addr,
+    pub last_heartbeat: Timestamp,
+    pub wind_down: bool,
+}
+
+pub const DEAD_MAN_SWITCH: Item<DeadManSwitch> = Item::new("dead_man_switch");
+/// New owner each guardian voted for.
+pub const RECOVERY_VOTES: Map<Addr, Addr> = Map::new("recovery_votes");
+
+/// Set by `CloseVault`. What is left after the reserved allowances is refunded
+/// to the depositors in proportion to their deposits.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Closure {
+    pub refund_pool: Uint128,
+    pub total_deposits: Uint128,
+    /// Depositors with a refund that has not been claimed yet.
+    pub unpaid_refunds: u64,
+    pub finalized: bool,
+}
+
+pub const CLOSURE: Item<Closure> = Item::new("closure");
+pub const REFUND_CLAIMS: Map<Addr, Uint128> = Map::new("refund_claims");
+
+/// Outflows are counted in buckets of `bucket_seconds`. Every bucket that
+/// overlaps a window counts towards it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RateLimits {
+    pub max_claim_per_window: Option<Uint128>,
+    pub window_seconds: u64,
+    pub max_daily_outflow: Option<Uint128>,
+    pub bucket_seconds: u64,
+}
+
+pub const RATE_LIMITS: Item<RateLimits> = Item::new("rate_limits");
+/// Claimed amount per spender and bucket.
+pub const SPENDER_OUTFLOW: Map<(Addr, u64), Uint128> = Map::new("spender_outflow");
+/// Claimed and withdrawn amount of the whole vault per bucket.
+pub const VAULT_OUTFLOW: Map<u64, Uint128> = Map::new("vault_outflow");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Budget {
+    pub allocated: Uint128,
+    pub spent: Uint128,
+    /// Running total of what the assigned allowances hold back.
+    pub reserved: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BudgetLink {
+    pub budget: String,
+    /// Part of the budget's `reserved` that comes from this spender.
+    pub reserved: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Reallocation {
+    pub from: String,
+    pub to: String,
+    pub amount: Uint128,
+    pub time: Timestamp,
+}
+
+pub const BUDGETS: Map<String, Budget> = Map::new("budgets");
+/// Budget each spender's allowance is paid from.
+pub const ALLOWANCE_BUDGETS: Map<Addr, BudgetLink> = Map::new("allowance_budgets");
+pub const REALLOCATIONS: Map<u64, Reallocation> = Map::new("reallocations");
+pub const REALLOCATION_COUNT: Item<u64> = Item::new("reallocation_count");
+
+/// Sub-vaults created with `CreateVault`. The vault created at instantiation
+/// keeps `STATE`, `DEPOSIT_ADDRESSES` and `ALLOWANCES` and has ID 0.
+pub const VAULTS: Map<u64, State> = Map::new("vaults");
+pub const VAULT_COUNT: Item<u64> = Item::new("vault_count");
+pub const VAULT_DEPOSIT_ADDRESSES: Map<(u64, Addr), Uint128> = Map::new("vault_deposit_addresses");
+pub const VAULT_ALLOWANCES: Map<(u64, Addr), Uint128> = Map::new("vault_allowances");
+
+/// Fundraising campaign. Contributions are kept apart from the cumulative
+/// `DEPOSIT_ADDRESSES` amounts and start from zero with every campaign.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Campaign {
+    pub goal: Uint128,
+    pub deadline: Timestamp,
+    pub raised: Uint128,
+    pub reclaimed: Uint128,
+    pub unlocked: bool,
+}
+
+pub const CAMPAIGN: Item<Campaign> = Item::new("campaign");
+pub const CAMPAIGN_CONTRIBUTIONS: Map<Addr, Uint128> = Map::new("campaign_contributions");
+
+/// Matching pledge of a sponsor. `budget` is what is left of the locked funds,
+/// which are held by the contract but not part of `total_amount`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Pledge {
+    pub ratio: Decimal,
+    pub cap_per_depositor: Uint128,
+    pub budget: Uint128,
+    pub matched: Uint128,
+    pub expires: Timestamp,
+}
+
+pub const PLEDGES: Map<Addr, Pledge> = Map::new("pledges");
+/// Pledges by (expiry in nanoseconds, sponsor), so deposits only visit running ones.
+pub const PLEDGE_EXPIRIES: Map<(u64, Addr), bool> = Map::new("pledge_expiries");
+/// Unused budget of all pledges, running or expired.
+pub const PLEDGED_TOTAL: Item<Uint128> = Item::new("pledged_total");
+/// Smallest budget a new pledge can lock.
+pub const MIN_PLEDGE_BUDGET: Item<Uint128> = Item::new("min_pledge_budget");
+/// Amount matched per (sponsor, depositor).
+pub const PLEDGE_MATCHES: Map<(Addr, Addr), Uint128> = Map::new("pledge_matches");
+
+/// Penalty rate taken from early exits of positions opened from now on.
+pub const EARLY_EXIT_PENALTY: Item<Decimal> = Item::new("early_exit_penalty");
+
+/// Time-locked deposit. The principal stays with the depositor and is not
+/// part of `total_amount`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Position {
+    pub amount: Uint128,
+    pub deposited_at: Timestamp,
+    pub unlocks_at: Timestamp,
+    pub penalty: Decimal,
+}
+
+pub const POSITIONS: Map<(Addr, u64), Position> = Map::new("positions");
+pub const POSITION_COUNT: Item<u64> = Item::new("position_count");
+
+/// Protocol fees in basis points. Each fee is kept between `min_fee` and
+/// `max_fee`, and never above the amount it is taken from.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FeeConfig {
+    pub deposit_bps: u16,
+    pub claim_bps: u16,
+    pub min_fee: Uint128,
+    pub max_fee: Option<Uint128>,
+    /// Fees are sent here right away, or kept for `CollectFees` when unset.
+    pub treasury: Option<Addr>,
+}
+
+pub const FEE_CONFIG: Item<FeeConfig> = Item::new("fee_config");
+/// Fees held by the contract, which are not part of `total_amount`.
+pub const ACCRUED_FEES: Item<Uint128> = Item::new("accrued_fees");
+
+/// Claimed amounts from `threshold` on in a calendar year are withheld at `rate`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct WithholdingBracket {
+    pub threshold: Uint128,
+    pub rate: Decimal,
+}
+
+/// Withholding on claims. `rate` applies below the first bracket.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct WithholdingRule {
+    pub rate: Decimal,
+    pub destination: Addr,
+    pub brackets: Vec<WithholdingBracket>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct WithholdingTotals {
+    pub claimed: Uint128,
+    pub withheld: Uint128,
+}
+
+pub const GLOBAL_WITHHOLDING: Item<WithholdingRule> = Item::new("global_withholding");
+/// Rules for single spenders, used instead of the global rule.
+pub const WITHHOLDING_RULES: Map<Addr, WithholdingRule> = Map::new("withholding_rules");
+/// Claims and withholding per (spender, calendar year).
+pub const WITHHOLDING_TOTALS: Map<(Addr, u32), WithholdingTotals> = Map::new("withholding_totals");
+
+/// Registry queried with `ComplianceQueryMsg::IsFlagged` before moving funds.
+pub const COMPLIANCE_REGISTRY: Item<Addr> = Item::new("compliance_registry");
+/// Addresses denied by the owner, checked with or without a registry.
+pub const DENY_LIST: Map<Addr, bool> = Map::new("deny_list");
+/// Spenders whose claims are suspended, with the time of the freeze.
+pub const FROZEN_ALLOWANCES: Map<Addr, Timestamp> = Map::new("frozen_allowances");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Clawback {
+    pub amount: Uint128,
+    pub reason: String,
+    pub time: Timestamp,
+}
+
+/// Clawbacks of each spender's allowance by ID, oldest first.
+pub const CLAWBACKS: Map<(Addr, u64), Clawback> = Map::new("clawbacks");
+pub const CLAWBACK_COUNT: Item<u64> = Item::new("clawback_count");
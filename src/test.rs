@@ -1,22 +1,196 @@
-#[cfg(test)]
-use crate::contract::{execute, instantiate_contract, query};
+use crate::compliance::{ComplianceQueryMsg, FlaggedResponse};
+use crate::contract::{
+    calendar_year, free_amount, CAMPAIGN_RECLAIM_SECONDS, IBC_TRANSFER_REPLY_ID,
+};
+use crate::dex::{DexExecuteMsg, DexQueryMsg, SimulationResponse};
+use crate::error::ContractError;
+use crate::ibc::IBC_VERSION;
+use crate::ics20::{callback_memo, transfer_msg, vault_funds_memo, MSG_TRANSFER_TYPE_URL};
+use crate::merkle::{leaf_hash, verify_proof, MerkleTree};
 use crate::msg::{
-    AllowanceResponse, AllowancesResponse, CanDepositResponse, DepositAddressesResponse,
-    ExecuteMsg, InstantiateMsg, QueryMsg, StateResponse,
+    AllowanceResponse, AllowancesResponse, BudgetResponse, BudgetsResponse, CampaignResponse,
+    CanDepositResponse, ClawbackResponse, ClosureResponse, ComplianceResponse,
+    DeadManSwitchResponse, DepositAddressesResponse, DistributionClaimedResponse,
+    DistributionResponse, ExecuteMsg, FeeConfigResponse, FeeReceipt, GrantResponse, HookExecuteMsg,
+    HooksResponse, IbcLifecycleComplete, InstantiateMsg, InvoiceResponse, InvoicesResponse,
+    MigrateMsg, MilestoneMsg, NonceUsedResponse, OracleConfigResponse, PausedResponse,
+    PeggedAllowanceResponse, PendingTransfersResponse, PledgeResponse, PledgesResponse,
+    PositionsResponse, PreviewDepositResponse, PreviewRedeemResponse, QueryMsg,
+    ReallocationsResponse, ReceiveMsg, RefundResponse, RemainingQuotaResponse, ShareConfigResponse,
+    StakingInfoResponse, StateResponse, SudoMsg, SwapConfigResponse, VaultAck,
+    VaultChannelsResponse, VaultHookMsg, VaultPacket, VaultsResponse, VendorsResponse, Voucher,
+    WithholdingBracketMsg, WithholdingReportResponse,
+};
+use crate::oracle::{OracleQueryMsg, PriceResponse};
+use crate::state::{Unbonding, DELEGATIONS, RESERVED_TOTAL, STATE, UNBONDINGS};
+use crate::{
+    execute, ibc_channel_connect, ibc_channel_open, ibc_packet_ack, ibc_packet_receive,
+    ibc_packet_timeout, instantiate, migrate, query, reply, sudo,
 };
-use crate::{execute, instantiate, query};
 use cosmwasm_std::{
-    coins,
-    testing::{mock_env, MockApi, MockQuerier, MockStorage, MOCK_CONTRACT_ADDR},
-    Addr, Empty, Uint128,
+    coins, from_json, testing::mock_dependencies, testing::mock_env,
+    testing::mock_ibc_channel_connect_ack, testing::mock_ibc_channel_open_try,
+    testing::mock_ibc_packet_ack, testing::mock_ibc_packet_recv, testing::mock_ibc_packet_timeout,
+    testing::mock_info, to_json_binary, to_json_vec, Addr, Binary, CosmosMsg, Decimal, Empty,
+    IbcAcknowledgement, IbcMsg, IbcOrder, Reply, SubMsg, SubMsgResponse, SubMsgResult, Timestamp,
+    Uint128, Validator,
 };
-use cw_multi_test::{App, BankKeeper, Contract, ContractWrapper, Executor};
+use cw20::{BalanceResponse, Cw20ExecuteMsg, MinterResponse};
+use cw_multi_test::{App, Contract, ContractWrapper, Executor, StakingInfo};
+use cw_storage_plus::Item;
+use k256::ecdsa::{signature::hazmat::PrehashSigner, Signature, SigningKey};
+use sha2::{Digest, Sha256};
 
 fn vault_contract() -> Box<dyn Contract<Empty>> {
-    let contract = ContractWrapper::new(execute, instantiate, query);
+    let contract = ContractWrapper::new(execute, instantiate, query)
+        .with_reply(reply)
+        .with_sudo(sudo);
+    Box::new(contract)
+}
+
+fn cw20_contract() -> Box<dyn Contract<Empty>> {
+    let contract = ContractWrapper::new(
+        cw20_base::contract::execute,
+        cw20_base::contract::instantiate,
+        cw20_base::contract::query,
+    );
+    Box::new(contract)
+}
+
+/// Pair that quotes one rate and pays another, so slippage can be tested.
+#[cosmwasm_schema::cw_serde]
+struct MockAmmInstantiateMsg {
+    quoted_rate: Decimal,
+    paid_rate: Decimal,
+}
+
+const MOCK_AMM_RATES: Item<MockAmmInstantiateMsg> = Item::new("rates");
+
+fn mock_amm_contract() -> Box<dyn Contract<Empty>> {
+    let contract = ContractWrapper::new(
+        |deps, _env, info, msg: DexExecuteMsg| -> cosmwasm_std::StdResult<cosmwasm_std::Response> {
+            let DexExecuteMsg::Swap {
+                offer_asset, to, ..
+            } = msg;
+            let rates = MOCK_AMM_RATES.load(deps.storage)?;
+            let return_amount = offer_asset.amount * rates.paid_rate;
+            Ok(cosmwasm_std::Response::new()
+                .add_message(cosmwasm_std::BankMsg::Send {
+                    to_address: to.unwrap_or(info.sender.to_string()),
+                    amount: coins(return_amount.u128(), "atom"),
+                })
+                .add_attribute("action", "swap")
+                .add_attribute("return_amount", return_amount))
+        },
+        |deps,
+         _env,
+         _info,
+         msg: MockAmmInstantiateMsg|
+         -> cosmwasm_std::StdResult<cosmwasm_std::Response> {
+            MOCK_AMM_RATES.save(deps.storage, &msg)?;
+            Ok(cosmwasm_std::Response::new())
+        },
+        |deps, _env, msg: DexQueryMsg| -> cosmwasm_std::StdResult<Binary> {
+            let DexQueryMsg::Simulation { offer_asset, .. } = msg;
+            let rates = MOCK_AMM_RATES.load(deps.storage)?;
+            to_json_binary(&SimulationResponse {
+                return_amount: offer_asset.amount * rates.quoted_rate,
+                spread_amount: Uint128::zero(),
+                commission_amount: Uint128::zero(),
+            })
+        },
+    );
+    Box::new(contract)
+}
+
+/// Oracle that answers every price query with the last price it was given.
+const MOCK_ORACLE_PRICE: Item<PriceResponse> = Item::new("price");
+
+fn mock_oracle_contract() -> Box<dyn Contract<Empty>> {
+    let contract = ContractWrapper::new(
+        |deps,
+         _env,
+         _info,
+         msg: PriceResponse|
+         -> cosmwasm_std::StdResult<cosmwasm_std::Response> {
+            MOCK_ORACLE_PRICE.save(deps.storage, &msg)?;
+            Ok(cosmwasm_std::Response::new())
+        },
+        |deps,
+         _env,
+         _info,
+         msg: PriceResponse|
+         -> cosmwasm_std::StdResult<cosmwasm_std::Response> {
+            MOCK_ORACLE_PRICE.save(deps.storage, &msg)?;
+            Ok(cosmwasm_std::Response::new())
+        },
+        |deps, _env, _msg: OracleQueryMsg| -> cosmwasm_std::StdResult<Binary> {
+            to_json_binary(&MOCK_ORACLE_PRICE.load(deps.storage)?)
+        },
+    );
+    Box::new(contract)
+}
+
+/// Compliance registry that flags the addresses it was last given.
+const MOCK_REGISTRY_FLAGGED: Item<Vec<String>> = Item::new("flagged");
+
+fn mock_registry_contract() -> Box<dyn Contract<Empty>> {
+    let contract = ContractWrapper::new(
+        |deps, _env, _info, msg: Vec<String>| -> cosmwasm_std::StdResult<cosmwasm_std::Response> {
+            MOCK_REGISTRY_FLAGGED.save(deps.storage, &msg)?;
+            Ok(cosmwasm_std::Response::new())
+        },
+        |deps, _env, _info, msg: Vec<String>| -> cosmwasm_std::StdResult<cosmwasm_std::Response> {
+            MOCK_REGISTRY_FLAGGED.save(deps.storage, &msg)?;
+            Ok(cosmwasm_std::Response::new())
+        },
+        |deps, _env, msg: ComplianceQueryMsg| -> cosmwasm_std::StdResult<Binary> {
+            let ComplianceQueryMsg::IsFlagged { address } = msg;
+            to_json_binary(&FlaggedResponse {
+                flagged: MOCK_REGISTRY_FLAGGED.load(deps.storage)?.contains(&address),
+            })
+        },
+    );
     Box::new(contract)
 }
 
+/// Hook that records every message, or fails when instantiated with `true`.
+const MOCK_HOOK_FAILS: Item<bool> = Item::new("fails");
+const MOCK_HOOK_CALLS: Item<Vec<VaultHookMsg>> = Item::new("calls");
+
+fn mock_hook_contract() -> Box<dyn Contract<Empty>> {
+    let contract = ContractWrapper::new(
+        |deps,
+         _env,
+         _info,
+         msg: HookExecuteMsg|
+         -> cosmwasm_std::StdResult<cosmwasm_std::Response> {
+            if MOCK_HOOK_FAILS.load(deps.storage)? {
+                return Err(cosmwasm_std::StdError::generic_err("hook failed"));
+            }
+            let HookExecuteMsg::VaultHook(msg) = msg;
+            let mut calls = MOCK_HOOK_CALLS.may_load(deps.storage)?.unwrap_or_default();
+            calls.push(msg);
+            MOCK_HOOK_CALLS.save(deps.storage, &calls)?;
+            Ok(cosmwasm_std::Response::new())
+        },
+        |deps, _env, _info, fails: bool| -> cosmwasm_std::StdResult<cosmwasm_std::Response> {
+            MOCK_HOOK_FAILS.save(deps.storage, &fails)?;
+            Ok(cosmwasm_std::Response::new())
+        },
+        |deps, _env, _msg: Empty| -> cosmwasm_std::StdResult<Binary> {
+            to_json_binary(&MOCK_HOOK_CALLS.may_load(deps.storage)?.unwrap_or_default())
+        },
+    );
+    Box::new(contract)
+}
+
+fn sign_voucher(signing_key: &SigningKey, voucher: &Voucher) -> Binary {
+    let hash = Sha256::digest(to_json_vec(voucher).unwrap());
+    let signature: Signature = signing_key.sign_prehash(&hash).unwrap();
+    Binary::from(signature.to_bytes().as_slice())
+}
+
 #[test]
 fn multitest_vault_contract() {
     // Create addresses
@@ -54,6 +228,7 @@ fn multitest_vault_contract() {
         owner.clone(),
         contract_addr.clone(),
         &ExecuteMsg::AddDepositAddress {
+            vault_id: None,
             address: "sender".to_string(),
         },
         &[],
@@ -63,7 +238,10 @@ fn multitest_vault_contract() {
     // Query - Get deposit addresses
     let resp: DepositAddressesResponse = app
         .wrap()
-        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetDepositAddresses {})
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetDepositAddresses { vault_id: None },
+        )
         .unwrap();
 
     assert_eq!(
@@ -77,7 +255,7 @@ fn multitest_vault_contract() {
     app.execute_contract(
         sender.clone(),
         contract_addr.clone(),
-        &ExecuteMsg::Deposit {},
+        &ExecuteMsg::Deposit { vault_id: None },
         &coins(10, "atom"),
     )
     .unwrap();
@@ -85,7 +263,10 @@ fn multitest_vault_contract() {
     // Query - Get token balance
     let resp: StateResponse = app
         .wrap()
-        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetState {})
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: None },
+        )
         .unwrap();
 
     assert_eq!(resp.total_amount, Uint128::from(10u128),);
@@ -95,6 +276,7 @@ fn multitest_vault_contract() {
         owner.clone(),
         contract_addr.clone(),
         &ExecuteMsg::RemoveDepositAddress {
+            vault_id: None,
             address: "sender".to_string(),
         },
         &[],
@@ -104,7 +286,10 @@ fn multitest_vault_contract() {
     // Query - Get deposit addresses
     let resp: DepositAddressesResponse = app
         .wrap()
-        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetDepositAddresses {})
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetDepositAddresses { vault_id: None },
+        )
         .unwrap();
 
     assert_eq!(
@@ -118,7 +303,7 @@ fn multitest_vault_contract() {
     app.execute_contract(
         owner.clone(),
         contract_addr.clone(),
-        &ExecuteMsg::Withdraw {},
+        &ExecuteMsg::Withdraw { vault_id: None },
         &[],
     )
     .unwrap();
@@ -126,7 +311,10 @@ fn multitest_vault_contract() {
     // Query - Get token balance
     let resp: StateResponse = app
         .wrap()
-        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetState {})
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: None },
+        )
         .unwrap();
 
     assert_eq!(resp.total_amount, Uint128::zero(),);
@@ -136,6 +324,7 @@ fn multitest_vault_contract() {
         owner.clone(),
         contract_addr.clone(),
         &ExecuteMsg::AddAllowance {
+            vault_id: None,
             spender: "sender".to_string(),
             amount: Uint128::from(5u128),
         },
@@ -149,6 +338,7 @@ fn multitest_vault_contract() {
         .query_wasm_smart(
             contract_addr.clone(),
             &QueryMsg::GetAllowance {
+                vault_id: None,
                 spender: "sender".to_string(),
             },
         )
@@ -162,6 +352,7 @@ fn multitest_vault_contract() {
         owner.clone(),
         contract_addr.clone(),
         &ExecuteMsg::AddAllowance {
+            vault_id: None,
             spender: "receiver".to_string(),
             amount: Uint128::from(10u128),
         },
@@ -172,7 +363,10 @@ fn multitest_vault_contract() {
     // Query - Get Allowance List
     let resp: AllowancesResponse = app
         .wrap()
-        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetAllowances {})
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetAllowances { vault_id: None },
+        )
         .unwrap();
 
     assert_eq!(
@@ -189,6 +383,7 @@ fn multitest_vault_contract() {
         owner.clone(),
         contract_addr.clone(),
         &ExecuteMsg::RemoveAllowance {
+            vault_id: None,
             spender: "receiver".to_string(),
         },
         &[],
@@ -198,7 +393,10 @@ fn multitest_vault_contract() {
     // Query - Get Allowance List
     let resp: AllowancesResponse = app
         .wrap()
-        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetAllowances {})
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetAllowances { vault_id: None },
+        )
         .unwrap();
 
     assert_eq!(resp.spenders, vec!["sender".to_string()],);
@@ -209,6 +407,7 @@ fn multitest_vault_contract() {
         owner.clone(),
         contract_addr.clone(),
         &ExecuteMsg::UpdateAllowance {
+            vault_id: None,
             spender: "sender".to_string(),
             amount: Uint128::from(15u128),
         },
@@ -219,7 +418,10 @@ fn multitest_vault_contract() {
     // Query - Get Allowances
     let resp: AllowancesResponse = app
         .wrap()
-        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetAllowances {})
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetAllowances { vault_id: None },
+        )
         .unwrap();
 
     assert_eq!(resp.spenders, vec!["sender".to_string()],);
@@ -230,6 +432,7 @@ fn multitest_vault_contract() {
         owner.clone(),
         contract_addr.clone(),
         &ExecuteMsg::RemoveAllowance {
+            vault_id: None,
             spender: "sender".to_string(),
         },
         &[],
@@ -239,7 +442,10 @@ fn multitest_vault_contract() {
     // Query - Get Allowances
     let resp: AllowancesResponse = app
         .wrap()
-        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetAllowances {})
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetAllowances { vault_id: None },
+        )
         .unwrap();
 
     assert_eq!(resp.spenders, Vec::<String>::new(),);
@@ -254,6 +460,7 @@ fn multitest_vault_contract() {
         owner.clone(),
         contract_addr.clone(),
         &ExecuteMsg::AddAllowanceList {
+            vault_id: None,
             spenders: allowance_spenders.clone(),
             amounts: allowance_amounts.clone(),
         },
@@ -264,7 +471,10 @@ fn multitest_vault_contract() {
     // Query - Get Allowance List
     let resp: AllowancesResponse = app
         .wrap()
-        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetAllowances {})
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetAllowances { vault_id: None },
+        )
         .unwrap();
 
     allowance_spenders.reverse();
@@ -277,7 +487,7 @@ fn multitest_vault_contract() {
     app.execute_contract(
         owner.clone(),
         contract_addr.clone(),
-        &ExecuteMsg::Deposit {},
+        &ExecuteMsg::Deposit { vault_id: None },
         &coins(10, "atom"),
     )
     .unwrap();
@@ -287,6 +497,7 @@ fn multitest_vault_contract() {
         owner.clone(),
         contract_addr.clone(),
         &ExecuteMsg::AddAllowance {
+            vault_id: None,
             spender: "ellie".to_string(),
             amount: Uint128::from(10u128),
         },
@@ -298,7 +509,7 @@ fn multitest_vault_contract() {
     app.execute_contract(
         Addr::unchecked("ellie"),
         contract_addr.clone(),
-        &ExecuteMsg::RetrieveAllowance {},
+        &ExecuteMsg::RetrieveAllowance { vault_id: None },
         &[],
     )
     .unwrap();
@@ -306,7 +517,10 @@ fn multitest_vault_contract() {
     // Query - Get token balance
     let resp: StateResponse = app
         .wrap()
-        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetState {})
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: None },
+        )
         .unwrap();
 
     assert_eq!(resp.total_amount, Uint128::zero(),);
@@ -316,6 +530,7 @@ fn multitest_vault_contract() {
         owner.clone(),
         contract_addr.clone(),
         &ExecuteMsg::UpdateName {
+            vault_id: None,
             name: "Vault Y".to_string(),
         },
         &[],
@@ -325,7 +540,10 @@ fn multitest_vault_contract() {
     // Query - Get State
     let resp: StateResponse = app
         .wrap()
-        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetState {})
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: None },
+        )
         .unwrap();
 
     assert_eq!(resp.name, "Vault Y".to_string(),);
@@ -335,6 +553,7 @@ fn multitest_vault_contract() {
         owner.clone(),
         contract_addr.clone(),
         &ExecuteMsg::UpdateOwner {
+            vault_id: None,
             owner: "new_owner".to_string(),
         },
         &[],
@@ -344,7 +563,10 @@ fn multitest_vault_contract() {
     // Query - Get State
     let resp: StateResponse = app
         .wrap()
-        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetState {})
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: None },
+        )
         .unwrap();
 
     assert_eq!(resp.owner, "new_owner".to_string(),);
@@ -352,7 +574,10 @@ fn multitest_vault_contract() {
     // Query Deposit Addresses
     let resp: DepositAddressesResponse = app
         .wrap()
-        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetDepositAddresses {})
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetDepositAddresses { vault_id: None },
+        )
         .unwrap();
 
     assert_eq!(
@@ -368,6 +593,7 @@ fn multitest_vault_contract() {
         .query_wasm_smart(
             contract_addr.clone(),
             &QueryMsg::CanDeposit {
+                vault_id: None,
                 address: "new_owner".to_string(),
             },
         )
@@ -375,3 +601,5876 @@ fn multitest_vault_contract() {
 
     assert_eq!(resp, CanDepositResponse { can_deposit: true },);
 }
+
+#[test]
+fn multitest_vendor_invoices() {
+    let owner = Addr::unchecked("owner");
+    let vendor = Addr::unchecked("vendor");
+
+    let mut app = App::new(|router, _api, storage| {
+        router
+            .bank
+            .init_balance(storage, &owner, coins(100, "atom"))
+            .unwrap();
+    });
+    let contract_id = app.store_code(vault_contract());
+    let contract_addr = app
+        .instantiate_contract(
+            contract_id,
+            owner.clone(),
+            &InstantiateMsg {
+                name: "Vault X".to_string(),
+                expected_denom: "atom".to_string(),
+            },
+            &[],
+            "Vault contract",
+            None,
+        )
+        .unwrap();
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit { vault_id: None },
+        &coins(50, "atom"),
+    )
+    .unwrap();
+
+    // Invoices can only be created for registered vendors
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::CreateInvoice {
+                vendor: vendor.to_string(),
+                amount: Uint128::from(20u128),
+                denom: None,
+                due_date: app.block_info().time.plus_seconds(100),
+                reference_id: "INV-1".to_string(),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::VendorNotFound {
+            address: vendor.to_string()
+        }
+    );
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddVendor {
+            address: vendor.to_string(),
+            name: "Office Supplies".to_string(),
+            default_denom: "atom".to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+
+    let resp: VendorsResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetVendors {})
+        .unwrap();
+    assert_eq!(resp.vendors.len(), 1);
+    assert_eq!(resp.vendors[0].name, "Office Supplies".to_string());
+
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::CreateInvoice {
+                vendor: vendor.to_string(),
+                amount: Uint128::zero(),
+                denom: None,
+                due_date: app.block_info().time.plus_seconds(100),
+                reference_id: "INV-0".to_string(),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::ZeroInvoiceAmount {}
+    );
+
+    // One invoice due soon, one due later
+    let now = app.block_info().time;
+    for (amount, due_in, reference_id) in [(20u128, 100, "INV-1"), (15u128, 1000, "INV-2")] {
+        app.execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::CreateInvoice {
+                vendor: vendor.to_string(),
+                amount: Uint128::from(amount),
+                denom: None,
+                due_date: now.plus_seconds(due_in),
+                reference_id: reference_id.to_string(),
+            },
+            &[],
+        )
+        .unwrap();
+    }
+
+    // Nothing is due yet
+    app.execute_contract(
+        vendor.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::PayDueInvoices { limit: None },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&vendor, "atom").unwrap().amount,
+        Uint128::zero()
+    );
+
+    app.update_block(|block| block.time = block.time.plus_seconds(200));
+
+    app.execute_contract(
+        vendor.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::PayDueInvoices { limit: None },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&vendor, "atom").unwrap().amount,
+        Uint128::from(20u128)
+    );
+
+    let resp: InvoiceResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetInvoice { id: 1 })
+        .unwrap();
+    let receipt = resp.receipt.unwrap();
+    assert_eq!(receipt.amount, Uint128::from(20u128));
+    assert_eq!(receipt.paid_at, app.block_info().time);
+
+    let resp: InvoicesResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetInvoices {})
+        .unwrap();
+    assert_eq!(resp.invoices.len(), 2);
+    assert_eq!(resp.invoices[1].receipt, None);
+
+    let resp: StateResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: None },
+        )
+        .unwrap();
+    assert_eq!(resp.total_amount, Uint128::from(30u128));
+
+    // Paid invoices cannot be cancelled
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::CancelInvoice { id: 1 },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::InvoiceAlreadyPaid { id: 1 }
+    );
+
+    // A cancelled invoice is never paid
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::CancelInvoice { id: 2 },
+        &[],
+    )
+    .unwrap();
+    app.update_block(|block| block.time = block.time.plus_seconds(1000));
+    app.execute_contract(
+        vendor.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::PayDueInvoices { limit: None },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&vendor, "atom").unwrap().amount,
+        Uint128::from(20u128)
+    );
+
+    // An invoice the free funds cannot cover does not hold back the ones
+    // due after it, and does not count against the limit
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddAllowance {
+            vault_id: None,
+            spender: "employee".to_string(),
+            amount: Uint128::from(20u128),
+        },
+        &[],
+    )
+    .unwrap();
+    let now = app.block_info().time;
+    for (amount, due_in, reference_id) in [(15u128, 100, "INV-3"), (5u128, 200, "INV-4")] {
+        app.execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::CreateInvoice {
+                vendor: vendor.to_string(),
+                amount: Uint128::from(amount),
+                denom: None,
+                due_date: now.plus_seconds(due_in),
+                reference_id: reference_id.to_string(),
+            },
+            &[],
+        )
+        .unwrap();
+    }
+    app.update_block(|block| block.time = block.time.plus_seconds(300));
+    app.execute_contract(
+        vendor.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::PayDueInvoices { limit: Some(1) },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&vendor, "atom").unwrap().amount,
+        Uint128::from(25u128)
+    );
+    let resp: InvoiceResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr, &QueryMsg::GetInvoice { id: 3 })
+        .unwrap();
+    assert_eq!(resp.receipt, None);
+}
+
+#[test]
+fn multitest_milestone_grants() {
+    let owner = Addr::unchecked("owner");
+    let grantee = Addr::unchecked("grantee");
+    let arbiter = Addr::unchecked("arbiter");
+
+    let mut app = App::new(|router, _api, storage| {
+        router
+            .bank
+            .init_balance(storage, &owner, coins(100, "atom"))
+            .unwrap();
+    });
+    let contract_id = app.store_code(vault_contract());
+    let contract_addr = app
+        .instantiate_contract(
+            contract_id,
+            owner.clone(),
+            &InstantiateMsg {
+                name: "Vault X".to_string(),
+                expected_denom: "atom".to_string(),
+            },
+            &[],
+            "Vault contract",
+            None,
+        )
+        .unwrap();
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit { vault_id: None },
+        &coins(100, "atom"),
+    )
+    .unwrap();
+
+    // Create a grant with two milestones
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::CreateGrant {
+            grantee: grantee.to_string(),
+            arbiter: arbiter.to_string(),
+            milestones: vec![
+                MilestoneMsg {
+                    amount: Uint128::from(30u128),
+                    description: "Prototype".to_string(),
+                },
+                MilestoneMsg {
+                    amount: Uint128::from(20u128),
+                    description: "Launch".to_string(),
+                },
+            ],
+        },
+        &[],
+    )
+    .unwrap();
+
+    // The grant amount leaves the free pool
+    let resp: StateResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: None },
+        )
+        .unwrap();
+    assert_eq!(resp.total_amount, Uint128::from(50u128));
+
+    // Funds promised as allowance cannot be escrowed
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddAllowance {
+            vault_id: None,
+            spender: "employee".to_string(),
+            amount: Uint128::from(40u128),
+        },
+        &[],
+    )
+    .unwrap();
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::CreateGrant {
+                grantee: grantee.to_string(),
+                arbiter: arbiter.to_string(),
+                milestones: vec![MilestoneMsg {
+                    amount: Uint128::from(20u128),
+                    description: "Audit".to_string(),
+                }],
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::NotEnoughFunds { total_amount: 10 }
+    );
+
+    // Only the arbiter can complete milestones
+    let err = app
+        .execute_contract(
+            grantee.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::CompleteMilestone {
+                grant_id: 1,
+                milestone: 0,
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::NotArbiter {
+            arbiter: arbiter.to_string()
+        }
+    );
+
+    // Milestones are completed in order
+    let err = app
+        .execute_contract(
+            arbiter.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::CompleteMilestone {
+                grant_id: 1,
+                milestone: 1,
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::MilestoneOutOfOrder { expected: 0 }
+    );
+
+    app.execute_contract(
+        arbiter.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::CompleteMilestone {
+            grant_id: 1,
+            milestone: 0,
+        },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&grantee, "atom").unwrap().amount,
+        Uint128::from(30u128)
+    );
+
+    // Cancelling returns the unreleased tranche to the pool
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::CancelGrant { grant_id: 1 },
+        &[],
+    )
+    .unwrap();
+
+    let resp: GrantResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetGrant { id: 1 })
+        .unwrap();
+    assert!(resp.cancelled);
+    assert_eq!(resp.released_amount, Uint128::from(30u128));
+    assert_eq!(resp.remaining_amount, Uint128::zero());
+
+    let resp: StateResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: None },
+        )
+        .unwrap();
+    assert_eq!(resp.total_amount, Uint128::from(70u128));
+}
+
+#[test]
+fn multitest_vouchers() {
+    let owner = Addr::unchecked("owner");
+    let employee = Addr::unchecked("employee");
+    let signing_key = SigningKey::from_bytes(&[7u8; 32].into()).unwrap();
+
+    let mut app = App::new(|router, _api, storage| {
+        router
+            .bank
+            .init_balance(storage, &owner, coins(100, "atom"))
+            .unwrap();
+    });
+    let contract_id = app.store_code(vault_contract());
+    let contract_addr = app
+        .instantiate_contract(
+            contract_id,
+            owner.clone(),
+            &InstantiateMsg {
+                name: "Vault X".to_string(),
+                expected_denom: "atom".to_string(),
+            },
+            &[],
+            "Vault contract",
+            None,
+        )
+        .unwrap();
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit { vault_id: None },
+        &coins(100, "atom"),
+    )
+    .unwrap();
+
+    // Register the owner key that signs vouchers
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::SetVoucherPubkey {
+            pubkey: Binary::from(
+                signing_key
+                    .verifying_key()
+                    .to_encoded_point(true)
+                    .as_bytes(),
+            ),
+        },
+        &[],
+    )
+    .unwrap();
+
+    let voucher = Voucher {
+        recipient: employee.to_string(),
+        amount: Uint128::from(25u128),
+        denom: "atom".to_string(),
+        nonce: 1,
+        expires: app.block_info().time.plus_seconds(3600),
+        vault: contract_addr.to_string(),
+    };
+    let signature = sign_voucher(&signing_key, &voucher);
+
+    // A voucher signed by another key is rejected
+    let other_key = SigningKey::from_bytes(&[9u8; 32].into()).unwrap();
+    let err = app
+        .execute_contract(
+            employee.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RedeemVoucher {
+                voucher: voucher.clone(),
+                signature: sign_voucher(&other_key, &voucher),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::InvalidVoucherSignature {}
+    );
+
+    // Only the recipient can redeem the voucher
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RedeemVoucher {
+                voucher: voucher.clone(),
+                signature: signature.clone(),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::VoucherWrongRecipient {
+            recipient: employee.to_string()
+        }
+    );
+
+    app.execute_contract(
+        employee.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RedeemVoucher {
+            voucher: voucher.clone(),
+            signature: signature.clone(),
+        },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&employee, "atom").unwrap().amount,
+        Uint128::from(25u128)
+    );
+
+    let resp: NonceUsedResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::IsNonceUsed { nonce: 1 })
+        .unwrap();
+    assert!(resp.used);
+
+    // Replaying the voucher fails
+    let err = app
+        .execute_contract(
+            employee.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RedeemVoucher {
+                voucher: voucher.clone(),
+                signature,
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::VoucherNonceUsed { nonce: 1 }
+    );
+
+    // Expired vouchers cannot be redeemed
+    let expired = Voucher {
+        nonce: 2,
+        expires: Timestamp::from_seconds(0),
+        ..voucher
+    };
+    let err = app
+        .execute_contract(
+            employee.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RedeemVoucher {
+                voucher: expired.clone(),
+                signature: sign_voucher(&signing_key, &expired),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::VoucherExpired { nonce: 2 }
+    );
+
+    let resp: StateResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: None },
+        )
+        .unwrap();
+    assert_eq!(resp.total_amount, Uint128::from(75u128));
+
+    // Vouchers only spend what no allowance reserves
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddAllowance {
+            vault_id: None,
+            spender: "spender".to_string(),
+            amount: Uint128::from(70u128),
+        },
+        &[],
+    )
+    .unwrap();
+    let reserved = Voucher {
+        nonce: 3,
+        amount: Uint128::from(10u128),
+        expires: app.block_info().time.plus_seconds(3600),
+        ..expired
+    };
+    let err = app
+        .execute_contract(
+            employee.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RedeemVoucher {
+                voucher: reserved.clone(),
+                signature: sign_voucher(&signing_key, &reserved),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::NotEnoughFunds { total_amount: 5 }
+    );
+
+    // A new owner must register a key again
+    let small = Voucher {
+        nonce: 4,
+        amount: Uint128::from(5u128),
+        ..reserved
+    };
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::UpdateOwner {
+            vault_id: None,
+            owner: "new_owner".to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+    let err = app
+        .execute_contract(
+            employee.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RedeemVoucher {
+                voucher: small.clone(),
+                signature: sign_voucher(&signing_key, &small),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::NoVoucherPubkey {}
+    );
+}
+
+#[test]
+fn merkle_leaf_encoding() {
+    // The address length keeps "bob1" with 0 apart from "bob" with 10
+    assert_ne!(
+        leaf_hash("bob1", Uint128::zero()),
+        leaf_hash("bob", Uint128::from(10u128))
+    );
+
+    let mut preimage = vec![0x00, 0, 0, 0, 5];
+    preimage.extend_from_slice(b"alice");
+    preimage.extend_from_slice(&10u128.to_be_bytes());
+    let leaf: [u8; 32] = Sha256::digest(&preimage).into();
+    assert_eq!(leaf_hash("alice", Uint128::from(10u128)), leaf);
+
+    // Parents are hashed with their own prefix
+    let tree = MerkleTree::new(&[
+        ("alice", Uint128::from(10u128)),
+        ("bob", Uint128::from(20u128)),
+    ]);
+    let bob = leaf_hash("bob", Uint128::from(20u128));
+    let (first, second) = if leaf <= bob {
+        (leaf, bob)
+    } else {
+        (bob, leaf)
+    };
+    let mut preimage = vec![0x01];
+    preimage.extend_from_slice(&first);
+    preimage.extend_from_slice(&second);
+    let parent: [u8; 32] = Sha256::digest(&preimage).into();
+    assert_eq!(tree.root(), parent);
+    assert!(verify_proof(&tree.root(), leaf, &tree.proof(0)));
+    assert!(!verify_proof(&tree.root(), leaf, &[]));
+}
+
+#[test]
+fn multitest_merkle_distribution() {
+    let owner = Addr::unchecked("owner");
+    let alice = Addr::unchecked("alice");
+
+    let mut app = App::new(|router, _api, storage| {
+        router
+            .bank
+            .init_balance(storage, &owner, coins(100, "atom"))
+            .unwrap();
+    });
+    let contract_id = app.store_code(vault_contract());
+    let contract_addr = app
+        .instantiate_contract(
+            contract_id,
+            owner.clone(),
+            &InstantiateMsg {
+                name: "Vault X".to_string(),
+                expected_denom: "atom".to_string(),
+            },
+            &[],
+            "Vault contract",
+            None,
+        )
+        .unwrap();
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit { vault_id: None },
+        &coins(100, "atom"),
+    )
+    .unwrap();
+
+    let tree = MerkleTree::new(&[
+        ("alice", Uint128::from(10u128)),
+        ("bob", Uint128::from(20u128)),
+        ("carol", Uint128::from(30u128)),
+    ]);
+    let hex_proof =
+        |index: usize| -> Vec<String> { tree.proof(index).iter().map(hex::encode).collect() };
+
+    // A round cannot take funds promised as allowance
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddAllowance {
+            vault_id: None,
+            spender: "employee".to_string(),
+            amount: Uint128::from(50u128),
+        },
+        &[],
+    )
+    .unwrap();
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RegisterDistribution {
+                merkle_root: hex::encode(tree.root()),
+                total_amount: Uint128::from(60u128),
+                expiration: None,
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::NotEnoughFunds { total_amount: 50 }
+    );
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RemoveAllowance {
+            vault_id: None,
+            spender: "employee".to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RegisterDistribution {
+            merkle_root: hex::encode(tree.root()),
+            total_amount: Uint128::from(60u128),
+            expiration: Some(app.block_info().time.plus_seconds(100)),
+        },
+        &[],
+    )
+    .unwrap();
+
+    app.execute_contract(
+        alice.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::ClaimDistribution {
+            round: 1,
+            amount: Uint128::from(10u128),
+            proof: hex_proof(0),
+        },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&alice, "atom").unwrap().amount,
+        Uint128::from(10u128)
+    );
+
+    let resp: DistributionClaimedResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::IsDistributionClaimed {
+                round: 1,
+                address: alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert!(resp.claimed);
+
+    // A leaf can only be claimed once
+    let err = app
+        .execute_contract(
+            alice.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::ClaimDistribution {
+                round: 1,
+                amount: Uint128::from(10u128),
+                proof: hex_proof(0),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::DistributionAlreadyClaimed {
+            address: alice.to_string()
+        }
+    );
+
+    // Claiming a different amount than the leaf fails
+    let err = app
+        .execute_contract(
+            Addr::unchecked("bob"),
+            contract_addr.clone(),
+            &ExecuteMsg::ClaimDistribution {
+                round: 1,
+                amount: Uint128::from(25u128),
+                proof: hex_proof(1),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::InvalidMerkleProof {}
+    );
+
+    // The odd leaf is carried up the tree and can still be claimed
+    app.execute_contract(
+        Addr::unchecked("carol"),
+        contract_addr.clone(),
+        &ExecuteMsg::ClaimDistribution {
+            round: 1,
+            amount: Uint128::from(30u128),
+            proof: hex_proof(2),
+        },
+        &[],
+    )
+    .unwrap();
+
+    // Unclaimed funds return to the pool after expiry
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::ClawbackDistribution { round: 1 },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::DistributionNotExpired { round: 1 }
+    );
+
+    app.update_block(|block| block.time = block.time.plus_seconds(100));
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::ClawbackDistribution { round: 1 },
+        &[],
+    )
+    .unwrap();
+
+    let resp: DistributionResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetDistribution { round: 1 },
+        )
+        .unwrap();
+    assert_eq!(resp.claimed_amount, Uint128::from(40u128));
+    assert!(resp.clawed_back);
+
+    let resp: StateResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: None },
+        )
+        .unwrap();
+    assert_eq!(resp.total_amount, Uint128::from(60u128));
+}
+
+#[test]
+fn multitest_vault_shares() {
+    let owner = Addr::unchecked("owner");
+    let alice = Addr::unchecked("alice");
+    let bob = Addr::unchecked("bob");
+
+    let mut app = App::new(|router, _api, storage| {
+        router
+            .bank
+            .init_balance(storage, &alice, coins(100, "atom"))
+            .unwrap();
+    });
+    let contract_id = app.store_code(vault_contract());
+    let contract_addr = app
+        .instantiate_contract(
+            contract_id,
+            owner.clone(),
+            &InstantiateMsg {
+                name: "Vault X".to_string(),
+                expected_denom: "atom".to_string(),
+            },
+            &[],
+            "Vault contract",
+            None,
+        )
+        .unwrap();
+
+    // The vault is the minter of its share token
+    let cw20_id = app.store_code(cw20_contract());
+    let token_addr = app
+        .instantiate_contract(
+            cw20_id,
+            owner.clone(),
+            &cw20_base::msg::InstantiateMsg {
+                name: "Vault X Shares".to_string(),
+                symbol: "VXS".to_string(),
+                decimals: 6,
+                initial_balances: vec![],
+                mint: Some(MinterResponse {
+                    minter: contract_addr.to_string(),
+                    cap: None,
+                }),
+                marketing: None,
+            },
+            &[],
+            "Vault shares",
+            None,
+        )
+        .unwrap();
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::EnableShareMode {
+            token: token_addr.to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddDepositAddress {
+            vault_id: None,
+            address: alice.to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+
+    // Allowances reserve part of the pool, so shares redeem less
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddAllowance {
+            vault_id: None,
+            spender: bob.to_string(),
+            amount: Uint128::from(40u128),
+        },
+        &[],
+    )
+    .unwrap();
+
+    // The first deposit mints shares one to one
+    app.execute_contract(
+        alice.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit { vault_id: None },
+        &coins(100, "atom"),
+    )
+    .unwrap();
+
+    let resp: BalanceResponse = app
+        .wrap()
+        .query_wasm_smart(
+            token_addr.clone(),
+            &cw20::Cw20QueryMsg::Balance {
+                address: alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(resp.balance, Uint128::from(100u128));
+
+    // Once shares are outstanding the owner can neither raise allowances
+    // nor withdraw the pool
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::UpdateAllowance {
+                vault_id: None,
+                spender: bob.to_string(),
+                amount: Uint128::from(41u128),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::SharesOutstanding {}
+    );
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Withdraw { vault_id: None },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::SharesOutstanding {}
+    );
+
+    // Nor can the pool leave through any other payout the owner controls
+    let expires = app.block_info().time.plus_seconds(100);
+    let payouts = vec![
+        ExecuteMsg::CreateGrant {
+            grantee: owner.to_string(),
+            arbiter: owner.to_string(),
+            milestones: vec![MilestoneMsg {
+                amount: Uint128::from(10u128),
+                description: "Anything".to_string(),
+            }],
+        },
+        ExecuteMsg::RegisterDistribution {
+            merkle_root: hex::encode(MerkleTree::new(&[("owner", Uint128::from(10u128))]).root()),
+            total_amount: Uint128::from(10u128),
+            expiration: None,
+        },
+        ExecuteMsg::RedeemVoucher {
+            voucher: Voucher {
+                recipient: owner.to_string(),
+                amount: Uint128::from(10u128),
+                denom: "atom".to_string(),
+                nonce: 1,
+                expires,
+                vault: contract_addr.to_string(),
+            },
+            signature: Binary::default(),
+        },
+        ExecuteMsg::PayDueInvoices { limit: None },
+        ExecuteMsg::SendFunds {
+            channel_id: "channel-1".to_string(),
+            amount: Uint128::from(10u128),
+        },
+        ExecuteMsg::CloseVault {},
+    ];
+    for msg in payouts {
+        let err = app
+            .execute_contract(owner.clone(), contract_addr.clone(), &msg, &[])
+            .unwrap_err();
+        assert_eq!(
+            err.downcast::<ContractError>().unwrap(),
+            ContractError::SharesOutstanding {}
+        );
+    }
+    let err = app
+        .wasm_sudo(
+            contract_addr.clone(),
+            &SudoMsg::RecoverFunds {
+                recipient: owner.to_string(),
+            },
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::SharesOutstanding {}
+    );
+
+    let resp: PreviewRedeemResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::PreviewRedeem {
+                shares: Uint128::from(50u128),
+            },
+        )
+        .unwrap();
+    assert_eq!(resp.amount, Uint128::from(30u128));
+
+    // A claim uses the allowance up and leaves the share price as it was
+    app.execute_contract(
+        bob.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RetrieveAllowance { vault_id: None },
+        &[],
+    )
+    .unwrap();
+    let err = app
+        .execute_contract(
+            bob.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RetrieveAllowance { vault_id: None },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::NoAllowance {
+            address: bob.to_string()
+        }
+    );
+    let resp: PreviewRedeemResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::PreviewRedeem {
+                shares: Uint128::from(50u128),
+            },
+        )
+        .unwrap();
+    assert_eq!(resp.amount, Uint128::from(30u128));
+
+    // Only the share token can redeem
+    let err = app
+        .execute_contract(
+            alice.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Receive(cw20::Cw20ReceiveMsg {
+                sender: alice.to_string(),
+                amount: Uint128::from(50u128),
+                msg: to_json_binary(&ReceiveMsg::RedeemShares {}).unwrap(),
+            }),
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::UnauthorizedShareToken {
+            token: token_addr.to_string()
+        }
+    );
+
+    app.execute_contract(
+        alice.clone(),
+        token_addr.clone(),
+        &Cw20ExecuteMsg::Send {
+            contract: contract_addr.to_string(),
+            amount: Uint128::from(50u128),
+            msg: to_json_binary(&ReceiveMsg::RedeemShares {}).unwrap(),
+        },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&alice, "atom").unwrap().amount,
+        Uint128::from(30u128)
+    );
+
+    let resp: cw20::TokenInfoResponse = app
+        .wrap()
+        .query_wasm_smart(token_addr.clone(), &cw20::Cw20QueryMsg::TokenInfo {})
+        .unwrap();
+    assert_eq!(resp.total_supply, Uint128::from(50u128));
+
+    let resp: StateResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: None },
+        )
+        .unwrap();
+    assert_eq!(resp.total_amount, Uint128::from(30u128));
+}
+
+#[test]
+fn multitest_staking() {
+    let owner = Addr::unchecked("owner");
+
+    let mut app = App::new(|router, api, storage| {
+        router
+            .bank
+            .init_balance(storage, &owner, coins(1000, "atom"))
+            .unwrap();
+        router
+            .staking
+            .setup(
+                storage,
+                StakingInfo {
+                    bonded_denom: "atom".to_string(),
+                    unbonding_time: 60,
+                    apr: Decimal::percent(10),
+                },
+            )
+            .unwrap();
+        router
+            .staking
+            .add_validator(
+                api,
+                storage,
+                &mock_env().block,
+                Validator {
+                    address: "validator".to_string(),
+                    commission: Decimal::zero(),
+                    max_commission: Decimal::one(),
+                    max_change_rate: Decimal::one(),
+                },
+            )
+            .unwrap();
+    });
+    let contract_id = app.store_code(vault_contract());
+    let contract_addr = app
+        .instantiate_contract(
+            contract_id,
+            owner.clone(),
+            &InstantiateMsg {
+                name: "Vault X".to_string(),
+                expected_denom: "atom".to_string(),
+            },
+            &[],
+            "Vault contract",
+            None,
+        )
+        .unwrap();
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit { vault_id: None },
+        &coins(1000, "atom"),
+    )
+    .unwrap();
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::SetStakingConfig {
+            max_stake_ratio: Decimal::percent(50),
+            unbonding_period: 60,
+        },
+        &[],
+    )
+    .unwrap();
+
+    // Allowances must remain payable, so only 400 of the 1000 can be staked
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddAllowance {
+            vault_id: None,
+            spender: "bob".to_string(),
+            amount: Uint128::from(600u128),
+        },
+        &[],
+    )
+    .unwrap();
+
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Delegate {
+                validator: "validator".to_string(),
+                amount: Uint128::from(500u128),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::OverStake { max_amount: 400 }
+    );
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Delegate {
+            validator: "validator".to_string(),
+            amount: Uint128::from(400u128),
+        },
+        &[],
+    )
+    .unwrap();
+
+    let resp: StateResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: None },
+        )
+        .unwrap();
+    assert_eq!(resp.total_amount, Uint128::from(600u128));
+
+    // Rewards for a year are added to the pool
+    app.update_block(|block| block.time = block.time.plus_seconds(365 * 24 * 60 * 60));
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::WithdrawRewards {},
+        &[],
+    )
+    .unwrap();
+
+    let resp: StateResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: None },
+        )
+        .unwrap();
+    assert_eq!(resp.total_amount, Uint128::from(640u128));
+    assert_eq!(
+        app.wrap()
+            .query_balance(&contract_addr, "atom")
+            .unwrap()
+            .amount,
+        Uint128::from(640u128)
+    );
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Undelegate {
+            validator: "validator".to_string(),
+            amount: Uint128::from(400u128),
+        },
+        &[],
+    )
+    .unwrap();
+
+    let resp: StakingInfoResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetStakingInfo {})
+        .unwrap();
+    assert_eq!(resp.total_staked, Uint128::zero());
+    assert_eq!(resp.unbondings.len(), 1);
+
+    // Unbonded funds return to the pool once the unbonding period is over
+    // and they have actually arrived
+    app.update_block(|block| block.time = block.time.plus_seconds(60));
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::ClaimUnbonded {},
+        &[],
+    )
+    .unwrap();
+    let resp: StakingInfoResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetStakingInfo {})
+        .unwrap();
+    assert_eq!(resp.unbondings.len(), 1);
+    let resp: StateResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: None },
+        )
+        .unwrap();
+    assert_eq!(resp.total_amount, Uint128::from(640u128));
+
+    // The staking module pays out the unbonding queue on the following block.
+    // A withdrawal before the claim leaves the unbonded tokens in the vault.
+    app.update_block(|block| block.height += 1);
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Withdraw { vault_id: None },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&owner, "atom").unwrap().amount,
+        Uint128::from(640u128)
+    );
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::ClaimUnbonded {},
+        &[],
+    )
+    .unwrap();
+
+    let resp: StakingInfoResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetStakingInfo {})
+        .unwrap();
+    assert!(resp.unbondings.is_empty());
+    let resp: StateResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: None },
+        )
+        .unwrap();
+    assert_eq!(resp.total_amount, Uint128::from(400u128));
+    assert_eq!(
+        app.wrap()
+            .query_balance(&contract_addr, "atom")
+            .unwrap()
+            .amount,
+        Uint128::from(400u128)
+    );
+}
+
+#[test]
+fn mock_ibc_payouts() {
+    let mut deps = mock_dependencies();
+    let env = mock_env();
+
+    instantiate(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("owner", &[]),
+        InstantiateMsg {
+            name: "Vault X".to_string(),
+            expected_denom: "atom".to_string(),
+        },
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("owner", &coins(100, "atom")),
+        ExecuteMsg::Deposit { vault_id: None },
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("owner", &[]),
+        ExecuteMsg::AddAllowance {
+            vault_id: None,
+            spender: "employee".to_string(),
+            amount: Uint128::from(40u128),
+        },
+    )
+    .unwrap();
+
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("employee", &[]),
+        ExecuteMsg::SetPayoutPreference {
+            channel_id: "channel-0".to_string(),
+            remote_address: "osmo1employee".to_string(),
+            timeout_seconds: 600,
+        },
+    )
+    .unwrap();
+
+    // The allowance leaves over IBC instead of a bank send
+    let res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("employee", &[]),
+        ExecuteMsg::RetrieveAllowance { vault_id: None },
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages,
+        vec![SubMsg::reply_on_success(
+            transfer_msg(
+                &env.contract.address,
+                "channel-0",
+                "osmo1employee",
+                &cosmwasm_std::coin(40, "atom"),
+                env.block.time.plus_seconds(600),
+                &callback_memo(&env.contract.address).unwrap(),
+            ),
+            IBC_TRANSFER_REPLY_ID,
+        )]
+    );
+
+    // The transfer module answers with the packet sequence
+    reply(
+        deps.as_mut(),
+        env.clone(),
+        Reply {
+            id: IBC_TRANSFER_REPLY_ID,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: Some(Binary::from(vec![0x08, 0x07])),
+            }),
+        },
+    )
+    .unwrap();
+
+    let resp: PendingTransfersResponse =
+        from_json(query(deps.as_ref(), env.clone(), QueryMsg::GetPendingTransfers {}).unwrap())
+            .unwrap();
+    assert_eq!(resp.transfers.len(), 1);
+    assert_eq!(resp.transfers[0].sequence, 7);
+
+    let resp: StateResponse = from_json(
+        query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::GetState { vault_id: None },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(resp.total_amount, Uint128::from(60u128));
+
+    // A timeout refunds the vault, so the amount goes back to the pool
+    sudo(
+        deps.as_mut(),
+        env.clone(),
+        SudoMsg::IbcLifecycleComplete(IbcLifecycleComplete::IbcTimeout {
+            channel: "channel-0".to_string(),
+            sequence: 7,
+        }),
+    )
+    .unwrap();
+
+    let resp: StateResponse = from_json(
+        query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::GetState { vault_id: None },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(resp.total_amount, Uint128::from(100u128));
+
+    let resp: PendingTransfersResponse =
+        from_json(query(deps.as_ref(), env, QueryMsg::GetPendingTransfers {}).unwrap()).unwrap();
+    assert!(resp.transfers.is_empty());
+}
+
+#[test]
+fn mock_ibc_payout_memo() {
+    let mut deps = mock_dependencies();
+    let env = mock_env();
+
+    instantiate(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("owner", &[]),
+        InstantiateMsg {
+            name: "Vault X".to_string(),
+            expected_denom: "atom".to_string(),
+        },
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("owner", &coins(100, "atom")),
+        ExecuteMsg::Deposit { vault_id: None },
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("owner", &[]),
+        ExecuteMsg::AddAllowance {
+            vault_id: None,
+            spender: "employee".to_string(),
+            amount: Uint128::from(40u128),
+        },
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("employee", &[]),
+        ExecuteMsg::SetPayoutPreference {
+            channel_id: "channel-0".to_string(),
+            remote_address: "osmo1employee".to_string(),
+            timeout_seconds: 600,
+        },
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("owner", &[]),
+        ExecuteMsg::SetWithholding {
+            spender: None,
+            rate: Decimal::percent(25),
+            destination: "authority".to_string(),
+            brackets: vec![],
+        },
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("owner", &[]),
+        ExecuteMsg::CloseVault {},
+    )
+    .unwrap();
+
+    // The transfer carries a memo asking the chain to call the vault back,
+    // the withholding stays in the vault until the ack
+    let res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("employee", &[]),
+        ExecuteMsg::RetrieveAllowance { vault_id: None },
+    )
+    .unwrap();
+    let value = match &res.messages[0].msg {
+        CosmosMsg::Stargate { type_url, value } => {
+            assert_eq!(type_url, MSG_TRANSFER_TYPE_URL);
+            value.to_vec()
+        }
+        msg => panic!("unexpected message {:?}", msg),
+    };
+    let memo = callback_memo(&env.contract.address).unwrap();
+    assert_eq!(
+        memo,
+        format!(r#"{{"ibc_callback":"{}"}}"#, env.contract.address)
+    );
+    assert!(value.ends_with(memo.as_bytes()));
+    assert_eq!(res.messages.len(), 1);
+
+    reply(
+        deps.as_mut(),
+        env.clone(),
+        Reply {
+            id: IBC_TRANSFER_REPLY_ID,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: Some(Binary::from(vec![0x08, 0x03])),
+            }),
+        },
+    )
+    .unwrap();
+
+    // The closed vault consumed the allowance but waits for the transfer
+    assert!(query(
+        deps.as_ref(),
+        env.clone(),
+        QueryMsg::GetAllowance {
+            vault_id: None,
+            spender: "employee".to_string(),
+        },
+    )
+    .is_err());
+    let resp: ClosureResponse =
+        from_json(query(deps.as_ref(), env.clone(), QueryMsg::GetClosure {}).unwrap()).unwrap();
+    assert!(!resp.finalized);
+
+    // The callback reports a failed ack, so the allowance is credited again
+    sudo(
+        deps.as_mut(),
+        env.clone(),
+        SudoMsg::IbcLifecycleComplete(IbcLifecycleComplete::IbcAck {
+            channel: "channel-0".to_string(),
+            sequence: 3,
+            ack: "{\"error\":\"failed\"}".to_string(),
+            success: false,
+        }),
+    )
+    .unwrap();
+    let resp: AllowanceResponse = from_json(
+        query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::GetAllowance {
+                vault_id: None,
+                spender: "employee".to_string(),
+            },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(resp.amount, Uint128::from(40u128));
+    let report = |deps: &cosmwasm_std::OwnedDeps<_, _, _>| -> WithholdingReportResponse {
+        from_json(
+            query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::GetWithholdingReport {
+                    spender: "employee".to_string(),
+                    year: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    };
+    let resp = report(&deps);
+    assert_eq!(resp.claimed, Uint128::zero());
+    assert_eq!(resp.withheld, Uint128::zero());
+
+    // A second attempt succeeds and the last refund settles the vault
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("employee", &[]),
+        ExecuteMsg::RetrieveAllowance { vault_id: None },
+    )
+    .unwrap();
+    reply(
+        deps.as_mut(),
+        env.clone(),
+        Reply {
+            id: IBC_TRANSFER_REPLY_ID,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: Some(Binary::from(vec![0x08, 0x04])),
+            }),
+        },
+    )
+    .unwrap();
+    let res = sudo(
+        deps.as_mut(),
+        env.clone(),
+        SudoMsg::IbcLifecycleComplete(IbcLifecycleComplete::IbcAck {
+            channel: "channel-0".to_string(),
+            sequence: 4,
+            ack: "{\"result\":\"AQ==\"}".to_string(),
+            success: true,
+        }),
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages[0].msg,
+        CosmosMsg::Bank(cosmwasm_std::BankMsg::Send {
+            to_address: "authority".to_string(),
+            amount: coins(10, "atom"),
+        })
+    );
+    let resp = report(&deps);
+    assert_eq!(resp.claimed, Uint128::from(40u128));
+    assert_eq!(resp.withheld, Uint128::from(10u128));
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("owner", &[]),
+        ExecuteMsg::ClaimRefund {},
+    )
+    .unwrap();
+    let resp: ClosureResponse =
+        from_json(query(deps.as_ref(), env, QueryMsg::GetClosure {}).unwrap()).unwrap();
+    assert!(resp.finalized);
+}
+
+#[test]
+fn mock_ibc_vault_protocol() {
+    let mut deps = mock_dependencies();
+    let env = mock_env();
+
+    instantiate(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("owner", &[]),
+        InstantiateMsg {
+            name: "Vault X".to_string(),
+            expected_denom: "atom".to_string(),
+        },
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("owner", &coins(100, "atom")),
+        ExecuteMsg::Deposit { vault_id: None },
+    )
+    .unwrap();
+
+    // Channels can only be opened by trusted vaults
+    let err = ibc_channel_open(
+        deps.as_mut(),
+        env.clone(),
+        mock_ibc_channel_open_try("channel-1", IbcOrder::Unordered, IBC_VERSION),
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        ContractError::UntrustedVault {
+            port_id: "their_port".to_string()
+        }
+    );
+
+    // The port must also come over the trusted connection
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("owner", &[]),
+        ExecuteMsg::AddTrustedVault {
+            port_id: "their_port".to_string(),
+            connection_id: "connection-7".to_string(),
+            remote_address: "remote_vault".to_string(),
+            transfer_channel_id: "channel-9".to_string(),
+        },
+    )
+    .unwrap();
+    let err = ibc_channel_open(
+        deps.as_mut(),
+        env.clone(),
+        mock_ibc_channel_open_try("channel-1", IbcOrder::Unordered, IBC_VERSION),
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        ContractError::UntrustedVault {
+            port_id: "their_port".to_string()
+        }
+    );
+
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("owner", &[]),
+        ExecuteMsg::AddTrustedVault {
+            port_id: "their_port".to_string(),
+            connection_id: "connection-2".to_string(),
+            remote_address: "remote_vault".to_string(),
+            transfer_channel_id: "channel-9".to_string(),
+        },
+    )
+    .unwrap();
+
+    let err = ibc_channel_open(
+        deps.as_mut(),
+        env.clone(),
+        mock_ibc_channel_open_try("channel-1", IbcOrder::Ordered, IBC_VERSION),
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::OrderedChannel {});
+
+    ibc_channel_open(
+        deps.as_mut(),
+        env.clone(),
+        mock_ibc_channel_open_try("channel-1", IbcOrder::Unordered, IBC_VERSION),
+    )
+    .unwrap();
+    ibc_channel_connect(
+        deps.as_mut(),
+        env.clone(),
+        mock_ibc_channel_connect_ack("channel-1", IbcOrder::Unordered, IBC_VERSION),
+    )
+    .unwrap();
+
+    // The remote vault asks for funds
+    let res = ibc_packet_receive(
+        deps.as_mut(),
+        env.clone(),
+        mock_ibc_packet_recv(
+            "channel-1",
+            &VaultPacket::FundRequest {
+                amount: Uint128::from(30u128),
+            },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    let ack: VaultAck = from_json(res.acknowledgement).unwrap();
+    assert_eq!(ack, VaultAck::Result(Binary::default()));
+
+    // Funds promised as allowance cannot be sent away
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("owner", &[]),
+        ExecuteMsg::AddAllowance {
+            vault_id: None,
+            spender: "employee".to_string(),
+            amount: Uint128::from(80u128),
+        },
+    )
+    .unwrap();
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("owner", &[]),
+        ExecuteMsg::SendFunds {
+            channel_id: "channel-1".to_string(),
+            amount: Uint128::from(30u128),
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::NotEnoughFunds { total_amount: 20 });
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("owner", &[]),
+        ExecuteMsg::RemoveAllowance {
+            vault_id: None,
+            spender: "employee".to_string(),
+        },
+    )
+    .unwrap();
+
+    // Sending funds announces them first
+    let packet = VaultPacket::FundTransfer {
+        amount: Uint128::from(30u128),
+    };
+    let res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("owner", &[]),
+        ExecuteMsg::SendFunds {
+            channel_id: "channel-1".to_string(),
+            amount: Uint128::from(30u128),
+        },
+    )
+    .unwrap();
+    assert!(matches!(
+        res.messages[0].msg,
+        CosmosMsg::Ibc(IbcMsg::SendPacket { .. })
+    ));
+
+    // The tokens are transferred once the remote vault acknowledges
+    let res = ibc_packet_ack(
+        deps.as_mut(),
+        env.clone(),
+        mock_ibc_packet_ack(
+            "channel-1",
+            &packet,
+            IbcAcknowledgement::new(to_json_binary(&VaultAck::Result(Binary::default())).unwrap()),
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    let memo = vault_funds_memo(&env.contract.address, "remote_vault", "channel-1234").unwrap();
+    assert_eq!(
+        memo,
+        format!(
+            r#"{{"wasm":{{"contract":"remote_vault","msg":{{"receive_vault_funds":{{"channel_id":"channel-1234"}}}}}},"ibc_callback":"{}"}}"#,
+            env.contract.address
+        )
+    );
+    assert_eq!(
+        res.messages,
+        vec![SubMsg::reply_on_success(
+            transfer_msg(
+                &env.contract.address,
+                "channel-9",
+                "remote_vault",
+                &cosmwasm_std::coin(30, "atom"),
+                env.block.time.plus_seconds(60 * 60),
+                &memo,
+            ),
+            IBC_TRANSFER_REPLY_ID,
+        )]
+    );
+
+    // A failed transfer returns the amount to the pool
+    reply(
+        deps.as_mut(),
+        env.clone(),
+        Reply {
+            id: IBC_TRANSFER_REPLY_ID,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: Some(Binary::from(vec![0x08, 0x01])),
+            }),
+        },
+    )
+    .unwrap();
+    let resp: PendingTransfersResponse =
+        from_json(query(deps.as_ref(), env.clone(), QueryMsg::GetPendingTransfers {}).unwrap())
+            .unwrap();
+    assert_eq!(
+        resp.transfers[0].vault_channel_id,
+        Some("channel-1".to_string())
+    );
+    sudo(
+        deps.as_mut(),
+        env.clone(),
+        SudoMsg::IbcLifecycleComplete(IbcLifecycleComplete::IbcTimeout {
+            channel: "channel-9".to_string(),
+            sequence: 1,
+        }),
+    )
+    .unwrap();
+
+    // A timed out announcement returns the amount to the pool
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("owner", &[]),
+        ExecuteMsg::SendFunds {
+            channel_id: "channel-1".to_string(),
+            amount: Uint128::from(20u128),
+        },
+    )
+    .unwrap();
+    ibc_packet_timeout(
+        deps.as_mut(),
+        env.clone(),
+        mock_ibc_packet_timeout(
+            "channel-1",
+            &VaultPacket::FundTransfer {
+                amount: Uint128::from(20u128),
+            },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+
+    // Incoming announcements and balance reports update the local state
+    for packet in [
+        VaultPacket::FundTransfer {
+            amount: Uint128::from(10u128),
+        },
+        VaultPacket::BalanceReport {
+            total_amount: Uint128::from(500u128),
+        },
+    ] {
+        ibc_packet_receive(
+            deps.as_mut(),
+            env.clone(),
+            mock_ibc_packet_recv("channel-1", &packet).unwrap(),
+        )
+        .unwrap();
+    }
+
+    // The announced amount is only credited when the tokens arrive
+    let resp: StateResponse = from_json(
+        query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::GetState { vault_id: None },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(resp.total_amount, Uint128::from(100u128));
+
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(
+            "ibc_hooks",
+            &coins(
+                10,
+                "ibc/27394FB092D2ECCD56123C74F36E4C1F926001CEADA9CA97EA622B25F41E5EB2",
+            ),
+        ),
+        ExecuteMsg::ReceiveVaultFunds {
+            channel_id: "channel-1".to_string(),
+        },
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        ContractError::InvalidDenom {
+            denom: "atom".to_string()
+        }
+    );
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("ibc_hooks", &coins(11, "atom")),
+        ExecuteMsg::ReceiveVaultFunds {
+            channel_id: "channel-1".to_string(),
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::UnannouncedVaultFunds { incoming: 10 });
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("ibc_hooks", &coins(10, "atom")),
+        ExecuteMsg::ReceiveVaultFunds {
+            channel_id: "channel-1".to_string(),
+        },
+    )
+    .unwrap();
+
+    let resp: StateResponse = from_json(
+        query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::GetState { vault_id: None },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(resp.total_amount, Uint128::from(110u128));
+
+    let resp: VaultChannelsResponse =
+        from_json(query(deps.as_ref(), env.clone(), QueryMsg::GetVaultChannels {}).unwrap())
+            .unwrap();
+    let channel = &resp.channels[0];
+    assert_eq!(channel.requested_by_peer, Uint128::zero());
+    assert_eq!(channel.incoming, Uint128::zero());
+    assert_eq!(channel.sent, Uint128::zero());
+    assert_eq!(channel.received, Uint128::from(10u128));
+    assert_eq!(channel.reported_balance, Some(Uint128::from(500u128)));
+
+    // Packets on unknown channels get an error acknowledgement
+    let res = ibc_packet_receive(
+        deps.as_mut(),
+        env,
+        mock_ibc_packet_recv(
+            "channel-5",
+            &VaultPacket::FundRequest {
+                amount: Uint128::from(1u128),
+            },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    let ack: VaultAck = from_json(res.acknowledgement).unwrap();
+    assert!(matches!(ack, VaultAck::Error(_)));
+}
+
+#[test]
+fn multitest_swap_deposits() {
+    let owner = Addr::unchecked("owner");
+    let sender = Addr::unchecked("sender");
+
+    let mut app = App::new(|router, _api, storage| {
+        router
+            .bank
+            .init_balance(storage, &owner, coins(1000, "atom"))
+            .unwrap();
+        router
+            .bank
+            .init_balance(storage, &sender, coins(100, "usdc"))
+            .unwrap();
+    });
+
+    let contract_id = app.store_code(vault_contract());
+    let amm_id = app.store_code(mock_amm_contract());
+
+    let contract_addr = app
+        .instantiate_contract(
+            contract_id,
+            owner.clone(),
+            &InstantiateMsg {
+                name: "Vault X".to_string(),
+                expected_denom: "atom".to_string(),
+            },
+            &[],
+            "Vault contract",
+            None,
+        )
+        .unwrap();
+
+    // The first pair pays 0.5 atom for 1 usdc, the second only 0.4 atom
+    let mut pairs = vec![];
+    for paid_rate in [Decimal::percent(50), Decimal::percent(40)] {
+        let pair = app
+            .instantiate_contract(
+                amm_id,
+                owner.clone(),
+                &MockAmmInstantiateMsg {
+                    quoted_rate: Decimal::percent(50),
+                    paid_rate,
+                },
+                &coins(500, "atom"),
+                "Pair",
+                None,
+            )
+            .unwrap();
+        pairs.push(pair);
+    }
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddDepositAddress {
+            vault_id: None,
+            address: sender.to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+
+    // Foreign denoms are rejected while no router is set
+    let err = app
+        .execute_contract(
+            sender.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Deposit { vault_id: None },
+            &coins(20, "usdc"),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::InvalidDenom {
+            denom: "atom".to_string()
+        }
+    );
+
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::SetSwapConfig {
+                router: pairs[0].to_string(),
+                auto_swap: true,
+                max_slippage: Decimal::percent(101),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::InvalidSlippage {}
+    );
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::SetSwapConfig {
+            router: pairs[0].to_string(),
+            auto_swap: true,
+            max_slippage: Decimal::percent(10),
+        },
+        &[],
+    )
+    .unwrap();
+    let resp: SwapConfigResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetSwapConfig {})
+        .unwrap();
+    assert_eq!(resp.router, Some(pairs[0].to_string()));
+
+    // A plain deposit of a foreign denom is not swapped
+    let err = app
+        .execute_contract(
+            sender.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Deposit { vault_id: None },
+            &coins(20, "usdc"),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::InvalidDenom {
+            denom: "atom".to_string()
+        }
+    );
+    let err = app
+        .execute_contract(
+            sender.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::SwapDeposit {
+                min_return: Uint128::zero(),
+            },
+            &coins(20, "usdc"),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::ZeroMinReturn {}
+    );
+
+    // The deposit is credited with what the pair returned
+    app.execute_contract(
+        sender.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::SwapDeposit {
+            min_return: Uint128::from(9u128),
+        },
+        &coins(20, "usdc"),
+    )
+    .unwrap();
+    let resp: StateResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: None },
+        )
+        .unwrap();
+    assert_eq!(resp.total_amount, Uint128::from(10u128));
+    assert_eq!(
+        app.wrap()
+            .query_balance(&contract_addr, "atom")
+            .unwrap()
+            .amount,
+        Uint128::from(10u128)
+    );
+    assert_eq!(
+        app.wrap().query_balance(&sender, "usdc").unwrap().amount,
+        Uint128::from(80u128)
+    );
+
+    // A return below the depositor's minimum reverts the deposit
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::SetSwapConfig {
+            router: pairs[1].to_string(),
+            auto_swap: true,
+            max_slippage: Decimal::percent(10),
+        },
+        &[],
+    )
+    .unwrap();
+    let err = app
+        .execute_contract(
+            sender.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::SwapDeposit {
+                min_return: Uint128::from(9u128),
+            },
+            &coins(20, "usdc"),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::SlippageExceeded { min_return: 9 }
+    );
+    assert_eq!(
+        app.wrap().query_balance(&sender, "usdc").unwrap().amount,
+        Uint128::from(80u128)
+    );
+
+    // With auto swap off swaps are rejected
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::SetSwapConfig {
+            router: pairs[0].to_string(),
+            auto_swap: false,
+            max_slippage: Decimal::percent(10),
+        },
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(
+        sender,
+        contract_addr,
+        &ExecuteMsg::SwapDeposit {
+            min_return: Uint128::from(9u128),
+        },
+        &coins(20, "usdc"),
+    )
+    .unwrap_err();
+}
+
+#[test]
+fn multitest_pegged_allowances() {
+    let owner = Addr::unchecked("owner");
+    let spender = Addr::unchecked("spender");
+
+    let mut app = App::new(|router, _api, storage| {
+        router
+            .bank
+            .init_balance(storage, &owner, coins(1000, "atom"))
+            .unwrap();
+    });
+
+    let contract_id = app.store_code(vault_contract());
+    let oracle_id = app.store_code(mock_oracle_contract());
+
+    let contract_addr = app
+        .instantiate_contract(
+            contract_id,
+            owner.clone(),
+            &InstantiateMsg {
+                name: "Vault X".to_string(),
+                expected_denom: "atom".to_string(),
+            },
+            &[],
+            "Vault contract",
+            None,
+        )
+        .unwrap();
+    let oracle = app
+        .instantiate_contract(
+            oracle_id,
+            owner.clone(),
+            &PriceResponse {
+                price: Decimal::percent(200),
+                updated_at: app.block_info().time,
+            },
+            &[],
+            "Oracle",
+            None,
+        )
+        .unwrap();
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit { vault_id: None },
+        &coins(1000, "atom"),
+    )
+    .unwrap();
+
+    // 50 USD a claim
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddPeggedAllowance {
+            spender: spender.to_string(),
+            quote_amount: Uint128::from(50u128),
+        },
+        &[],
+    )
+    .unwrap();
+
+    let err = app
+        .execute_contract(
+            spender.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RetrieveAllowance { vault_id: None },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::OracleNotConfigured {}
+    );
+
+    let set_oracle = |max_tokens_per_claim: u128| ExecuteMsg::SetOracleConfig {
+        oracle: oracle.to_string(),
+        quote: "USD".to_string(),
+        max_staleness: 60,
+        max_deviation: Decimal::percent(10),
+        max_tokens_per_claim: Uint128::from(max_tokens_per_claim),
+    };
+    app.execute_contract(owner.clone(), contract_addr.clone(), &set_oracle(100), &[])
+        .unwrap();
+
+    let resp: PeggedAllowanceResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetPeggedAllowance {
+                spender: spender.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(resp.quote_amount, Uint128::from(50u128));
+    assert_eq!(resp.quote, "USD");
+
+    // 50 USD at 2 USD per atom
+    app.execute_contract(
+        spender.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RetrieveAllowance { vault_id: None },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&spender, "atom").unwrap().amount,
+        Uint128::from(25u128)
+    );
+
+    // A 25% jump from the last paid price is rejected
+    app.execute_contract(
+        owner.clone(),
+        oracle.clone(),
+        &PriceResponse {
+            price: Decimal::percent(250),
+            updated_at: app.block_info().time,
+        },
+        &[],
+    )
+    .unwrap();
+    let err = app
+        .execute_contract(
+            spender.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RetrieveAllowance { vault_id: None },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::PriceDeviation {
+            price: "2.5".to_string(),
+            last_price: "2".to_string(),
+        }
+    );
+
+    // A 5% move is paid
+    app.execute_contract(
+        owner.clone(),
+        oracle.clone(),
+        &PriceResponse {
+            price: Decimal::percent(210),
+            updated_at: app.block_info().time,
+        },
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(
+        spender.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RetrieveAllowance { vault_id: None },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&spender, "atom").unwrap().amount,
+        Uint128::from(48u128)
+    );
+
+    // The allowance holds back 50 USD at the last paid price
+    let resp: AllowanceResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetAllowance {
+                vault_id: None,
+                spender: spender.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(resp.amount, Uint128::from(23u128));
+    let resp: ShareConfigResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetShareConfig {})
+        .unwrap();
+    assert_eq!(resp.free_amount, Uint128::from(929u128));
+
+    // Prices older than a minute are stale
+    let updated_at = app.block_info().time;
+    app.update_block(|block| block.time = block.time.plus_seconds(61));
+    let err = app
+        .execute_contract(
+            spender.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RetrieveAllowance { vault_id: None },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::StalePrice {
+            updated_at: updated_at.seconds(),
+        }
+    );
+
+    // A claim never pays more than the cap
+    app.execute_contract(
+        owner.clone(),
+        oracle.clone(),
+        &PriceResponse {
+            price: Decimal::percent(20),
+            updated_at: app.block_info().time,
+        },
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(owner.clone(), contract_addr.clone(), &set_oracle(100), &[])
+        .unwrap();
+    app.execute_contract(
+        spender.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RetrieveAllowance { vault_id: None },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&spender, "atom").unwrap().amount,
+        Uint128::from(148u128)
+    );
+
+    // A day later the last paid price no longer bounds the oracle
+    let price_jump = |app: &mut App| {
+        app.execute_contract(
+            owner.clone(),
+            oracle.clone(),
+            &PriceResponse {
+                price: Decimal::one(),
+                updated_at: app.block_info().time,
+            },
+            &[],
+        )
+        .unwrap();
+        app.execute_contract(
+            spender.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RetrieveAllowance { vault_id: None },
+            &[],
+        )
+    };
+    let err = price_jump(&mut app).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::PriceDeviation {
+            price: "1".to_string(),
+            last_price: "0.2".to_string(),
+        }
+    );
+    app.update_block(|block| block.time = block.time.plus_seconds(86_400));
+    price_jump(&mut app).unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&spender, "atom").unwrap().amount,
+        Uint128::from(198u128)
+    );
+
+    let resp: StateResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: None },
+        )
+        .unwrap();
+    assert_eq!(resp.total_amount, Uint128::from(802u128));
+    let resp: OracleConfigResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr, &QueryMsg::GetOracleConfig {})
+        .unwrap();
+    assert_eq!(resp.last_price, Some(Decimal::one()));
+    assert_eq!(resp.last_price_at, Some(app.block_info().time));
+}
+
+#[test]
+fn multitest_hooks() {
+    let owner = Addr::unchecked("owner");
+    let spender = Addr::unchecked("spender");
+
+    let mut app = App::new(|router, _api, storage| {
+        router
+            .bank
+            .init_balance(storage, &owner, coins(100, "atom"))
+            .unwrap();
+    });
+
+    let contract_id = app.store_code(vault_contract());
+    let hook_id = app.store_code(mock_hook_contract());
+
+    let contract_addr = app
+        .instantiate_contract(
+            contract_id,
+            owner.clone(),
+            &InstantiateMsg {
+                name: "Vault X".to_string(),
+                expected_denom: "atom".to_string(),
+            },
+            &[],
+            "Vault contract",
+            None,
+        )
+        .unwrap();
+    let recorder = app
+        .instantiate_contract(hook_id, owner.clone(), &false, &[], "Recorder", None)
+        .unwrap();
+    let failing = app
+        .instantiate_contract(hook_id, owner.clone(), &true, &[], "Failing", None)
+        .unwrap();
+
+    for (hook, blocking) in [(&recorder, true), (&failing, false)] {
+        app.execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::AddHook {
+                address: hook.to_string(),
+                blocking,
+            },
+            &[],
+        )
+        .unwrap();
+    }
+
+    let resp: HooksResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetHooks {})
+        .unwrap();
+    assert_eq!(resp.hooks.len(), 2);
+
+    // The failing hook is not blocking, so every action goes through
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit { vault_id: None },
+        &coins(50, "atom"),
+    )
+    .unwrap();
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddAllowance {
+            vault_id: None,
+            spender: spender.to_string(),
+            amount: Uint128::from(10u128),
+        },
+        &[],
+    )
+    .unwrap();
+    let res = app
+        .execute_contract(
+            spender.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RetrieveAllowance { vault_id: None },
+            &[],
+        )
+        .unwrap();
+    assert!(res.events.iter().any(|event| event
+        .attributes
+        .iter()
+        .any(|attribute| attribute.value == "hook_failed")));
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RemoveAllowance {
+            vault_id: None,
+            spender: spender.to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Withdraw { vault_id: None },
+        &[],
+    )
+    .unwrap();
+
+    let calls: Vec<VaultHookMsg> = app
+        .wrap()
+        .query_wasm_smart(recorder.clone(), &Empty {})
+        .unwrap();
+    assert_eq!(
+        calls,
+        vec![
+            VaultHookMsg::Deposit {
+                address: owner.to_string(),
+                amount: Uint128::from(50u128),
+            },
+            VaultHookMsg::AllowanceChanged {
+                spender: spender.to_string(),
+                amount: Some(Uint128::from(10u128)),
+            },
+            VaultHookMsg::Claim {
+                spender: spender.to_string(),
+                amount: Uint128::from(10u128),
+            },
+            VaultHookMsg::AllowanceChanged {
+                spender: spender.to_string(),
+                amount: None,
+            },
+            VaultHookMsg::Withdraw {
+                owner: owner.to_string(),
+                amount: coins(40, "atom"),
+            },
+        ]
+    );
+
+    // A failing blocking hook reverts the action
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddHook {
+            address: failing.to_string(),
+            blocking: true,
+        },
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit { vault_id: None },
+        &coins(10, "atom"),
+    )
+    .unwrap_err();
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RemoveHook {
+            address: failing.to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit { vault_id: None },
+        &coins(10, "atom"),
+    )
+    .unwrap();
+    let resp: StateResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: None },
+        )
+        .unwrap();
+    assert_eq!(resp.total_amount, Uint128::from(10u128));
+
+    // Pegged allowances and a governance recovery are reported too
+    for msg in [
+        ExecuteMsg::AddPeggedAllowance {
+            spender: spender.to_string(),
+            quote_amount: Uint128::from(5u128),
+        },
+        ExecuteMsg::RemovePeggedAllowance {
+            spender: spender.to_string(),
+        },
+    ] {
+        app.execute_contract(owner.clone(), contract_addr.clone(), &msg, &[])
+            .unwrap();
+    }
+    app.wasm_sudo(
+        contract_addr,
+        &SudoMsg::RecoverFunds {
+            recipient: "recovery".to_string(),
+        },
+    )
+    .unwrap();
+    let calls: Vec<VaultHookMsg> = app.wrap().query_wasm_smart(recorder, &Empty {}).unwrap();
+    assert_eq!(
+        calls[calls.len() - 3..],
+        [
+            VaultHookMsg::AllowanceChanged {
+                spender: spender.to_string(),
+                amount: Some(Uint128::from(5u128)),
+            },
+            VaultHookMsg::AllowanceChanged {
+                spender: spender.to_string(),
+                amount: None,
+            },
+            VaultHookMsg::Withdraw {
+                owner: "recovery".to_string(),
+                amount: coins(10, "atom"),
+            },
+        ]
+    );
+}
+
+#[test]
+fn multitest_sudo() {
+    let owner = Addr::unchecked("owner");
+    let governance_owner = Addr::unchecked("governance_owner");
+    let recovery = Addr::unchecked("recovery");
+
+    let mut app = App::new(|router, _api, storage| {
+        router
+            .bank
+            .init_balance(storage, &owner, coins(100, "atom"))
+            .unwrap();
+        router
+            .bank
+            .init_balance(storage, &governance_owner, coins(10, "atom"))
+            .unwrap();
+    });
+
+    let contract_id = app.store_code(vault_contract());
+    let contract_addr = app
+        .instantiate_contract(
+            contract_id,
+            owner.clone(),
+            &InstantiateMsg {
+                name: "Vault X".to_string(),
+                expected_denom: "atom".to_string(),
+            },
+            &[],
+            "Vault contract",
+            None,
+        )
+        .unwrap();
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit { vault_id: None },
+        &coins(60, "atom"),
+    )
+    .unwrap();
+
+    // Governance hands the vault to a new owner
+    let res = app
+        .wasm_sudo(
+            contract_addr.clone(),
+            &SudoMsg::ForceOwnerChange {
+                owner: governance_owner.to_string(),
+            },
+        )
+        .unwrap();
+    assert!(res.has_event(
+        &cosmwasm_std::Event::new("wasm-vault_sudo")
+            .add_attribute("action", "force_owner_change")
+            .add_attribute("previous_owner", owner.to_string())
+            .add_attribute("owner", governance_owner.to_string())
+    ));
+    let resp: StateResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: None },
+        )
+        .unwrap();
+    assert_eq!(resp.owner, governance_owner.to_string());
+
+    // A paused vault rejects every execute message
+    app.wasm_sudo(contract_addr.clone(), &SudoMsg::Pause {})
+        .unwrap();
+    let resp: PausedResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::IsPaused {})
+        .unwrap();
+    assert!(resp.paused);
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Deposit { vault_id: None },
+            &coins(10, "atom"),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::Paused {}
+    );
+
+    // Recovery works while paused and sends everything to the recovery address
+    app.wasm_sudo(
+        contract_addr.clone(),
+        &SudoMsg::RecoverFunds {
+            recipient: recovery.to_string(),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&recovery, "atom").unwrap().amount,
+        Uint128::from(60u128)
+    );
+
+    // The deposit address moved to the new owner along with the vault
+    app.wasm_sudo(contract_addr.clone(), &SudoMsg::Unpause {})
+        .unwrap();
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Deposit { vault_id: None },
+            &coins(10, "atom"),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::UnauthorizedDepositAddress {
+            address: owner.to_string()
+        }
+    );
+    app.execute_contract(
+        governance_owner,
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit { vault_id: None },
+        &coins(10, "atom"),
+    )
+    .unwrap();
+    let resp: StateResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr, &QueryMsg::GetState { vault_id: None })
+        .unwrap();
+    assert_eq!(resp.total_amount, Uint128::from(10u128));
+}
+
+#[test]
+fn multitest_dead_man_switch() {
+    let owner = Addr::unchecked("owner");
+    let new_owner = Addr::unchecked("new_owner");
+    let spender = Addr::unchecked("spender");
+    let beneficiary = Addr::unchecked("beneficiary");
+    let guardians = ["guardian1", "guardian2", "guardian3"].map(Addr::unchecked);
+
+    let mut app = App::new(|router, _api, storage| {
+        router
+            .bank
+            .init_balance(storage, &owner, coins(100, "atom"))
+            .unwrap();
+    });
+
+    let contract_id = app.store_code(vault_contract());
+    let contract_addr = app
+        .instantiate_contract(
+            contract_id,
+            owner.clone(),
+            &InstantiateMsg {
+                name: "Vault X".to_string(),
+                expected_denom: "atom".to_string(),
+            },
+            &[],
+            "Vault contract",
+            None,
+        )
+        .unwrap();
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit { vault_id: None },
+        &coins(100, "atom"),
+    )
+    .unwrap();
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddAllowance {
+            vault_id: None,
+            spender: spender.to_string(),
+            amount: Uint128::from(30u128),
+        },
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::CreateGrant {
+            grantee: "grantee".to_string(),
+            arbiter: "arbiter".to_string(),
+            milestones: vec![MilestoneMsg {
+                amount: Uint128::from(20u128),
+                description: "Report".to_string(),
+            }],
+        },
+        &[],
+    )
+    .unwrap();
+
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::SetDeadManSwitch {
+                interval: 100,
+                guardians: guardians.iter().map(|x| x.to_string()).collect(),
+                threshold: 4,
+                recovery_period: 50,
+                beneficiary: beneficiary.to_string(),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::InvalidGuardianThreshold {}
+    );
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::SetDeadManSwitch {
+            interval: 100,
+            guardians: guardians.iter().map(|x| x.to_string()).collect(),
+            threshold: 2,
+            recovery_period: 50,
+            beneficiary: beneficiary.to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+
+    // A heartbeat restarts the interval
+    app.update_block(|block| block.time = block.time.plus_seconds(80));
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Heartbeat {},
+        &[],
+    )
+    .unwrap();
+    app.update_block(|block| block.time = block.time.plus_seconds(60));
+    let resp: DeadManSwitchResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetDeadManSwitch {})
+        .unwrap();
+    assert_eq!(resp.seconds_remaining, 40);
+    assert!(!resp.lapsed);
+
+    let err = app
+        .execute_contract(
+            guardians[0].clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::VoteRecovery {
+                new_owner: new_owner.to_string(),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::SwitchNotLapsed {
+            seconds_remaining: 40
+        }
+    );
+
+    // After the lapse two guardians hand the vault to a new owner
+    app.update_block(|block| block.time = block.time.plus_seconds(41));
+    let err = app
+        .execute_contract(
+            spender.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::VoteRecovery {
+                new_owner: spender.to_string(),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::NotGuardian {}
+    );
+    for guardian in &guardians[..2] {
+        app.execute_contract(
+            guardian.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::VoteRecovery {
+                new_owner: new_owner.to_string(),
+            },
+            &[],
+        )
+        .unwrap();
+    }
+    let resp: StateResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: None },
+        )
+        .unwrap();
+    assert_eq!(resp.owner, new_owner.to_string());
+    let resp: DeadManSwitchResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetDeadManSwitch {})
+        .unwrap();
+    assert_eq!(resp.seconds_remaining, 100);
+    assert!(resp.votes.is_empty());
+
+    // The new owner goes silent too, and nobody recovers the vault in time
+    app.update_block(|block| block.time = block.time.plus_seconds(101));
+    let err = app
+        .execute_contract(
+            spender.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::StartWindDown {},
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::RecoveryPeriodActive {
+            seconds_remaining: 49
+        }
+    );
+    app.update_block(|block| block.time = block.time.plus_seconds(50));
+    app.execute_contract(
+        spender.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::StartWindDown {},
+        &[],
+    )
+    .unwrap();
+
+    let err = app
+        .execute_contract(
+            new_owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Heartbeat {},
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::WindingDown {}
+    );
+
+    // Escrowed grants are still paid out
+    app.execute_contract(
+        Addr::unchecked("arbiter"),
+        contract_addr.clone(),
+        &ExecuteMsg::CompleteMilestone {
+            grant_id: 1,
+            milestone: 0,
+        },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance("grantee", "atom").unwrap().amount,
+        Uint128::from(20u128)
+    );
+
+    // The allowance stays claimable and the rest goes to the beneficiary
+    app.execute_contract(
+        spender.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::ReleaseToBeneficiary {},
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap()
+            .query_balance(&beneficiary, "atom")
+            .unwrap()
+            .amount,
+        Uint128::from(50u128)
+    );
+    app.execute_contract(
+        spender.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RetrieveAllowance { vault_id: None },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&spender, "atom").unwrap().amount,
+        Uint128::from(30u128)
+    );
+}
+
+#[test]
+fn multitest_close_vault() {
+    let owner = Addr::unchecked("owner");
+    let alice = Addr::unchecked("alice");
+    let bob = Addr::unchecked("bob");
+    let spender = Addr::unchecked("spender");
+
+    let mut app = App::new(|router, _api, storage| {
+        router
+            .bank
+            .init_balance(storage, &alice, coins(30, "atom"))
+            .unwrap();
+        router
+            .bank
+            .init_balance(storage, &bob, coins(10, "atom"))
+            .unwrap();
+    });
+
+    let contract_id = app.store_code(vault_contract());
+    let contract_addr = app
+        .instantiate_contract(
+            contract_id,
+            owner.clone(),
+            &InstantiateMsg {
+                name: "Vault X".to_string(),
+                expected_denom: "atom".to_string(),
+            },
+            &[],
+            "Vault contract",
+            None,
+        )
+        .unwrap();
+
+    for (depositor, amount) in [(&alice, 30), (&bob, 10)] {
+        app.execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::AddDepositAddress {
+                vault_id: None,
+                address: depositor.to_string(),
+            },
+            &[],
+        )
+        .unwrap();
+        app.execute_contract(
+            depositor.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Deposit { vault_id: None },
+            &coins(amount, "atom"),
+        )
+        .unwrap();
+    }
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddAllowance {
+            vault_id: None,
+            spender: spender.to_string(),
+            amount: Uint128::from(20u128),
+        },
+        &[],
+    )
+    .unwrap();
+    // Allowances that could never be claimed are dropped at closure
+    for (name, amount) in [("frozen", 4u128), ("zero", 0)] {
+        app.execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::AddAllowance {
+                vault_id: None,
+                spender: name.to_string(),
+                amount: Uint128::from(amount),
+            },
+            &[],
+        )
+        .unwrap();
+    }
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::FreezeAllowance {
+            spender: "frozen".to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+    // Bob may no longer deposit but keeps the refund
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RemoveDepositAddress {
+            vault_id: None,
+            address: bob.to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::CloseVault {},
+        &[],
+    )
+    .unwrap();
+    let resp: AllowancesResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetAllowances { vault_id: None },
+        )
+        .unwrap();
+    assert_eq!(resp.spenders, vec![spender.to_string()]);
+    let resp: ClosureResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetClosure {})
+        .unwrap();
+    assert_eq!(
+        resp,
+        ClosureResponse {
+            closed: true,
+            finalized: false,
+            refund_pool: Uint128::from(20u128),
+            total_deposits: Uint128::from(40u128),
+        }
+    );
+
+    // No new deposits or allowances
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::AddAllowance {
+                vault_id: None,
+                spender: alice.to_string(),
+                amount: Uint128::from(1u128),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::VaultClosed {}
+    );
+
+    // The reserved allowance is paid once more
+    app.execute_contract(
+        spender.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RetrieveAllowance { vault_id: None },
+        &[],
+    )
+    .unwrap();
+    let err = app
+        .execute_contract(
+            spender.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RetrieveAllowance { vault_id: None },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::NoAllowance {
+            address: spender.to_string()
+        }
+    );
+    assert_eq!(
+        app.wrap().query_balance(&spender, "atom").unwrap().amount,
+        Uint128::from(20u128)
+    );
+
+    // The rest goes back 3:1 as deposited
+    let resp: RefundResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetRefund {
+                address: alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(resp.amount, Uint128::from(15u128));
+    app.execute_contract(
+        alice.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::ClaimRefund {},
+        &[],
+    )
+    .unwrap();
+    let err = app
+        .execute_contract(
+            alice.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::ClaimRefund {},
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::RefundAlreadyClaimed {}
+    );
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::ClaimRefund {},
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::NoRefund {}
+    );
+    app.execute_contract(
+        bob.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::ClaimRefund {},
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&alice, "atom").unwrap().amount,
+        Uint128::from(15u128)
+    );
+    assert_eq!(
+        app.wrap().query_balance(&bob, "atom").unwrap().amount,
+        Uint128::from(5u128)
+    );
+
+    // Everything is paid, so the vault is finalized
+    let resp: ClosureResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetClosure {})
+        .unwrap();
+    assert!(resp.finalized);
+    let err = app
+        .execute_contract(
+            owner,
+            contract_addr,
+            &ExecuteMsg::Withdraw { vault_id: None },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::VaultFinalized {}
+    );
+}
+
+#[test]
+fn multitest_rate_limits() {
+    let owner = Addr::unchecked("owner");
+    let alice = Addr::unchecked("alice");
+    let bob = Addr::unchecked("bob");
+
+    let mut app = App::new(|router, _api, storage| {
+        router
+            .bank
+            .init_balance(storage, &owner, coins(1000, "atom"))
+            .unwrap();
+    });
+
+    let contract_id = app.store_code(vault_contract());
+    let contract_addr = app
+        .instantiate_contract(
+            contract_id,
+            owner.clone(),
+            &InstantiateMsg {
+                name: "Vault X".to_string(),
+                expected_denom: "atom".to_string(),
+            },
+            &[],
+            "Vault contract",
+            None,
+        )
+        .unwrap();
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit { vault_id: None },
+        &coins(1000, "atom"),
+    )
+    .unwrap();
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddAllowanceList {
+            vault_id: None,
+            spenders: vec![alice.to_string(), bob.to_string()],
+            amounts: vec![Uint128::from(30u128), Uint128::from(40u128)],
+        },
+        &[],
+    )
+    .unwrap();
+
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::SetRateLimits {
+                max_claim_per_window: Some(Uint128::from(50u128)),
+                window_seconds: 3600,
+                max_daily_outflow: Some(Uint128::from(120u128)),
+                bucket_seconds: 7200,
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::InvalidRateLimits {}
+    );
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::SetRateLimits {
+            max_claim_per_window: Some(Uint128::from(50u128)),
+            window_seconds: 3600,
+            max_daily_outflow: Some(Uint128::from(120u128)),
+            bucket_seconds: 600,
+        },
+        &[],
+    )
+    .unwrap();
+
+    let claim = |app: &mut App, spender: &Addr| {
+        app.execute_contract(
+            spender.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RetrieveAllowance { vault_id: None },
+            &[],
+        )
+    };
+
+    // Each spender can claim 50 per hour
+    claim(&mut app, &alice).unwrap();
+    let err = claim(&mut app, &alice).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::SpenderLimitExceeded { remaining: 20 }
+    );
+    claim(&mut app, &bob).unwrap();
+
+    let resp: RemainingQuotaResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetRemainingQuota {
+                spender: Some(alice.to_string()),
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        resp,
+        RemainingQuotaResponse {
+            spender_remaining: Some(Uint128::from(20u128)),
+            daily_outflow_remaining: Some(Uint128::from(50u128)),
+        }
+    );
+
+    // An hour later the spender windows are clear, but the day is not over
+    app.update_block(|block| block.time = block.time.plus_seconds(4200));
+    claim(&mut app, &alice).unwrap();
+    let err = claim(&mut app, &bob).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::DailyOutflowExceeded { remaining: 20 }
+    );
+
+    // A withdrawal is capped to what is left of the day
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Withdraw { vault_id: None },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&owner, "atom").unwrap().amount,
+        Uint128::from(20u128)
+    );
+    let resp: StateResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: None },
+        )
+        .unwrap();
+    assert_eq!(resp.total_amount, Uint128::from(880u128));
+
+    // Raising the limit keeps the counters of the same bucket size
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::SetRateLimits {
+            max_claim_per_window: Some(Uint128::from(50u128)),
+            window_seconds: 3600,
+            max_daily_outflow: Some(Uint128::from(1000u128)),
+            bucket_seconds: 600,
+        },
+        &[],
+    )
+    .unwrap();
+    let resp: RemainingQuotaResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetRemainingQuota { spender: None },
+        )
+        .unwrap();
+    assert_eq!(
+        resp,
+        RemainingQuotaResponse {
+            spender_remaining: None,
+            daily_outflow_remaining: Some(Uint128::from(880u128)),
+        }
+    );
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Withdraw { vault_id: None },
+        &[],
+    )
+    .unwrap();
+    let resp: RemainingQuotaResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr,
+            &QueryMsg::GetRemainingQuota { spender: None },
+        )
+        .unwrap();
+    assert_eq!(
+        resp,
+        RemainingQuotaResponse {
+            spender_remaining: None,
+            daily_outflow_remaining: Some(Uint128::zero()),
+        }
+    );
+}
+
+#[test]
+fn multitest_budgets() {
+    let owner = Addr::unchecked("owner");
+    let alice = Addr::unchecked("alice");
+    let bob = Addr::unchecked("bob");
+
+    let mut app = App::new(|router, _api, storage| {
+        router
+            .bank
+            .init_balance(storage, &owner, coins(1000, "atom"))
+            .unwrap();
+    });
+
+    let contract_id = app.store_code(vault_contract());
+    let contract_addr = app
+        .instantiate_contract(
+            contract_id,
+            owner.clone(),
+            &InstantiateMsg {
+                name: "Vault X".to_string(),
+                expected_denom: "atom".to_string(),
+            },
+            &[],
+            "Vault contract",
+            None,
+        )
+        .unwrap();
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit { vault_id: None },
+        &coins(1000, "atom"),
+    )
+    .unwrap();
+    for (name, allocated) in [("engineering", 100u128), ("marketing", 50u128)] {
+        app.execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::CreateBudget {
+                name: name.to_string(),
+                allocated: Uint128::from(allocated),
+            },
+            &[],
+        )
+        .unwrap();
+    }
+    for spender in [&alice, &bob] {
+        app.execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::AssignBudget {
+                spender: spender.to_string(),
+                budget: "engineering".to_string(),
+            },
+            &[],
+        )
+        .unwrap();
+    }
+
+    let allowance = |app: &mut App, spender: &Addr, amount: u128| {
+        app.execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::AddAllowance {
+                vault_id: None,
+                spender: spender.to_string(),
+                amount: Uint128::from(amount),
+            },
+            &[],
+        )
+    };
+
+    // Allowances of a budget cannot reserve more than its allocation
+    allowance(&mut app, &alice, 60).unwrap();
+    let err = allowance(&mut app, &bob, 50).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::BudgetExceeded {
+            name: "engineering".to_string(),
+            available: 100
+        }
+    );
+    allowance(&mut app, &bob, 40).unwrap();
+
+    // Claims are spent from the budget
+    app.execute_contract(
+        alice.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RetrieveAllowance { vault_id: None },
+        &[],
+    )
+    .unwrap();
+    let err = app
+        .execute_contract(
+            alice.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RetrieveAllowance { vault_id: None },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::BudgetExceeded {
+            name: "engineering".to_string(),
+            available: 40
+        }
+    );
+
+    let resp: BudgetResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetBudget {
+                name: "engineering".to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        resp,
+        BudgetResponse {
+            name: "engineering".to_string(),
+            allocated: Uint128::from(100u128),
+            reserved: Uint128::from(100u128),
+            spent: Uint128::from(60u128),
+        }
+    );
+
+    // Only the unreserved part of a budget can be reallocated
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RemoveAllowance {
+            vault_id: None,
+            spender: alice.to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+    allowance(&mut app, &bob, 20).unwrap();
+    let reallocate = |app: &mut App, amount: u128| {
+        app.execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::ReallocateBudget {
+                from: "engineering".to_string(),
+                to: "marketing".to_string(),
+                amount: Uint128::from(amount),
+            },
+            &[],
+        )
+    };
+    let err = reallocate(&mut app, 25).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::BudgetExceeded {
+            name: "engineering".to_string(),
+            available: 20
+        }
+    );
+    reallocate(&mut app, 15).unwrap();
+
+    let resp: BudgetsResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetBudgets {})
+        .unwrap();
+    assert_eq!(resp.budgets[0].allocated, Uint128::from(85u128));
+    assert_eq!(resp.budgets[1].allocated, Uint128::from(65u128));
+
+    let resp: ReallocationsResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetReallocations {})
+        .unwrap();
+    assert_eq!(resp.reallocations.len(), 1);
+    assert_eq!(resp.reallocations[0].amount, Uint128::from(15u128));
+    assert_eq!(resp.reallocations[0].from, "engineering");
+
+    // A removed allowance left its budget
+    allowance(&mut app, &alice, 30).unwrap();
+
+    // Grants to a budgeted grantee are spent from what the budget has left
+    let grant = |app: &mut App, amount: u128| {
+        app.execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::CreateGrant {
+                grantee: bob.to_string(),
+                arbiter: owner.to_string(),
+                milestones: vec![MilestoneMsg {
+                    amount: Uint128::from(amount),
+                    description: "prototype".to_string(),
+                }],
+            },
+            &[],
+        )
+    };
+    let err = grant(&mut app, 10).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::BudgetExceeded {
+            name: "engineering".to_string(),
+            available: 5
+        }
+    );
+    grant(&mut app, 5).unwrap();
+
+    // Unassigning a spender frees its reservation
+    let unassign = |app: &mut App| {
+        app.execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::UnassignBudget {
+                spender: bob.to_string(),
+            },
+            &[],
+        )
+    };
+    unassign(&mut app).unwrap();
+    let err = unassign(&mut app).unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::BudgetNotAssigned {
+            spender: bob.to_string()
+        }
+    );
+    let resp: BudgetResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr,
+            &QueryMsg::GetBudget {
+                name: "engineering".to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        resp,
+        BudgetResponse {
+            name: "engineering".to_string(),
+            allocated: Uint128::from(85u128),
+            reserved: Uint128::zero(),
+            spent: Uint128::from(65u128),
+        }
+    );
+}
+
+#[test]
+fn multitest_sub_vaults() {
+    let owner = Addr::unchecked("owner");
+    let alice = Addr::unchecked("alice");
+    let carol = Addr::unchecked("carol");
+
+    let mut app = App::new(|router, _api, storage| {
+        router
+            .bank
+            .init_balance(storage, &owner, coins(100, "atom"))
+            .unwrap();
+        router
+            .bank
+            .init_balance(storage, &alice, coins(50, "atom"))
+            .unwrap();
+    });
+
+    let contract_id = app.store_code(vault_contract());
+    let contract_addr = app
+        .instantiate_contract(
+            contract_id,
+            owner.clone(),
+            &InstantiateMsg {
+                name: "Vault X".to_string(),
+                expected_denom: "atom".to_string(),
+            },
+            &[],
+            "Vault contract",
+            None,
+        )
+        .unwrap();
+
+    for (name, denom) in [("Team A", "atom"), ("Team B", "atom"), ("Team C", "usdc")] {
+        app.execute_contract(
+            alice.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::CreateVault {
+                name: name.to_string(),
+                expected_denom: denom.to_string(),
+            },
+            &[],
+        )
+        .unwrap();
+    }
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit { vault_id: None },
+        &coins(100, "atom"),
+    )
+    .unwrap();
+    app.execute_contract(
+        alice.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit { vault_id: Some(1) },
+        &coins(50, "atom"),
+    )
+    .unwrap();
+
+    // The owner of the default vault has no say over a sub-vault
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::AddAllowance {
+                spender: carol.to_string(),
+                amount: Uint128::from(30u128),
+                vault_id: Some(1),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::NotOwner {
+            owner: alice.to_string()
+        }
+    );
+    app.execute_contract(
+        alice.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddAllowance {
+            spender: carol.to_string(),
+            amount: Uint128::from(30u128),
+            vault_id: Some(1),
+        },
+        &[],
+    )
+    .unwrap();
+
+    // A sub-vault only pays with its own funds
+    app.execute_contract(
+        carol.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RetrieveAllowance { vault_id: Some(1) },
+        &[],
+    )
+    .unwrap();
+    let err = app
+        .execute_contract(
+            carol.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RetrieveAllowance { vault_id: Some(1) },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::NotEnoughFunds { total_amount: 20 }
+    );
+    // A freeze by the default vault's owner does not reach other vaults
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::FreezeAllowance {
+            spender: carol.to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+    let err = app
+        .execute_contract(
+            carol.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RetrieveAllowance { vault_id: Some(1) },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::NotEnoughFunds { total_amount: 20 }
+    );
+
+    // Withdrawing the default vault leaves the sub-vault funds
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Withdraw { vault_id: None },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&owner, "atom").unwrap().amount,
+        Uint128::from(100u128)
+    );
+    assert_eq!(
+        app.wrap()
+            .query_balance(&contract_addr, "atom")
+            .unwrap()
+            .amount,
+        Uint128::from(20u128)
+    );
+
+    // Transfers need the same owner and denom on both sides, and only move
+    // funds that are not promised as allowance
+    app.execute_contract(
+        alice.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RemoveAllowance {
+            spender: carol.to_string(),
+            vault_id: Some(1),
+        },
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(
+        alice.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::TransferBetweenVaults {
+            from_vault: 1,
+            to_vault: 2,
+            amount: Uint128::from(15u128),
+        },
+        &[],
+    )
+    .unwrap();
+    let err = app
+        .execute_contract(
+            alice.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::TransferBetweenVaults {
+                from_vault: 2,
+                to_vault: 0,
+                amount: Uint128::from(5u128),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::NotOwner {
+            owner: owner.to_string()
+        }
+    );
+    let err = app
+        .execute_contract(
+            alice.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::TransferBetweenVaults {
+                from_vault: 2,
+                to_vault: 3,
+                amount: Uint128::from(5u128),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::VaultDenomMismatch {}
+    );
+
+    let resp: StateResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: Some(2) },
+        )
+        .unwrap();
+    assert_eq!(resp.name, "Team B");
+    assert_eq!(resp.total_amount, Uint128::from(15u128));
+
+    let resp: VaultsResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetVaults {})
+        .unwrap();
+    assert_eq!(
+        resp.vaults
+            .iter()
+            .map(|vault| vault.total_amount.u128())
+            .collect::<Vec<u128>>(),
+        vec![0, 5, 15, 0]
+    );
+
+    let err = app
+        .execute_contract(
+            alice,
+            contract_addr,
+            &ExecuteMsg::Deposit { vault_id: Some(9) },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::VaultNotFound { id: 9 }
+    );
+}
+
+#[test]
+fn multitest_campaign() {
+    let owner = Addr::unchecked("owner");
+    let alice = Addr::unchecked("alice");
+    let bob = Addr::unchecked("bob");
+    let spender = Addr::unchecked("spender");
+
+    let mut app = App::new(|router, _api, storage| {
+        router
+            .bank
+            .init_balance(storage, &alice, coins(60, "atom"))
+            .unwrap();
+        router
+            .bank
+            .init_balance(storage, &bob, coins(30, "atom"))
+            .unwrap();
+    });
+
+    let contract_id = app.store_code(vault_contract());
+    let contract_addr = app
+        .instantiate_contract(
+            contract_id,
+            owner.clone(),
+            &InstantiateMsg {
+                name: "Vault X".to_string(),
+                expected_denom: "atom".to_string(),
+            },
+            &[],
+            "Vault contract",
+            None,
+        )
+        .unwrap();
+    for depositor in [&alice, &bob] {
+        app.execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::AddDepositAddress {
+                vault_id: None,
+                address: depositor.to_string(),
+            },
+            &[],
+        )
+        .unwrap();
+    }
+
+    let deadline = app.block_info().time.plus_seconds(100);
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::StartCampaign {
+            goal: Uint128::from(100u128),
+            deadline,
+        },
+        &[],
+    )
+    .unwrap();
+    for (depositor, amount) in [(&alice, 30), (&bob, 10)] {
+        app.execute_contract(
+            depositor.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Deposit { vault_id: None },
+            &coins(amount, "atom"),
+        )
+        .unwrap();
+    }
+
+    // Funds are locked while the campaign runs
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Withdraw { vault_id: None },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::CampaignLocked {}
+    );
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RemoveDepositAddress {
+                vault_id: None,
+                address: alice.to_string(),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::CampaignLocked {}
+    );
+    // Adding a depositor again keeps the contribution
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddDepositAddress {
+            vault_id: None,
+            address: alice.to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::UnlockCampaign {},
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::GoalNotMet {
+            missing: Uint128::from(60u128)
+        }
+    );
+    let err = app
+        .execute_contract(
+            alice.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::ReclaimContribution {},
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::CampaignNotFailed {}
+    );
+
+    // The deadline passes without reaching the goal
+    app.update_block(|block| block.time = block.time.plus_seconds(101));
+    let err = app
+        .execute_contract(
+            alice.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Deposit { vault_id: None },
+            &coins(10, "atom"),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::CampaignEnded {}
+    );
+    let resp: CampaignResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetCampaign {})
+        .unwrap();
+    assert!(resp.active && resp.failed);
+    assert_eq!(resp.raised, Uint128::from(40u128));
+
+    app.execute_contract(
+        alice.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::ReclaimContribution {},
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&alice, "atom").unwrap().amount,
+        Uint128::from(60u128)
+    );
+    let err = app
+        .execute_contract(
+            alice.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::ReclaimContribution {},
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::NothingToReclaim {}
+    );
+
+    // A new campaign waits until every contribution is back
+    let deadline = app.block_info().time.plus_seconds(100);
+    let start = ExecuteMsg::StartCampaign {
+        goal: Uint128::from(50u128),
+        deadline,
+    };
+    let err = app
+        .execute_contract(owner.clone(), contract_addr.clone(), &start, &[])
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::CampaignActive {}
+    );
+    app.execute_contract(
+        bob.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::ReclaimContribution {},
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(owner.clone(), contract_addr.clone(), &start, &[])
+        .unwrap();
+
+    for (depositor, amount) in [(&alice, 30), (&bob, 20)] {
+        app.execute_contract(
+            depositor.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Deposit { vault_id: None },
+            &coins(amount, "atom"),
+        )
+        .unwrap();
+    }
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddAllowance {
+            vault_id: None,
+            spender: spender.to_string(),
+            amount: Uint128::from(25u128),
+        },
+        &[],
+    )
+    .unwrap();
+    let err = app
+        .execute_contract(
+            spender.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RetrieveAllowance { vault_id: None },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::CampaignLocked {}
+    );
+
+    // The goal is met, so the owner unlocks the funds
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::UnlockCampaign {},
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(
+        spender.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RetrieveAllowance { vault_id: None },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&spender, "atom").unwrap().amount,
+        Uint128::from(25u128)
+    );
+    let resp: CampaignResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetCampaign {})
+        .unwrap();
+    assert!(!resp.active && resp.unlocked && !resp.failed);
+    assert_eq!(resp.raised, Uint128::from(50u128));
+
+    // A failed campaign lets go of the funds once the reclaim period is over
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::StartCampaign {
+            goal: Uint128::from(100u128),
+            deadline: app.block_info().time.plus_seconds(100),
+        },
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(
+        alice.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit { vault_id: None },
+        &coins(10, "atom"),
+    )
+    .unwrap();
+    app.update_block(|block| block.time = block.time.plus_seconds(101 + CAMPAIGN_RECLAIM_SECONDS));
+    let err = app
+        .execute_contract(
+            alice.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::ReclaimContribution {},
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::ReclaimPeriodOver {}
+    );
+    let resp: CampaignResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetCampaign {})
+        .unwrap();
+    assert!(!resp.active && resp.failed);
+    assert_eq!(resp.reclaimed, Uint128::zero());
+
+    // Closure refunds follow every deposit, not only the last campaign's.
+    // 10 of the 35 in the pool are free, alice deposited 40 and bob 20.
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::CloseVault {},
+        &[],
+    )
+    .unwrap();
+    let resp: RefundResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetRefund {
+                address: alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(resp.amount, Uint128::from(6u128));
+}
+
+#[test]
+fn multitest_matching_pledges() {
+    let owner = Addr::unchecked("owner");
+    let sponsor = Addr::unchecked("sponsor");
+    let alice = Addr::unchecked("alice");
+    let bob = Addr::unchecked("bob");
+
+    let mut app = App::new(|router, _api, storage| {
+        router
+            .bank
+            .init_balance(storage, &sponsor, coins(100, "atom"))
+            .unwrap();
+        router
+            .bank
+            .init_balance(storage, &alice, coins(100, "atom"))
+            .unwrap();
+        router
+            .bank
+            .init_balance(storage, &bob, coins(200, "atom"))
+            .unwrap();
+    });
+
+    let contract_id = app.store_code(vault_contract());
+    let contract_addr = app
+        .instantiate_contract(
+            contract_id,
+            owner.clone(),
+            &InstantiateMsg {
+                name: "Vault X".to_string(),
+                expected_denom: "atom".to_string(),
+            },
+            &[],
+            "Vault contract",
+            None,
+        )
+        .unwrap();
+    for depositor in [&alice, &bob] {
+        app.execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::AddDepositAddress {
+                vault_id: None,
+                address: depositor.to_string(),
+            },
+            &[],
+        )
+        .unwrap();
+    }
+
+    // Pledges below the owner's minimum budget are refused
+    let expires = app.block_info().time.plus_seconds(100);
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::SetMinPledgeBudget {
+            amount: Uint128::from(50u128),
+        },
+        &[],
+    )
+    .unwrap();
+    let err = app
+        .execute_contract(
+            alice.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::PledgeMatch {
+                ratio: Decimal::percent(50),
+                cap_per_depositor: Uint128::from(30u128),
+                expires,
+            },
+            &coins(10, "atom"),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::PledgeBelowMinimum { min_budget: 50 }
+    );
+
+    app.execute_contract(
+        sponsor.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::PledgeMatch {
+            ratio: Decimal::percent(50),
+            cap_per_depositor: Uint128::from(30u128),
+            expires,
+        },
+        &coins(100, "atom"),
+    )
+    .unwrap();
+
+    // Half of each deposit is matched, up to 30 per depositor
+    for (depositor, amount) in [(&alice, 40), (&alice, 40), (&bob, 200)] {
+        app.execute_contract(
+            depositor.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Deposit { vault_id: None },
+            &coins(amount, "atom"),
+        )
+        .unwrap();
+    }
+    let state: StateResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: None },
+        )
+        .unwrap();
+    assert_eq!(state.total_amount, Uint128::from(340u128));
+
+    let resp: PledgeResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetPledge {
+                sponsor: sponsor.to_string(),
+                depositor: Some(alice.to_string()),
+            },
+        )
+        .unwrap();
+    assert_eq!(resp.budget, Uint128::from(40u128));
+    assert_eq!(resp.matched, Uint128::from(60u128));
+    assert_eq!(resp.depositor_remaining, Some(Uint128::zero()));
+    let resp: PledgeResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetPledge {
+                sponsor: sponsor.to_string(),
+                depositor: Some(owner.to_string()),
+            },
+        )
+        .unwrap();
+    assert_eq!(resp.depositor_remaining, Some(Uint128::from(30u128)));
+
+    // The unused budget is not part of a withdrawal
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Withdraw { vault_id: None },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&owner, "atom").unwrap().amount,
+        Uint128::from(340u128)
+    );
+
+    let err = app
+        .execute_contract(
+            sponsor.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::WithdrawPledge {},
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::PledgeNotExpired {
+            seconds_remaining: 100
+        }
+    );
+
+    app.update_block(|block| block.time = block.time.plus_seconds(100));
+    let resp: PledgesResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetPledges {})
+        .unwrap();
+    assert!(resp.pledges[0].expired);
+    app.execute_contract(
+        sponsor.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::WithdrawPledge {},
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&sponsor, "atom").unwrap().amount,
+        Uint128::from(40u128)
+    );
+    let resp: PledgesResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetPledges {})
+        .unwrap();
+    assert!(resp.pledges.is_empty());
+}
+
+#[test]
+fn multitest_savings_positions() {
+    let owner = Addr::unchecked("owner");
+    let alice = Addr::unchecked("alice");
+
+    let mut app = App::new(|router, _api, storage| {
+        router
+            .bank
+            .init_balance(storage, &alice, coins(150, "atom"))
+            .unwrap();
+    });
+
+    let contract_id = app.store_code(vault_contract());
+    let contract_addr = app
+        .instantiate_contract(
+            contract_id,
+            owner.clone(),
+            &InstantiateMsg {
+                name: "Vault X".to_string(),
+                expected_denom: "atom".to_string(),
+            },
+            &[],
+            "Vault contract",
+            None,
+        )
+        .unwrap();
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddDepositAddress {
+            vault_id: None,
+            address: alice.to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+
+    let err = app
+        .execute_contract(
+            alice.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::DepositLocked { lock_seconds: 100 },
+            &coins(100, "atom"),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::SavingsNotEnabled {}
+    );
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::SetEarlyExitPenalty {
+            penalty: Decimal::percent(10),
+        },
+        &[],
+    )
+    .unwrap();
+    for (amount, lock_seconds) in [(100, 100), (50, 1000)] {
+        app.execute_contract(
+            alice.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::DepositLocked { lock_seconds },
+            &coins(amount, "atom"),
+        )
+        .unwrap();
+    }
+
+    // Positions belong to the depositor, not to the pool
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Withdraw { vault_id: None },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&owner, "atom").unwrap().amount,
+        Uint128::zero()
+    );
+
+    app.update_block(|block| block.time = block.time.plus_seconds(100));
+    let resp: PositionsResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetPositions {
+                owner: alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(resp.early_exit_penalty, Some(Decimal::percent(10)));
+    assert_eq!(resp.positions.len(), 2);
+    assert!(resp.positions[0].unlocked);
+    assert!(!resp.positions[1].unlocked);
+
+    // The first lock is over, the second one is left early
+    for id in [1, 2] {
+        app.execute_contract(
+            alice.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::ClosePosition { id },
+            &[],
+        )
+        .unwrap();
+    }
+    assert_eq!(
+        app.wrap().query_balance(&alice, "atom").unwrap().amount,
+        Uint128::from(145u128)
+    );
+    let state: StateResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: None },
+        )
+        .unwrap();
+    assert_eq!(state.total_amount, Uint128::from(5u128));
+
+    let err = app
+        .execute_contract(
+            alice.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::ClosePosition { id: 1 },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::PositionNotFound { id: 1 }
+    );
+}
+
+#[test]
+fn multitest_protocol_fees() {
+    let owner = Addr::unchecked("owner");
+    let alice = Addr::unchecked("alice");
+    let bob = Addr::unchecked("bob");
+    let spender = Addr::unchecked("spender");
+    let treasury = Addr::unchecked("treasury");
+
+    let mut app = App::new(|router, _api, storage| {
+        router
+            .bank
+            .init_balance(storage, &alice, coins(202, "atom"))
+            .unwrap();
+        router
+            .bank
+            .init_balance(storage, &bob, coins(2000, "atom"))
+            .unwrap();
+    });
+
+    let contract_id = app.store_code(vault_contract());
+    let contract_addr = app
+        .instantiate_contract(
+            contract_id,
+            owner.clone(),
+            &InstantiateMsg {
+                name: "Vault X".to_string(),
+                expected_denom: "atom".to_string(),
+            },
+            &[],
+            "Vault contract",
+            None,
+        )
+        .unwrap();
+    for depositor in [&alice, &bob] {
+        app.execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::AddDepositAddress {
+                vault_id: None,
+                address: depositor.to_string(),
+            },
+            &[],
+        )
+        .unwrap();
+    }
+
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::SetFeeConfig {
+                deposit_bps: 100,
+                claim_bps: 500,
+                min_fee: Uint128::from(20u128),
+                max_fee: Some(Uint128::from(10u128)),
+                treasury: None,
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::InvalidFeeConfig {}
+    );
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::SetFeeConfig {
+            deposit_bps: 100,
+            claim_bps: 500,
+            min_fee: Uint128::from(2u128),
+            max_fee: Some(Uint128::from(10u128)),
+            treasury: None,
+        },
+        &[],
+    )
+    .unwrap();
+
+    // 1% of 100 is below the minimum and 1% of 2000 above the maximum
+    let res = app
+        .execute_contract(
+            alice.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Deposit { vault_id: None },
+            &coins(100, "atom"),
+        )
+        .unwrap();
+    let receipt: FeeReceipt = from_json(res.data.unwrap()).unwrap();
+    assert_eq!(
+        receipt,
+        FeeReceipt {
+            gross: Uint128::from(100u128),
+            fee: Uint128::from(2u128),
+            net: Uint128::from(98u128),
+        }
+    );
+    app.execute_contract(
+        bob.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit { vault_id: None },
+        &coins(2000, "atom"),
+    )
+    .unwrap();
+    let err = app
+        .execute_contract(
+            alice.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Deposit { vault_id: None },
+            &coins(2, "atom"),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::FeeExceedsAmount {
+            fee: Uint128::from(2u128)
+        }
+    );
+
+    // The spender gets the allowance minus 5%
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddAllowance {
+            vault_id: None,
+            spender: spender.to_string(),
+            amount: Uint128::from(100u128),
+        },
+        &[],
+    )
+    .unwrap();
+    let res = app
+        .execute_contract(
+            spender.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RetrieveAllowance { vault_id: None },
+            &[],
+        )
+        .unwrap();
+    let wasm = res.events.iter().find(|ev| ev.ty == "wasm").unwrap();
+    for (key, value) in [("gross_amount", "100"), ("fee", "5"), ("net_amount", "95")] {
+        assert!(wasm
+            .attributes
+            .iter()
+            .any(|attr| attr.key == key && attr.value == value));
+    }
+    assert_eq!(
+        app.wrap().query_balance(&spender, "atom").unwrap().amount,
+        Uint128::from(95u128)
+    );
+
+    // A claim the minimum fee would swallow whole is refused
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::UpdateAllowance {
+            spender: spender.to_string(),
+            amount: Uint128::from(2u128),
+            vault_id: None,
+        },
+        &[],
+    )
+    .unwrap();
+    let err = app
+        .execute_contract(
+            spender.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RetrieveAllowance { vault_id: None },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::FeeExceedsAmount {
+            fee: Uint128::from(2u128)
+        }
+    );
+
+    let state: StateResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetState { vault_id: None },
+        )
+        .unwrap();
+    assert_eq!(state.total_amount, Uint128::from(1988u128));
+    let resp: FeeConfigResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetFeeConfig {})
+        .unwrap();
+    assert_eq!(resp.accrued, Uint128::from(17u128));
+
+    // Withdrawals leave the fees for CollectFees
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Withdraw { vault_id: None },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&owner, "atom").unwrap().amount,
+        Uint128::from(1988u128)
+    );
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::CollectFees {},
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&owner, "atom").unwrap().amount,
+        Uint128::from(2005u128)
+    );
+
+    // With a treasury the fee is sent right away
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::SetFeeConfig {
+            deposit_bps: 100,
+            claim_bps: 500,
+            min_fee: Uint128::from(2u128),
+            max_fee: None,
+            treasury: Some(treasury.to_string()),
+        },
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(
+        alice.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit { vault_id: None },
+        &coins(100, "atom"),
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&treasury, "atom").unwrap().amount,
+        Uint128::from(2u128)
+    );
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::CollectFees {},
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::NoFees {}
+    );
+}
+
+#[test]
+fn calendar_years() {
+    assert_eq!(calendar_year(Timestamp::from_seconds(0)), 1970);
+    assert_eq!(calendar_year(Timestamp::from_seconds(951_868_799)), 2000);
+    assert_eq!(calendar_year(Timestamp::from_seconds(1_704_067_199)), 2023);
+    assert_eq!(calendar_year(Timestamp::from_seconds(1_704_067_200)), 2024);
+}
+
+#[test]
+fn multitest_withholding() {
+    let owner = Addr::unchecked("owner");
+    let authority = Addr::unchecked("authority");
+    let alice = Addr::unchecked("alice");
+    let bob = Addr::unchecked("bob");
+
+    let mut app = App::new(|router, _api, storage| {
+        router
+            .bank
+            .init_balance(storage, &owner, coins(1000, "atom"))
+            .unwrap();
+    });
+
+    let contract_id = app.store_code(vault_contract());
+    let contract_addr = app
+        .instantiate_contract(
+            contract_id,
+            owner.clone(),
+            &InstantiateMsg {
+                name: "Vault X".to_string(),
+                expected_denom: "atom".to_string(),
+            },
+            &[],
+            "Vault contract",
+            None,
+        )
+        .unwrap();
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit { vault_id: None },
+        &coins(1000, "atom"),
+    )
+    .unwrap();
+    for spender in [&alice, &bob] {
+        app.execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::AddAllowance {
+                vault_id: None,
+                spender: spender.to_string(),
+                amount: Uint128::from(100u128),
+            },
+            &[],
+        )
+        .unwrap();
+    }
+
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::SetWithholding {
+                spender: None,
+                rate: Decimal::percent(10),
+                destination: authority.to_string(),
+                brackets: vec![
+                    WithholdingBracketMsg {
+                        threshold: Uint128::from(200u128),
+                        rate: Decimal::percent(20),
+                    },
+                    WithholdingBracketMsg {
+                        threshold: Uint128::from(100u128),
+                        rate: Decimal::percent(30),
+                    },
+                ],
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::InvalidWithholding {}
+    );
+    // Withholding everything would leave nothing to pay out
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::SetWithholding {
+                spender: None,
+                rate: Decimal::percent(10),
+                destination: authority.to_string(),
+                brackets: vec![WithholdingBracketMsg {
+                    threshold: Uint128::from(100u128),
+                    rate: Decimal::one(),
+                }],
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::InvalidWithholding {}
+    );
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::SetWithholding {
+            spender: None,
+            rate: Decimal::percent(10),
+            destination: authority.to_string(),
+            brackets: vec![],
+        },
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::SetWithholding {
+            spender: Some(bob.to_string()),
+            rate: Decimal::percent(10),
+            destination: authority.to_string(),
+            brackets: vec![WithholdingBracketMsg {
+                threshold: Uint128::from(150u128),
+                rate: Decimal::percent(30),
+            }],
+        },
+        &[],
+    )
+    .unwrap();
+
+    // Alice falls under the global rule
+    app.execute_contract(
+        alice.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RetrieveAllowance { vault_id: None },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&alice, "atom").unwrap().amount,
+        Uint128::from(90u128)
+    );
+
+    // Bob's second claim crosses into the 30% bracket halfway
+    for _ in 0..2 {
+        app.execute_contract(
+            bob.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RetrieveAllowance { vault_id: None },
+            &[],
+        )
+        .unwrap();
+    }
+    assert_eq!(
+        app.wrap().query_balance(&bob, "atom").unwrap().amount,
+        Uint128::from(170u128)
+    );
+    assert_eq!(
+        app.wrap().query_balance(&authority, "atom").unwrap().amount,
+        Uint128::from(40u128)
+    );
+    let report: WithholdingReportResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetWithholdingReport {
+                spender: bob.to_string(),
+                year: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(report.year, 2019);
+    assert_eq!(report.claimed, Uint128::from(200u128));
+    assert_eq!(report.withheld, Uint128::from(30u128));
+    assert_eq!(report.rate, Some(Decimal::percent(10)));
+
+    // Brackets start over in a new year
+    app.update_block(|block| block.time = block.time.plus_seconds(80 * 86_400));
+    app.execute_contract(
+        bob.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RetrieveAllowance { vault_id: None },
+        &[],
+    )
+    .unwrap();
+    let report: WithholdingReportResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetWithholdingReport {
+                spender: bob.to_string(),
+                year: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(report.year, 2020);
+    assert_eq!(report.claimed, Uint128::from(100u128));
+    assert_eq!(report.withheld, Uint128::from(10u128));
+    let report: WithholdingReportResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetWithholdingReport {
+                spender: bob.to_string(),
+                year: Some(2019),
+            },
+        )
+        .unwrap();
+    assert_eq!(report.withheld, Uint128::from(30u128));
+}
+
+#[test]
+fn multitest_compliance_screening() {
+    let owner = Addr::unchecked("owner");
+    let alice = Addr::unchecked("alice");
+    let bob = Addr::unchecked("bob");
+    let carol = Addr::unchecked("carol");
+
+    let mut app = App::new(|router, _api, storage| {
+        router
+            .bank
+            .init_balance(storage, &owner, coins(100, "atom"))
+            .unwrap();
+        router
+            .bank
+            .init_balance(storage, &carol, coins(10, "atom"))
+            .unwrap();
+    });
+
+    let contract_id = app.store_code(vault_contract());
+    let contract_addr = app
+        .instantiate_contract(
+            contract_id,
+            owner.clone(),
+            &InstantiateMsg {
+                name: "Vault X".to_string(),
+                expected_denom: "atom".to_string(),
+            },
+            &[],
+            "Vault contract",
+            None,
+        )
+        .unwrap();
+    let registry_id = app.store_code(mock_registry_contract());
+    let registry = app
+        .instantiate_contract(
+            registry_id,
+            owner.clone(),
+            &vec![bob.to_string()],
+            &[],
+            "Registry",
+            None,
+        )
+        .unwrap();
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit { vault_id: None },
+        &coins(100, "atom"),
+    )
+    .unwrap();
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddDepositAddress {
+            vault_id: None,
+            address: carol.to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+    for spender in [&alice, &bob] {
+        app.execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::AddAllowance {
+                vault_id: None,
+                spender: spender.to_string(),
+                amount: Uint128::from(10u128),
+            },
+            &[],
+        )
+        .unwrap();
+    }
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::SetComplianceRegistry {
+            registry: Some(registry.to_string()),
+        },
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddToDenyList {
+            address: carol.to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+
+    // Bob is flagged by the registry and Carol is on the deny list
+    let err = app
+        .execute_contract(
+            bob.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RetrieveAllowance { vault_id: None },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::AddressFlagged {
+            address: bob.to_string()
+        }
+    );
+    let err = app
+        .execute_contract(
+            carol.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Deposit { vault_id: None },
+            &coins(10, "atom"),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::AddressFlagged {
+            address: carol.to_string()
+        }
+    );
+    // Locked deposits and matching pledges are deposits too
+    let expires = app.block_info().time.plus_seconds(100);
+    for msg in [
+        ExecuteMsg::DepositLocked { lock_seconds: 100 },
+        ExecuteMsg::PledgeMatch {
+            ratio: Decimal::one(),
+            cap_per_depositor: Uint128::from(10u128),
+            expires,
+        },
+    ] {
+        let err = app
+            .execute_contract(
+                carol.clone(),
+                contract_addr.clone(),
+                &msg,
+                &coins(10, "atom"),
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.downcast::<ContractError>().unwrap(),
+            ContractError::AddressFlagged {
+                address: carol.to_string()
+            }
+        );
+    }
+    app.execute_contract(
+        alice.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RetrieveAllowance { vault_id: None },
+        &[],
+    )
+    .unwrap();
+
+    // Without the registry only the deny list applies
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::SetComplianceRegistry { registry: None },
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(
+        bob.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RetrieveAllowance { vault_id: None },
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(
+        carol.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit { vault_id: None },
+        &coins(10, "atom"),
+    )
+    .unwrap_err();
+
+    // A frozen spender keeps the allowance but cannot claim
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::FreezeAllowance {
+            spender: alice.to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+    let err = app
+        .execute_contract(
+            alice.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RetrieveAllowance { vault_id: None },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::AllowanceFrozen {
+            spender: alice.to_string()
+        }
+    );
+    let resp: AllowanceResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetAllowance {
+                vault_id: None,
+                spender: alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(resp.amount, Uint128::from(10u128));
+    let resp: ComplianceResponse = app
+        .wrap()
+        .query_wasm_smart(contract_addr.clone(), &QueryMsg::GetCompliance {})
+        .unwrap();
+    assert_eq!(
+        resp,
+        ComplianceResponse {
+            registry: None,
+            denied: vec![carol.to_string()],
+            frozen: vec![alice.to_string()],
+        }
+    );
+
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::UnfreezeAllowance {
+            spender: alice.to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(
+        alice.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RetrieveAllowance { vault_id: None },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&alice, "atom").unwrap().amount,
+        Uint128::from(20u128)
+    );
+
+    // Refunds of a closed vault are screened like every other payout
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::CloseVault {},
+        &[],
+    )
+    .unwrap();
+    let err = app
+        .execute_contract(
+            carol.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::ClaimRefund {},
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::AddressFlagged {
+            address: carol.to_string()
+        }
+    );
+}
+
+#[test]
+fn multitest_freeze_and_clawback() {
+    let owner = Addr::unchecked("owner");
+    let spender = Addr::unchecked("spender");
+
+    let mut app = App::new(|router, _api, storage| {
+        router
+            .bank
+            .init_balance(storage, &owner, coins(100, "atom"))
+            .unwrap();
+    });
+
+    let contract_id = app.store_code(vault_contract());
+    let contract_addr = app
+        .instantiate_contract(
+            contract_id,
+            owner.clone(),
+            &InstantiateMsg {
+                name: "Vault X".to_string(),
+                expected_denom: "atom".to_string(),
+            },
+            &[],
+            "Vault contract",
+            None,
+        )
+        .unwrap();
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit { vault_id: None },
+        &coins(100, "atom"),
+    )
+    .unwrap();
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddAllowance {
+            vault_id: None,
+            spender: spender.to_string(),
+            amount: Uint128::from(30u128),
+        },
+        &[],
+    )
+    .unwrap();
+
+    // A dispute freezes the allowance, then part of it is clawed back
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::FreezeAllowance {
+            spender: spender.to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+    let err = app
+        .execute_contract(
+            owner.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::ClawbackAllowance {
+                spender: spender.to_string(),
+                amount: Uint128::from(40u128),
+                reason: "dispute".to_string(),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::ClawbackExceedsAllowance {
+            allowance: Uint128::from(30u128)
+        }
+    );
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::ClawbackAllowance {
+            spender: spender.to_string(),
+            amount: Uint128::from(20u128),
+            reason: "dispute".to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+
+    let frozen_at = app.block_info().time;
+    let resp: AllowanceResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetAllowance {
+                vault_id: None,
+                spender: spender.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        resp,
+        AllowanceResponse {
+            spender: spender.to_string(),
+            amount: Uint128::from(10u128),
+            frozen_since: Some(frozen_at),
+            clawbacks: vec![ClawbackResponse {
+                amount: Uint128::from(20u128),
+                reason: "dispute".to_string(),
+                time: frozen_at,
+            }],
+        }
+    );
+
+    // Once unfrozen the spender claims what is left
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::UnfreezeAllowance {
+            spender: spender.to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(
+        spender.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RetrieveAllowance { vault_id: None },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&spender, "atom").unwrap().amount,
+        Uint128::from(10u128)
+    );
+
+    // An allowance clawed back to zero stays visible but pays nothing
+    app.update_block(|block| block.time = block.time.plus_seconds(60));
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::ClawbackAllowance {
+            spender: spender.to_string(),
+            amount: Uint128::from(10u128),
+            reason: "terminated".to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+    let err = app
+        .execute_contract(
+            spender.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RetrieveAllowance { vault_id: None },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.downcast::<ContractError>().unwrap(),
+        ContractError::ZeroAllowance {
+            address: spender.to_string()
+        }
+    );
+    let resp: AllowanceResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr.clone(),
+            &QueryMsg::GetAllowance {
+                vault_id: None,
+                spender: spender.to_string(),
+            },
+        )
+        .unwrap();
+    assert!(resp.amount.is_zero() && resp.frozen_since.is_none());
+    assert_eq!(resp.clawbacks.len(), 2);
+    assert_eq!(resp.clawbacks[1].reason, "terminated");
+
+    // Closing drops the zero allowance but not its clawbacks
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::CloseVault {},
+        &[],
+    )
+    .unwrap();
+    let resp: AllowanceResponse = app
+        .wrap()
+        .query_wasm_smart(
+            contract_addr,
+            &QueryMsg::GetAllowance {
+                vault_id: None,
+                spender: spender.to_string(),
+            },
+        )
+        .unwrap();
+    assert!(resp.amount.is_zero());
+    assert_eq!(resp.clawbacks.len(), 2);
+}
+
+#[test]
+fn mock_reserved_total() {
+    let mut deps = mock_dependencies();
+    let env = mock_env();
+
+    instantiate(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("owner", &[]),
+        InstantiateMsg {
+            name: "Vault X".to_string(),
+            expected_denom: "atom".to_string(),
+        },
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("owner", &coins(100, "atom")),
+        ExecuteMsg::Deposit { vault_id: None },
+    )
+    .unwrap();
+    for (spender, amount) in [("alice", 40u128), ("bob", 30u128), ("alice", 25u128)] {
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("owner", &[]),
+            ExecuteMsg::AddAllowance {
+                vault_id: None,
+                spender: spender.to_string(),
+                amount: Uint128::from(amount),
+            },
+        )
+        .unwrap();
+    }
+    let state = STATE.load(&deps.storage).unwrap();
+    assert_eq!(
+        free_amount(&deps.storage, &state).unwrap(),
+        Uint128::from(45u128)
+    );
+
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("owner", &[]),
+        ExecuteMsg::RemoveAllowance {
+            vault_id: None,
+            spender: "bob".to_string(),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        free_amount(&deps.storage, &state).unwrap(),
+        Uint128::from(75u128)
+    );
+
+    // Vaults from before the running total get it back on migration.
+    RESERVED_TOTAL.remove(&mut deps.storage);
+    migrate(deps.as_mut(), env, MigrateMsg {}).unwrap();
+    assert_eq!(
+        RESERVED_TOTAL.load(&deps.storage).unwrap(),
+        Uint128::from(25u128)
+    );
+}
+
+#[test]
+fn mock_share_assets() {
+    let mut deps = mock_dependencies();
+    let env = mock_env();
+
+    instantiate(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("owner", &[]),
+        InstantiateMsg {
+            name: "Vault X".to_string(),
+            expected_denom: "atom".to_string(),
+        },
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("owner", &[]),
+        ExecuteMsg::EnableShareMode {
+            token: "shares".to_string(),
+        },
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("owner", &coins(100, "atom")),
+        ExecuteMsg::Deposit { vault_id: None },
+    )
+    .unwrap();
+
+    // Staked and unbonding funds still back the shares
+    let mut state = STATE.load(&deps.storage).unwrap();
+    state.total_amount = Uint128::from(40u128);
+    STATE.save(&mut deps.storage, &state).unwrap();
+    DELEGATIONS
+        .save(
+            &mut deps.storage,
+            "validator".to_string(),
+            &Uint128::from(40u128),
+        )
+        .unwrap();
+    UNBONDINGS
+        .save(
+            &mut deps.storage,
+            1,
+            &Unbonding {
+                validator: "validator".to_string(),
+                amount: Uint128::from(20u128),
+                completes_at: env.block.time,
+            },
+        )
+        .unwrap();
+    let resp: PreviewDepositResponse = from_json(
+        query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::PreviewDeposit {
+                amount: Uint128::from(50u128),
+            },
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(resp.shares, Uint128::from(50u128));
+
+    // Redemptions wait for the funds to be unbonded
+    let err = execute(
+        deps.as_mut(),
+        env,
+        mock_info("shares", &[]),
+        ExecuteMsg::Receive(cw20::Cw20ReceiveMsg {
+            sender: "owner".to_string(),
+            amount: Uint128::from(50u128),
+            msg: to_json_binary(&ReceiveMsg::RedeemShares {}).unwrap(),
+        }),
+    )
+    .unwrap_err();
+    assert_eq!(err, ContractError::NotEnoughFunds { total_amount: 40 });
+}